@@ -1,26 +1,54 @@
+use crate::auth::{EnrollmentRequest, RenewalRequest, RenewalResponse};
 use crate::daemon::HiveDaemon;
-use crate::replicator::Replicator;
+use crate::replicator::{AppliedCommand, HistoryError, HistoryPoint, Replicator, WatchFilter};
+use crate::server::ClientIdentity;
 use crate::types::*;
 use axum::{
-    extract::State,
-    http::StatusCode,
-    response::IntoResponse,
-    routing::{get, post},
+    extract::{Path, Query, State},
+    Extension,
+    http::{header, HeaderMap, StatusCode},
+    response::sse::{Event, KeepAlive, Sse},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+use tokio_stream::StreamExt;
 
 pub fn create_router(daemon: Arc<HiveDaemon>) -> Router {
     Router::new()
         .route("/health", get(health_check))
         .route("/status", get(get_status))
         .route("/cluster", get(get_cluster_view))
+        .route("/history", get(get_history))
+        .route("/diff", get(get_diff))
+        .route("/watch", get(watch_applied))
         .route("/tasks", get(list_tasks))
         .route("/tasks", post(submit_task))
         .route("/goals", get(list_goals))
         .route("/goals", post(add_goal))
+        .route("/goals/:goal_id", put(update_goal))
         .route("/attachments", get(list_attachments))
+        .route("/kv", get(list_kv))
+        .route("/kv/*key", get(get_kv).put(put_kv).delete(delete_kv))
+        .route("/locks", get(list_locks))
+        .route(
+            "/locks/*key",
+            get(get_lock).post(acquire_lock).put(renew_lock).delete(release_lock),
+        )
+        .route("/enroll", post(enroll_node))
+        .route("/enroll/tokens", post(create_enrollment_token))
+        .route("/enroll/renew", post(renew_certificate))
+        .route("/admin/nodes/:node_id/revoke", post(revoke_node))
+        .route("/admin/ca", get(get_ca_status))
+        .route("/admin/members", get(list_members))
+        .route("/admin/members", post(add_member))
+        .route("/admin/members/:node_id", delete(remove_member))
+        .route("/admin/members/:node_id/promote", post(promote_member))
+        .route("/admin/leader", post(transfer_leader))
+        .route("/admin/backup", get(backup_state))
         .with_state(daemon)
 }
 
@@ -31,6 +59,7 @@ async fn health_check() -> impl IntoResponse {
 #[derive(Serialize)]
 struct StatusResponse {
     node_id: String,
+    raft_id: u64,
     is_leader: bool,
     leader_id: Option<String>,
     cluster_size: usize,
@@ -43,20 +72,210 @@ async fn get_status(State(daemon): State<Arc<HiveDaemon>>) -> impl IntoResponse
 
     Json(StatusResponse {
         node_id: daemon.node_id().to_string(),
+        raft_id: daemon.replicator().node_id(),
         is_leader,
         leader_id,
         cluster_size: view.nodes.len(),
     })
 }
 
-async fn get_cluster_view(State(daemon): State<Arc<HiveDaemon>>) -> impl IntoResponse {
-    let view = daemon.replicator().snapshot();
-    Json(view)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Consistency {
+    /// Whatever this node has applied so far; may lag the leader.
+    #[default]
+    Local,
+    /// Reflects every write committed before the request arrived.
+    Linearizable,
 }
 
-async fn list_tasks(State(daemon): State<Arc<HiveDaemon>>) -> impl IntoResponse {
-    let view = daemon.replicator().snapshot();
-    Json(view.tasks)
+#[derive(Deserialize)]
+struct ReadQuery {
+    #[serde(default)]
+    consistency: Consistency,
+    /// Log index or RFC 3339 time to rebuild the view as of, from this
+    /// node's history.
+    #[serde(default)]
+    at: Option<String>,
+}
+
+async fn read_view(daemon: &HiveDaemon, query: &ReadQuery) -> Result<ClusterView, Response> {
+    if let Some(at) = &query.at {
+        return at
+            .parse::<HistoryPoint>()
+            .and_then(|at| daemon.replicator().view_at(at))
+            .map_err(history_error_response);
+    }
+    ensure_consistency(daemon, query.consistency).await?;
+    Ok(daemon.replicator().snapshot())
+}
+
+async fn ensure_consistency(daemon: &HiveDaemon, consistency: Consistency) -> Result<(), Response> {
+    if consistency == Consistency::Linearizable {
+        if let Err(e) = daemon.replicator().ensure_linearizable().await {
+            return Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+                .into_response());
+        }
+    }
+    Ok(())
+}
+
+async fn get_cluster_view(
+    State(daemon): State<Arc<HiveDaemon>>,
+    Query(query): Query<ReadQuery>,
+) -> Response {
+    match read_view(&daemon, &query).await {
+        Ok(view) => Json(view).into_response(),
+        Err(resp) => resp,
+    }
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    from: Option<String>,
+    to: Option<String>,
+    entity: Option<String>,
+}
+
+async fn get_history(
+    State(daemon): State<Arc<HiveDaemon>>,
+    Query(query): Query<HistoryQuery>,
+) -> Response {
+    let from = query.from.as_deref().map(str::parse).transpose();
+    let to = query.to.as_deref().map(str::parse).transpose();
+    let history = match (from, to) {
+        (Ok(from), Ok(to)) => daemon
+            .replicator()
+            .history(from, to, query.entity.as_deref()),
+        (Err(e), _) | (_, Err(e)) => Err(e),
+    };
+    match history {
+        Ok(entries) => Json(entries).into_response(),
+        Err(e) => history_error_response(e),
+    }
+}
+
+#[derive(Deserialize)]
+struct DiffQuery {
+    from: String,
+    to: Option<String>,
+}
+
+async fn get_diff(
+    State(daemon): State<Arc<HiveDaemon>>,
+    Query(query): Query<DiffQuery>,
+) -> Response {
+    let from = query.from.parse();
+    let to = query.to.as_deref().map(str::parse).transpose();
+    let diff = match (from, to) {
+        (Ok(from), Ok(to)) => daemon.replicator().diff(from, to),
+        (Err(e), _) | (_, Err(e)) => Err(e),
+    };
+    match diff {
+        Ok(diff) => Json(diff).into_response(),
+        Err(e) => history_error_response(e),
+    }
+}
+
+#[derive(Deserialize)]
+struct WatchQuery {
+    /// Comma-separated entity types, e.g. `task,node`.
+    types: Option<String>,
+    node: Option<String>,
+    task: Option<String>,
+    key: Option<String>,
+    /// Treat `key` as a prefix.
+    #[serde(default)]
+    prefix: bool,
+    from_index: Option<u64>,
+}
+
+/// Streams applied commands as server-sent events with the log index as
+/// event id. `from_index`, or the `Last-Event-ID` header a reconnecting
+/// client sends, first replays commands from history. A subscriber that
+/// falls behind gets a `lagged` event with the number of commands it
+/// missed, and can reconnect to fill them in.
+async fn watch_applied(
+    State(daemon): State<Arc<HiveDaemon>>,
+    headers: HeaderMap,
+    Query(query): Query<WatchQuery>,
+) -> Response {
+    let types = query
+        .types
+        .iter()
+        .flat_map(|types| types.split(','))
+        .map(|t| t.trim().parse())
+        .collect::<Result<Vec<EntityType>, _>>();
+    let types = match types {
+        Ok(types) => types,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": e })))
+                .into_response()
+        }
+    };
+    let filter = WatchFilter {
+        types,
+        node: query.node,
+        task: query.task,
+        key: query.key,
+        key_prefix: query.prefix,
+    };
+
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.parse::<u64>().ok());
+    let from_index = query.from_index.or(last_event_id.map(|id| id + 1));
+    let (backlog, receiver) = match from_index {
+        Some(index) => match daemon.replicator().watch_from(index) {
+            Ok(watch) => watch,
+            Err(e) => return history_error_response(e),
+        },
+        None => (Vec::new(), daemon.replicator().subscribe()),
+    };
+
+    let live = BroadcastStream::new(receiver).map(|item| {
+        item.map_err(|BroadcastStreamRecvError::Lagged(missed)| {
+            Event::default().event("lagged").data(missed.to_string())
+        })
+    });
+    let events = tokio_stream::iter(backlog.into_iter().map(Ok))
+        .chain(live)
+        .filter_map(move |item| match item {
+            Ok(applied) if filter.matches(&applied.command) => Some(applied_event(&applied)),
+            Ok(_) => None,
+            Err(event) => Some(Ok(event)),
+        });
+    Sse::new(events).keep_alive(KeepAlive::default()).into_response()
+}
+
+fn applied_event(applied: &AppliedCommand) -> Result<Event, axum::Error> {
+    Event::default()
+        .id(applied.index.to_string())
+        .event("applied")
+        .json_data(applied)
+}
+
+fn history_error_response(e: HistoryError) -> Response {
+    let status = match e {
+        HistoryError::InvalidPoint(_) => StatusCode::BAD_REQUEST,
+        HistoryError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        _ => StatusCode::NOT_FOUND,
+    };
+    (status, Json(serde_json::json!({ "error": e.to_string() }))).into_response()
+}
+
+async fn list_tasks(
+    State(daemon): State<Arc<HiveDaemon>>,
+    Query(query): Query<ReadQuery>,
+) -> Response {
+    match read_view(&daemon, &query).await {
+        Ok(view) => Json(view.tasks).into_response(),
+        Err(resp) => resp,
+    }
 }
 
 #[derive(Deserialize)]
@@ -64,12 +283,48 @@ struct SubmitTaskRequest {
     target_node: String,
     payload: TaskPayload,
     priority: Option<u8>,
+    lock: Option<String>,
+}
+
+/// Maps the outcome of a write to a response, returning the written entity
+/// with the revision it was assigned.
+fn command_response<T: Serialize>(
+    result: anyhow::Result<CommandResult>,
+    status: StatusCode,
+    entity: T,
+    set_revision: impl FnOnce(&mut T, u64),
+) -> Response {
+    match result {
+        Ok(
+            CommandResult::Applied { revision }
+            | CommandResult::Leased(Lease { revision, .. }),
+        ) => {
+            let mut entity = entity;
+            set_revision(&mut entity, revision);
+            (status, Json(entity)).into_response()
+        }
+        Ok(CommandResult::NotFound) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": "Not found" })),
+        )
+            .into_response(),
+        Ok(CommandResult::Conflict) => (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({ "error": "Revision does not match" })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
 }
 
 async fn submit_task(
     State(daemon): State<Arc<HiveDaemon>>,
     Json(req): Json<SubmitTaskRequest>,
-) -> impl IntoResponse {
+) -> Response {
     let task = Task {
         id: uuid::Uuid::new_v4().to_string(),
         target_node: req.target_node,
@@ -79,25 +334,28 @@ async fn submit_task(
         created_at: chrono::Utc::now(),
         updated_at: chrono::Utc::now(),
         result: None,
+        revision: 0,
+        lock: req.lock,
     };
 
-    match daemon
+    let result = daemon
         .replicator()
-        .apply(ClusterCommand::PutTask(task.clone()))
-        .await
-    {
-        Ok(_) => (StatusCode::CREATED, Json(task)).into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(serde_json::json!({ "error": e.to_string() })),
-        )
-            .into_response(),
-    }
+        .apply(ClusterCommand::PutTaskIf {
+            task: task.clone(),
+            expected_revision: 0,
+        })
+        .await;
+    command_response(result, StatusCode::CREATED, task, |t, rev| t.revision = rev)
 }
 
-async fn list_goals(State(daemon): State<Arc<HiveDaemon>>) -> impl IntoResponse {
-    let view = daemon.replicator().snapshot();
-    Json(view.goals)
+async fn list_goals(
+    State(daemon): State<Arc<HiveDaemon>>,
+    Query(query): Query<ReadQuery>,
+) -> Response {
+    match read_view(&daemon, &query).await {
+        Ok(view) => Json(view.goals).into_response(),
+        Err(resp) => resp,
+    }
 }
 
 #[derive(Deserialize)]
@@ -110,7 +368,7 @@ struct AddGoalRequest {
 async fn add_goal(
     State(daemon): State<Arc<HiveDaemon>>,
     Json(req): Json<AddGoalRequest>,
-) -> impl IntoResponse {
+) -> Response {
     let goal = Goal {
         id: uuid::Uuid::new_v4().to_string(),
         description: req.description,
@@ -118,14 +376,378 @@ async fn add_goal(
         priority: req.priority.unwrap_or(5),
         active: true,
         created_at: chrono::Utc::now(),
+        revision: 0,
     };
 
-    match daemon
+    let result = daemon
         .replicator()
-        .apply(ClusterCommand::PutGoal(goal.clone()))
+        .apply(ClusterCommand::PutGoalIf {
+            goal: goal.clone(),
+            expected_revision: 0,
+        })
+        .await;
+    command_response(result, StatusCode::CREATED, goal, |g, rev| g.revision = rev)
+}
+
+#[derive(Deserialize)]
+struct UpdateGoalRequest {
+    description: Option<String>,
+    constraints: Option<Vec<String>>,
+    priority: Option<u8>,
+    active: Option<bool>,
+    /// Revision the client last read. When omitted the update applies on
+    /// top of whatever this node currently holds.
+    expected_revision: Option<u64>,
+}
+
+async fn update_goal(
+    State(daemon): State<Arc<HiveDaemon>>,
+    Path(goal_id): Path<String>,
+    Json(req): Json<UpdateGoalRequest>,
+) -> Response {
+    let current = daemon
+        .replicator()
+        .shared_state()
+        .read(|state| state.goals.get(&goal_id).cloned());
+    let Some(current) = current else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": format!("Goal {} not found", goal_id) })),
+        )
+            .into_response();
+    };
+
+    let expected_revision = req.expected_revision.unwrap_or(current.revision);
+    let goal = Goal {
+        description: req.description.unwrap_or(current.description),
+        constraints: req.constraints.unwrap_or(current.constraints),
+        priority: req.priority.unwrap_or(current.priority),
+        active: req.active.unwrap_or(current.active),
+        ..current
+    };
+
+    let result = daemon
+        .replicator()
+        .apply(ClusterCommand::PutGoalIf {
+            goal: goal.clone(),
+            expected_revision,
+        })
+        .await;
+    command_response(result, StatusCode::OK, goal, |g, rev| g.revision = rev)
+}
+
+async fn list_attachments(State(daemon): State<Arc<HiveDaemon>>) -> impl IntoResponse {
+    let attachments = daemon.attachments().list();
+    Json(attachments)
+}
+
+#[derive(Deserialize)]
+struct KvQuery {
+    #[serde(default)]
+    consistency: Consistency,
+    #[serde(default)]
+    prefix: String,
+}
+
+async fn list_kv(State(daemon): State<Arc<HiveDaemon>>, Query(query): Query<KvQuery>) -> Response {
+    if let Err(resp) = ensure_consistency(&daemon, query.consistency).await {
+        return resp;
+    }
+    let entries = daemon
+        .replicator()
+        .shared_state()
+        .read(|state| state.kv_list(&query.prefix, chrono::Utc::now()));
+    Json(entries).into_response()
+}
+
+async fn get_kv(
+    State(daemon): State<Arc<HiveDaemon>>,
+    Path(key): Path<String>,
+    Query(query): Query<KvQuery>,
+) -> Response {
+    if let Err(resp) = ensure_consistency(&daemon, query.consistency).await {
+        return resp;
+    }
+    let entry = daemon
+        .replicator()
+        .shared_state()
+        .read(|state| state.kv_get(&key, chrono::Utc::now()).cloned());
+    match entry {
+        Some(entry) => Json(entry).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": format!("Key {} not found", key) })),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct PutKvRequest {
+    value: String,
+    ttl_secs: Option<u64>,
+    /// Only write if the key is at this revision; 0 means it must not
+    /// exist yet.
+    expected_revision: Option<u64>,
+}
+
+async fn put_kv(
+    State(daemon): State<Arc<HiveDaemon>>,
+    Path(key): Path<String>,
+    Json(req): Json<PutKvRequest>,
+) -> Response {
+    if req.ttl_secs.is_some_and(|ttl| ttl == 0 || ttl > MAX_KV_TTL_SECS) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": format!("ttl_secs must be between 1 and {}", MAX_KV_TTL_SECS)
+            })),
+        )
+            .into_response();
+    }
+    let written_at = chrono::Utc::now();
+    let command = match req.expected_revision {
+        Some(expected_revision) => ClusterCommand::KvCas {
+            key: key.clone(),
+            value: req.value.clone(),
+            ttl_secs: req.ttl_secs,
+            expected_revision,
+            written_at,
+        },
+        None => ClusterCommand::KvPut {
+            key: key.clone(),
+            value: req.value.clone(),
+            ttl_secs: req.ttl_secs,
+            written_at,
+        },
+    };
+    let entry = KvEntry {
+        key,
+        value: req.value,
+        revision: 0,
+        updated_at: written_at,
+        expires_at: req.ttl_secs.and_then(|ttl| expiry_after(written_at, ttl)),
+    };
+
+    let result = daemon.replicator().apply(command).await;
+    command_response(result, StatusCode::OK, entry, |e, rev| e.revision = rev)
+}
+
+#[derive(Deserialize)]
+struct DeleteKvQuery {
+    expected_revision: Option<u64>,
+}
+
+async fn delete_kv(
+    State(daemon): State<Arc<HiveDaemon>>,
+    Path(key): Path<String>,
+    Query(query): Query<DeleteKvQuery>,
+) -> Response {
+    let result = daemon
+        .replicator()
+        .apply(ClusterCommand::KvDelete {
+            key,
+            expected_revision: query.expected_revision,
+            written_at: chrono::Utc::now(),
+        })
+        .await;
+    match result {
+        Ok(CommandResult::Applied { .. }) => StatusCode::NO_CONTENT.into_response(),
+        other => command_response(other, StatusCode::NO_CONTENT, (), |_, _| {}),
+    }
+}
+
+async fn list_locks(
+    State(daemon): State<Arc<HiveDaemon>>,
+    Query(query): Query<KvQuery>,
+) -> Response {
+    if let Err(resp) = ensure_consistency(&daemon, query.consistency).await {
+        return resp;
+    }
+    let leases = daemon
+        .replicator()
+        .shared_state()
+        .read(|state| state.leases(chrono::Utc::now()));
+    let leases: Vec<_> = leases
+        .into_iter()
+        .filter(|l| l.key.starts_with(&query.prefix))
+        .collect();
+    Json(leases).into_response()
+}
+
+async fn get_lock(
+    State(daemon): State<Arc<HiveDaemon>>,
+    Path(key): Path<String>,
+    Query(query): Query<KvQuery>,
+) -> Response {
+    if let Err(resp) = ensure_consistency(&daemon, query.consistency).await {
+        return resp;
+    }
+    match read_lease(&daemon, &key) {
+        Some(lease) => Json(lease).into_response(),
+        None => lock_error(StatusCode::NOT_FOUND, format!("Lock {} is not held", key)),
+    }
+}
+
+fn read_lease(daemon: &HiveDaemon, key: &str) -> Option<Lease> {
+    daemon
+        .replicator()
+        .shared_state()
+        .read(|state| state.lease(key, chrono::Utc::now()).cloned())
+}
+
+fn lock_error(status: StatusCode, error: String) -> Response {
+    (status, Json(serde_json::json!({ "error": error }))).into_response()
+}
+
+#[derive(Deserialize)]
+struct LockRequest {
+    holder: String,
+    ttl_secs: u64,
+}
+
+/// Takes the lock for `holder`, answering with the lease and its fencing
+/// token, or 409 with the current holder if someone else has it.
+async fn acquire_lock(
+    State(daemon): State<Arc<HiveDaemon>>,
+    Path(key): Path<String>,
+    Json(req): Json<LockRequest>,
+) -> Response {
+    if let Some(resp) = lock_ttl_error(req.ttl_secs) {
+        return resp;
+    }
+    let command = ClusterCommand::AcquireLock {
+        key: key.clone(),
+        holder: req.holder.clone(),
+        ttl_secs: req.ttl_secs,
+        requested_at: chrono::Utc::now(),
+    };
+    lock_response(&daemon, &key, &req.holder, command).await
+}
+
+async fn renew_lock(
+    State(daemon): State<Arc<HiveDaemon>>,
+    Path(key): Path<String>,
+    Json(req): Json<LockRequest>,
+) -> Response {
+    if let Some(resp) = lock_ttl_error(req.ttl_secs) {
+        return resp;
+    }
+    let command = ClusterCommand::RenewLock {
+        key: key.clone(),
+        holder: req.holder.clone(),
+        ttl_secs: req.ttl_secs,
+        requested_at: chrono::Utc::now(),
+    };
+    lock_response(&daemon, &key, &req.holder, command).await
+}
+
+fn lock_ttl_error(ttl_secs: u64) -> Option<Response> {
+    (ttl_secs == 0 || ttl_secs > MAX_LOCK_TTL_SECS).then(|| {
+        lock_error(
+            StatusCode::BAD_REQUEST,
+            format!("ttl_secs must be between 1 and {}", MAX_LOCK_TTL_SECS),
+        )
+    })
+}
+
+/// Applies an acquire or renew, answering with the lease the state machine
+/// left, fencing token included.
+async fn lock_response(
+    daemon: &HiveDaemon,
+    key: &str,
+    holder: &str,
+    command: ClusterCommand,
+) -> Response {
+    let result = match daemon.replicator().apply(command).await {
+        Ok(result) => result,
+        Err(e) => return lock_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    match result {
+        CommandResult::Leased(lease) => Json(lease).into_response(),
+        CommandResult::NotFound => {
+            lock_error(StatusCode::NOT_FOUND, format!("Lock {} is not held", key))
+        }
+        CommandResult::Conflict => match read_lease(daemon, key) {
+            Some(lease) if lease.holder != holder => (
+                StatusCode::CONFLICT,
+                Json(serde_json::json!({
+                    "error": format!("Lock {} is held by {}", key, lease.holder),
+                    "holder": lease.holder,
+                    "expires_at": lease.expires_at,
+                })),
+            )
+                .into_response(),
+            _ => lock_error(
+                StatusCode::CONFLICT,
+                format!("Lock {} is not held by {}", key, holder),
+            ),
+        },
+        CommandResult::Applied { .. } => lock_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Lock {} was written without a lease", key),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+struct ReleaseLockQuery {
+    holder: String,
+}
+
+async fn release_lock(
+    State(daemon): State<Arc<HiveDaemon>>,
+    Path(key): Path<String>,
+    Query(query): Query<ReleaseLockQuery>,
+) -> Response {
+    let result = daemon
+        .replicator()
+        .apply(ClusterCommand::ReleaseLock {
+            key: key.clone(),
+            holder: query.holder.clone(),
+        })
+        .await;
+    match result {
+        Ok(CommandResult::Applied { .. } | CommandResult::Leased(_)) => {
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(CommandResult::NotFound) => {
+            lock_error(StatusCode::NOT_FOUND, format!("Lock {} is not held", key))
+        }
+        Ok(CommandResult::Conflict) => lock_error(
+            StatusCode::CONFLICT,
+            format!("Lock {} is not held by {}", key, query.holder),
+        ),
+        Err(e) => lock_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+#[derive(Deserialize)]
+struct CreateTokenRequest {
+    valid_hours: Option<i64>,
+    allowed_tags: Option<Vec<String>>,
+}
+
+async fn create_enrollment_token(
+    State(daemon): State<Arc<HiveDaemon>>,
+    Json(req): Json<CreateTokenRequest>,
+) -> impl IntoResponse {
+    let Some(manager) = daemon.enrollment() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "error": "This node does not hold the cluster CA" })),
+        )
+            .into_response();
+    };
+
+    match manager
+        .generate_token(
+            req.valid_hours.unwrap_or(24),
+            req.allowed_tags.unwrap_or_default(),
+        )
         .await
     {
-        Ok(_) => (StatusCode::CREATED, Json(goal)).into_response(),
+        Ok(token) => (StatusCode::CREATED, Json(token)).into_response(),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(serde_json::json!({ "error": e.to_string() })),
@@ -134,7 +756,293 @@ async fn add_goal(
     }
 }
 
-async fn list_attachments(State(daemon): State<Arc<HiveDaemon>>) -> impl IntoResponse {
-    let attachments = daemon.attachments().list();
-    Json(attachments)
+async fn enroll_node(
+    State(daemon): State<Arc<HiveDaemon>>,
+    Json(req): Json<EnrollmentRequest>,
+) -> impl IntoResponse {
+    let Some(manager) = daemon.enrollment() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "error": "This node does not hold the cluster CA" })),
+        )
+            .into_response();
+    };
+
+    if !daemon.replicator().is_leader() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({
+                "error": "Enrollment must be sent to the leader",
+                "leader_id": daemon.replicator().leader_id(),
+            })),
+        )
+            .into_response();
+    }
+
+    match manager.enroll(req).await {
+        Ok(resp) => {
+            tracing::info!("Enrolled node {}", resp.node_id);
+            (StatusCode::OK, Json(resp)).into_response()
+        }
+        Err(e) => (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+async fn renew_certificate(
+    State(daemon): State<Arc<HiveDaemon>>,
+    identity: Option<Extension<ClientIdentity>>,
+    Json(req): Json<RenewalRequest>,
+) -> impl IntoResponse {
+    let Some(Extension(identity)) = identity else {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({ "error": "Renewal requires a client certificate" })),
+        )
+            .into_response();
+    };
+
+    let Some(manager) = daemon.enrollment() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "error": "This node does not hold the cluster CA" })),
+        )
+            .into_response();
+    };
+
+    if !daemon.replicator().is_leader() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({
+                "error": "Renewal must be sent to the leader",
+                "leader_id": daemon.replicator().leader_id(),
+            })),
+        )
+            .into_response();
+    }
+
+    match manager
+        .renew(&identity.node_id, &identity.serial, req.hostnames, req.ips)
+        .await
+    {
+        Ok(cert) => {
+            tracing::info!("Renewed certificate for node {}", identity.node_id);
+            (
+                StatusCode::OK,
+                Json(RenewalResponse {
+                    node_cert_pem: cert.cert_pem,
+                    node_key_pem: cert.key_pem,
+                }),
+            )
+                .into_response()
+        }
+        Err(e) => (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Serialize)]
+struct CaStatusResponse {
+    signing_key_id: String,
+    trust_bundle_version: Option<u64>,
+    trusted_roots: usize,
+    pending_reissue: Vec<NodeId>,
+}
+
+async fn get_ca_status(State(daemon): State<Arc<HiveDaemon>>) -> impl IntoResponse {
+    let Some(manager) = daemon.enrollment() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "error": "This node does not hold the cluster CA" })),
+        )
+            .into_response();
+    };
+
+    let status = manager.signing_key_id().and_then(|signing_key_id| {
+        Ok(CaStatusResponse {
+            signing_key_id,
+            trust_bundle_version: daemon
+                .replicator()
+                .hive_state()
+                .trust_bundle
+                .map(|bundle| bundle.version),
+            trusted_roots: pem::parse_many(manager.ca_cert_pem())?.len(),
+            pending_reissue: manager
+                .nodes_pending_reissue()?
+                .into_iter()
+                .map(|n| n.node_id)
+                .collect(),
+        })
+    });
+
+    match status {
+        Ok(status) => Json(status).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct RevokeNodeRequest {
+    reason: Option<String>,
+}
+
+async fn revoke_node(
+    State(daemon): State<Arc<HiveDaemon>>,
+    Path(node_id): Path<String>,
+    Json(req): Json<RevokeNodeRequest>,
+) -> impl IntoResponse {
+    let Some(manager) = daemon.enrollment() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "error": "This node does not hold the cluster CA" })),
+        )
+            .into_response();
+    };
+
+    if !daemon.replicator().is_leader() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({
+                "error": "Revocation must be sent to the leader",
+                "leader_id": daemon.replicator().leader_id(),
+            })),
+        )
+            .into_response();
+    }
+
+    if node_id == daemon.node_id() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": "A node cannot revoke itself" })),
+        )
+            .into_response();
+    }
+
+    match manager.revoke_node(&node_id, req.reason).await {
+        Ok(revoked) => {
+            tracing::warn!("Revoked node {}", node_id);
+            (StatusCode::OK, Json(revoked)).into_response()
+        }
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+fn not_leader_response(daemon: &HiveDaemon, what: &str) -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(serde_json::json!({
+            "error": format!("{} must be sent to the leader", what),
+            "leader_id": daemon.replicator().leader_id(),
+        })),
+    )
+        .into_response()
+}
+
+fn membership_result(result: anyhow::Result<()>) -> Response {
+    match result {
+        Ok(()) => (StatusCode::OK, Json(serde_json::json!({}))).into_response(),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+async fn list_members(State(daemon): State<Arc<HiveDaemon>>) -> impl IntoResponse {
+    Json(daemon.replicator().members())
+}
+
+async fn add_member(
+    State(daemon): State<Arc<HiveDaemon>>,
+    Json(peer): Json<PeerInfo>,
+) -> impl IntoResponse {
+    if !daemon.replicator().is_leader() {
+        return not_leader_response(&daemon, "Membership changes");
+    }
+
+    let result = daemon.replicator().add_peer(peer.clone()).await;
+    if result.is_ok() {
+        tracing::info!(
+            "Added {} as {}",
+            peer.node_id,
+            if peer.is_voter { "voter" } else { "learner" }
+        );
+    }
+    membership_result(result)
+}
+
+async fn remove_member(
+    State(daemon): State<Arc<HiveDaemon>>,
+    Path(node_id): Path<String>,
+) -> impl IntoResponse {
+    if !daemon.replicator().is_leader() {
+        return not_leader_response(&daemon, "Membership changes");
+    }
+
+    membership_result(daemon.replicator().remove_peer(&node_id).await)
+}
+
+async fn promote_member(
+    State(daemon): State<Arc<HiveDaemon>>,
+    Path(node_id): Path<String>,
+) -> impl IntoResponse {
+    if !daemon.replicator().is_leader() {
+        return not_leader_response(&daemon, "Membership changes");
+    }
+
+    membership_result(daemon.replicator().promote_peer(&node_id).await)
+}
+
+#[derive(Deserialize)]
+struct TransferLeaderRequest {
+    node_id: NodeId,
+}
+
+async fn transfer_leader(
+    State(daemon): State<Arc<HiveDaemon>>,
+    Json(req): Json<TransferLeaderRequest>,
+) -> impl IntoResponse {
+    if !daemon.replicator().is_leader() {
+        return not_leader_response(&daemon, "Leadership transfer");
+    }
+
+    membership_result(daemon.replicator().transfer_leadership(&req.node_id).await)
+}
+
+async fn backup_state(State(daemon): State<Arc<HiveDaemon>>) -> impl IntoResponse {
+    if !daemon.replicator().is_leader() {
+        return not_leader_response(&daemon, "Backups");
+    }
+
+    let backup = daemon
+        .replicator()
+        .backup()
+        .await
+        .and_then(|backup| Ok(backup.encode()?));
+    match backup {
+        Ok(data) => {
+            tracing::info!("Served backup of {} bytes", data.len());
+            ([(header::CONTENT_TYPE, "application/octet-stream")], data).into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
 }
@@ -25,6 +25,7 @@ impl AttachmentRegistry {
             capabilities,
             metadata: HashMap::new(),
             created_at: Utc::now(),
+            revision: 0,
         };
 
         self.inner
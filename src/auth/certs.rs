@@ -1,11 +1,22 @@
+use crate::types::RevokedCertificate;
 use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
 use rcgen::{
-    BasicConstraints, CertificateParams, DistinguishedName, DnType, IsCa,
-    KeyPair, KeyUsagePurpose, SanType,
+    BasicConstraints, Certificate, CertificateParams, CertificateRevocationListParams, DistinguishedName,
+    DnType, IsCa, KeyIdMethod, KeyPair, KeyUsagePurpose, RevokedCertParams, SanType,
+    SerialNumber,
 };
+use std::collections::HashSet;
 use std::path::Path;
 use std::sync::Arc;
-use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use x509_parser::extensions::ParsedExtension;
+use tokio_rustls::rustls::pki_types::{
+    CertificateDer, CertificateRevocationListDer, PrivateKeyDer, ServerName, UnixTime,
+};
+
+/// Lifetime of issued node certificates. Daemons renew theirs well before
+/// this runs out, see `TlsSettings::renew_before_hours`.
+pub const NODE_CERT_VALIDITY_DAYS: i64 = 90;
 
 #[derive(Clone)]
 pub struct NodeCertificate {
@@ -14,17 +25,33 @@ pub struct NodeCertificate {
     pub node_id: String,
 }
 
+/// Lifetime of a root CA created by [`CaCertificate::generate_root`].
+pub const ROOT_CA_VALIDITY_DAYS: i64 = 3650;
+
+/// Lifetime of an intermediate CA created by
+/// [`CaCertificate::issue_intermediate`].
+pub const INTERMEDIATE_CA_VALIDITY_DAYS: i64 = 730;
+
+/// A CA that can sign certificates. This is either a self-signed root, as
+/// produced by [`CaCertificate::generate`], or an intermediate whose
+/// `cert_pem` holds its own certificate followed by any issuers below the
+/// root. Node certificates signed by an intermediate carry that chain so
+/// peers only need the root in their trust store.
 pub struct CaCertificate {
     key_pair: KeyPair,
-    cn: String,
+    issuer: Certificate,
+    is_root: bool,
     pub cert_pem: String,
 }
 
 impl Clone for CaCertificate {
     fn clone(&self) -> Self {
+        let key_pair = KeyPair::from_pem(&self.key_pair.serialize_pem()).unwrap();
+        let issuer = Self::issuer_from_pem(&self.cert_pem, &key_pair).unwrap();
         Self {
-            key_pair: KeyPair::from_pem(&self.key_pair.serialize_pem()).unwrap(),
-            cn: self.cn.clone(),
+            key_pair,
+            issuer,
+            is_root: self.is_root,
             cert_pem: self.cert_pem.clone(),
         }
     }
@@ -47,35 +74,137 @@ impl CaCertificate {
         params
     }
 
+    fn make_tiered_ca_params(cn: &str, path_len: u8, valid_days: i64) -> CertificateParams {
+        let now = time::OffsetDateTime::now_utc();
+        let mut params = Self::make_ca_params(cn);
+        params.is_ca = IsCa::Ca(BasicConstraints::Constrained(path_len));
+        params.not_before = now - time::Duration::minutes(5);
+        params.not_after = now + time::Duration::days(valid_days);
+        params
+    }
+
+    /// rcgen only signs with an issuer `Certificate`, so rebuild one from
+    /// the parameters in `ca.crt`. Issued certificates take their issuer
+    /// name and authority key identifier from it, which keeps them chained
+    /// to the CA on disk rather than to a fresh self-signed copy.
+    fn issuer_from_pem(cert_pem: &str, key_pair: &KeyPair) -> Result<Certificate> {
+        let pem = pem::parse(cert_pem)?;
+        let (_, cert) = x509_parser::parse_x509_certificate(pem.contents())
+            .map_err(|e| anyhow!("Failed to parse CA certificate: {:?}", e))?;
+        if cert.public_key().raw != key_pair.public_key_der().as_slice() {
+            return Err(anyhow!("CA key does not match the CA certificate"));
+        }
+
+        let params = CertificateParams::from_ca_cert_pem(cert_pem)?;
+        Ok(params.self_signed(key_pair)?)
+    }
+
+    fn from_parts(cert_pem: String, key_pair: KeyPair) -> Result<Self> {
+        let issuer = Self::issuer_from_pem(&cert_pem, &key_pair)?;
+        let pem = pem::parse(&cert_pem)?;
+        let (_, cert) = x509_parser::parse_x509_certificate(pem.contents())
+            .map_err(|e| anyhow!("Failed to parse CA certificate: {:?}", e))?;
+        let is_root = cert.issuer().as_raw() == cert.subject().as_raw();
+
+        Ok(Self {
+            key_pair,
+            issuer,
+            is_root,
+            cert_pem,
+        })
+    }
+
     pub fn generate(cluster_id: &str) -> Result<Self> {
         let cn = format!("FlockMind CA - {}", cluster_id);
         let params = Self::make_ca_params(&cn);
 
         let key_pair = KeyPair::generate()?;
-        let cert = params.self_signed(&key_pair)?;
-        let cert_pem = cert.pem();
+        let issuer = params.self_signed(&key_pair)?;
+        let cert_pem = issuer.pem();
 
         Ok(Self {
             key_pair,
-            cn,
+            issuer,
+            is_root: true,
             cert_pem,
         })
     }
 
-    pub fn load<P: AsRef<Path>>(cert_path: P, key_path: P) -> Result<Self> {
-        let cert_pem = std::fs::read_to_string(&cert_path)?;
-        let key_pem = std::fs::read_to_string(key_path)?;
-        let key_pair = KeyPair::from_pem(&key_pem)?;
+    /// Creates a root for a two-tier hierarchy. It may only sign
+    /// intermediates, and its key is meant to be kept off the cluster.
+    pub fn generate_root(cluster_id: &str) -> Result<Self> {
+        let cn = format!(
+            "FlockMind Root CA - {} - {}",
+            cluster_id,
+            Utc::now().format("%Y%m%d%H%M%S")
+        );
+        let params = Self::make_tiered_ca_params(&cn, 1, ROOT_CA_VALIDITY_DAYS);
 
-        let cn = extract_cn_from_pem(&cert_pem).unwrap_or_else(|_| "FlockMind CA".to_string());
+        let key_pair = KeyPair::generate()?;
+        let issuer = params.self_signed(&key_pair)?;
+        let cert_pem = issuer.pem();
 
         Ok(Self {
             key_pair,
-            cn,
+            issuer,
+            is_root: true,
             cert_pem,
         })
     }
 
+    /// Issues an online intermediate that signs node certificates.
+    pub fn issue_intermediate(&self, cluster_id: &str) -> Result<Self> {
+        let cn = format!(
+            "FlockMind Intermediate CA - {} - {}",
+            cluster_id,
+            Utc::now().format("%Y%m%d%H%M%S")
+        );
+        let mut params = Self::make_tiered_ca_params(&cn, 0, INTERMEDIATE_CA_VALIDITY_DAYS);
+        params.use_authority_key_identifier_extension = true;
+
+        let key_pair = KeyPair::generate()?;
+        let cert = params.signed_by(&key_pair, &self.issuer, &self.key_pair)?;
+        let cert_pem = cert.pem() + self.chain_pem();
+
+        Self::from_parts(cert_pem, key_pair)
+    }
+
+    pub fn load<P: AsRef<Path>>(cert_path: P, key_path: P) -> Result<Self> {
+        let cert_pem = std::fs::read_to_string(&cert_path)?;
+        let key_pem = std::fs::read_to_string(key_path)?;
+        let key_pair = KeyPair::from_pem(&key_pem)?;
+
+        Self::from_parts(cert_pem, key_pair)
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.is_root
+    }
+
+    /// Certificates that must accompany anything this CA signs. Empty for
+    /// a root, since peers already hold it as a trust anchor.
+    pub fn chain_pem(&self) -> &str {
+        if self.is_root {
+            ""
+        } else {
+            &self.cert_pem
+        }
+    }
+
+    /// Subject key identifier of the signing certificate, as hex. Node
+    /// certificates record it as their authority key identifier.
+    pub fn key_id(&self) -> Result<String> {
+        let pem = pem::parse(&self.cert_pem)?;
+        let (_, cert) = x509_parser::parse_x509_certificate(pem.contents())
+            .map_err(|e| anyhow!("Failed to parse CA certificate: {:?}", e))?;
+
+        let key_id = cert.iter_extensions().find_map(|ext| match ext.parsed_extension() {
+            ParsedExtension::SubjectKeyIdentifier(id) => Some(serial_to_hex(id.0)),
+            _ => None,
+        });
+        key_id.ok_or_else(|| anyhow!("CA certificate has no subject key identifier"))
+    }
+
     pub fn save<P: AsRef<Path>>(&self, cert_path: P, key_path: P) -> Result<()> {
         std::fs::write(cert_path, &self.cert_pem)?;
         std::fs::write(key_path, self.key_pair.serialize_pem())?;
@@ -88,8 +217,27 @@ impl CaCertificate {
         hostnames: Vec<String>,
         ips: Vec<String>,
     ) -> Result<NodeCertificate> {
+        self.sign_node_with_validity(
+            node_id,
+            hostnames,
+            ips,
+            chrono::Duration::days(NODE_CERT_VALIDITY_DAYS),
+        )
+    }
+
+    pub fn sign_node_with_validity(
+        &self,
+        node_id: &str,
+        hostnames: Vec<String>,
+        ips: Vec<String>,
+        valid_for: chrono::Duration,
+    ) -> Result<NodeCertificate> {
+        let now = time::OffsetDateTime::now_utc();
         let mut params = CertificateParams::default();
+        params.not_before = now - time::Duration::minutes(5);
+        params.not_after = now + time::Duration::seconds(valid_for.num_seconds());
         params.is_ca = IsCa::NoCa;
+        params.use_authority_key_identifier_extension = true;
         params.key_usages = vec![
             KeyUsagePurpose::DigitalSignature,
             KeyUsagePurpose::KeyEncipherment,
@@ -119,16 +267,46 @@ impl CaCertificate {
 
         let node_key = KeyPair::generate()?;
 
-        let ca_params = Self::make_ca_params(&self.cn);
-        let ca_cert = ca_params.self_signed(&self.key_pair)?;
-        let cert = params.signed_by(&node_key, &ca_cert, &self.key_pair)?;
+        let cert = params.signed_by(&node_key, &self.issuer, &self.key_pair)?;
 
         Ok(NodeCertificate {
-            cert_pem: cert.pem(),
+            cert_pem: cert.pem() + self.chain_pem(),
             key_pem: node_key.serialize_pem(),
             node_id: node_id.to_string(),
         })
     }
+
+    /// Signs a CRL listing every certificate in `revoked`. The list is
+    /// always complete, so the newest CRL supersedes all earlier ones.
+    pub fn sign_crl(&self, revoked: &[RevokedCertificate], crl_number: u64) -> Result<String> {
+        let now = time::OffsetDateTime::now_utc();
+
+        let revoked_certs = revoked
+            .iter()
+            .map(|cert| {
+                Ok(RevokedCertParams {
+                    serial_number: SerialNumber::from_slice(&serial_from_hex(&cert.serial)?),
+                    revocation_time: time::OffsetDateTime::from_unix_timestamp(
+                        cert.revoked_at.timestamp(),
+                    )?,
+                    reason_code: None,
+                    invalidity_date: None,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let params = CertificateRevocationListParams {
+            this_update: now,
+            next_update: now + time::Duration::days(365),
+            crl_number: SerialNumber::from_slice(&crl_number.to_be_bytes()),
+            issuing_distribution_point: None,
+            revoked_certs,
+            key_identifier_method: KeyIdMethod::Sha256,
+        };
+
+        let crl = params.signed_by(&self.issuer, &self.key_pair)?;
+        Ok(crl.pem()?)
+    }
 }
 
 impl NodeCertificate {
@@ -156,15 +334,96 @@ impl NodeCertificate {
         Ok(CertificateDer::from(pem.contents().to_vec()))
     }
 
+    /// The node certificate followed by any intermediates it was issued
+    /// with, ready to present in a handshake.
+    pub fn cert_chain_der(&self) -> Result<Vec<CertificateDer<'static>>> {
+        Ok(pem::parse_many(&self.cert_pem)?
+            .into_iter()
+            .map(|pem| CertificateDer::from(pem.into_contents()))
+            .collect())
+    }
+
     pub fn key_der(&self) -> Result<PrivateKeyDer<'static>> {
         let pem = pem::parse(&self.key_pem)?;
         Ok(PrivateKeyDer::Pkcs8(pem.contents().to_vec().into()))
     }
+
+    pub fn serial(&self) -> Result<String> {
+        let pem = pem::parse(&self.cert_pem)?;
+        extract_serial_from_der(pem.contents())
+    }
+
+    /// Key identifier of the CA that issued this certificate, if recorded.
+    pub fn issuer_key_id(&self) -> Result<Option<String>> {
+        let pem = pem::parse(&self.cert_pem)?;
+        let (_, cert) = x509_parser::parse_x509_certificate(pem.contents())
+            .map_err(|e| anyhow!("Failed to parse certificate: {:?}", e))?;
+
+        let key_id = cert.iter_extensions().find_map(|ext| match ext.parsed_extension() {
+            ParsedExtension::AuthorityKeyIdentifier(aki) => {
+                aki.key_identifier.as_ref().map(|id| serial_to_hex(id.0))
+            }
+            _ => None,
+        });
+        Ok(key_id)
+    }
+
+    pub fn not_after(&self) -> Result<DateTime<Utc>> {
+        let pem = pem::parse(&self.cert_pem)?;
+        let (_, cert) = x509_parser::parse_x509_certificate(pem.contents())
+            .map_err(|e| anyhow!("Failed to parse certificate: {:?}", e))?;
+
+        let timestamp = cert.validity().not_after.timestamp();
+        DateTime::from_timestamp(timestamp, 0)
+            .ok_or_else(|| anyhow!("Certificate expiry {} out of range", timestamp))
+    }
+
+    /// Returns the DNS names and IP addresses the certificate was issued
+    /// for, excluding the node ID, in the form `CaCertificate::sign_node`
+    /// takes them.
+    pub fn subject_alt_names(&self) -> Result<(Vec<String>, Vec<String>)> {
+        use x509_parser::extensions::GeneralName;
+
+        let pem = pem::parse(&self.cert_pem)?;
+        let (_, cert) = x509_parser::parse_x509_certificate(pem.contents())
+            .map_err(|e| anyhow!("Failed to parse certificate: {:?}", e))?;
+
+        let mut hostnames = Vec::new();
+        let mut ips = Vec::new();
+        let Some(san) = cert
+            .subject_alternative_name()
+            .map_err(|e| anyhow!("Invalid subject alternative names: {:?}", e))?
+        else {
+            return Ok((hostnames, ips));
+        };
+
+        for name in &san.value.general_names {
+            match name {
+                GeneralName::DNSName(dns) if *dns != self.node_id => {
+                    hostnames.push(dns.to_string());
+                }
+                GeneralName::IPAddress(bytes) => {
+                    if let Ok(octets) = <[u8; 4]>::try_from(*bytes) {
+                        ips.push(std::net::Ipv4Addr::from(octets).to_string());
+                    } else if let Ok(octets) = <[u8; 16]>::try_from(*bytes) {
+                        ips.push(std::net::Ipv6Addr::from(octets).to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok((hostnames, ips))
+    }
 }
 
 fn extract_cn_from_pem(pem_str: &str) -> Result<String> {
     let pem = pem::parse(pem_str)?;
-    let (_, cert) = x509_parser::parse_x509_certificate(pem.contents())
+    extract_cn_from_der(pem.contents())
+}
+
+pub fn extract_cn_from_der(der: &[u8]) -> Result<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der)
         .map_err(|e| anyhow!("Failed to parse certificate: {:?}", e))?;
 
     for attr in cert.subject().iter_common_name() {
@@ -176,21 +435,98 @@ fn extract_cn_from_pem(pem_str: &str) -> Result<String> {
     Err(anyhow!("No CN found in certificate"))
 }
 
+/// Returns the certificate serial as lowercase hex, the form used in the
+/// replicated revocation list.
+pub fn extract_serial_from_der(der: &[u8]) -> Result<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der)
+        .map_err(|e| anyhow!("Failed to parse certificate: {:?}", e))?;
+    Ok(serial_to_hex(cert.tbs_certificate.raw_serial()))
+}
+
+/// Lists the serials revoked by a PEM-encoded CRL.
+pub fn revoked_serials_from_crl(crl_pem: &str) -> Result<Vec<String>> {
+    let pem = pem::parse(crl_pem)?;
+    let (_, crl) = x509_parser::parse_x509_crl(pem.contents())
+        .map_err(|e| anyhow!("Failed to parse CRL: {:?}", e))?;
+    Ok(crl
+        .iter_revoked_certificates()
+        .map(|cert| serial_to_hex(cert.raw_serial()))
+        .collect())
+}
+
+fn serial_to_hex(serial: &[u8]) -> String {
+    serial.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn serial_from_hex(serial: &str) -> Result<Vec<u8>> {
+    if !serial.len().is_multiple_of(2) {
+        return Err(anyhow!("Invalid certificate serial {}", serial));
+    }
+    (0..serial.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&serial[i..i + 2], 16)
+                .map_err(|_| anyhow!("Invalid certificate serial {}", serial))
+        })
+        .collect()
+}
+
+/// Builds a trust store from every certificate in `ca_cert_pem`. During a
+/// CA rollover the bundle holds both the outgoing and the incoming root.
+fn root_store_from_pem(ca_cert_pem: &str) -> Result<tokio_rustls::rustls::RootCertStore> {
+    let mut root_store = tokio_rustls::rustls::RootCertStore::empty();
+    for pem in pem::parse_many(ca_cert_pem)? {
+        root_store.add(CertificateDer::from(pem.into_contents()))?;
+    }
+    if root_store.is_empty() {
+        return Err(anyhow!("No CA certificates found"));
+    }
+    Ok(root_store)
+}
+
+/// CRLs are issued by whichever CA signs node certificates, so only the end
+/// entity is checked. The verifiers allow an unknown revocation status,
+/// which lets through exactly the certificates whose issuer has no CRL
+/// here: those of an outgoing CA during a rollover, whose key has left the
+/// cluster and can no longer sign one. Such certificates are still refused
+/// by serial, against the CRL and the replicated revocation records: by
+/// [`ClusterServerVerifier`] when dialling a peer, and per request by the
+/// server middleware when a peer connects to us.
+fn parse_crls(crl_pems: &[String]) -> Result<Vec<CertificateRevocationListDer<'static>>> {
+    crl_pems
+        .iter()
+        .map(|crl| Ok(CertificateRevocationListDer::from(pem::parse(crl)?.contents().to_vec())))
+        .collect()
+}
+
 pub fn create_tls_config(
     node_cert: &NodeCertificate,
     ca_cert_pem: &str,
 ) -> Result<Arc<tokio_rustls::rustls::ServerConfig>> {
-    use tokio_rustls::rustls::{server::WebPkiClientVerifier, RootCertStore, ServerConfig};
+    create_tls_config_with_crls(node_cert, ca_cert_pem, &[])
+}
 
-    let cert_chain = vec![node_cert.cert_der()?];
-    let key = node_cert.key_der()?;
+/// Like [`create_tls_config`], but client certificates listed in any of
+/// `crl_pems` fail the handshake.
+pub fn create_tls_config_with_crls(
+    node_cert: &NodeCertificate,
+    ca_cert_pem: &str,
+    crl_pems: &[String],
+) -> Result<Arc<tokio_rustls::rustls::ServerConfig>> {
+    use tokio_rustls::rustls::{server::WebPkiClientVerifier, ServerConfig};
 
-    let mut root_store = RootCertStore::empty();
-    let ca_pem = pem::parse(ca_cert_pem)?;
-    let ca_der = CertificateDer::from(ca_pem.contents().to_vec());
-    root_store.add(ca_der)?;
+    let cert_chain = node_cert.cert_chain_der()?;
+    let key = node_cert.key_der()?;
 
-    let client_verifier = WebPkiClientVerifier::builder(Arc::new(root_store))
+    let mut builder = WebPkiClientVerifier::builder(Arc::new(root_store_from_pem(ca_cert_pem)?))
+        .allow_unauthenticated();
+    if !crl_pems.is_empty() {
+        builder = builder
+            .with_crls(parse_crls(crl_pems)?)
+            .only_check_end_entity_revocation()
+            .allow_unknown_revocation_status();
+    }
+    let client_verifier = builder
         .build()
         .map_err(|e| anyhow!("Failed to build client verifier: {}", e))?;
 
@@ -206,20 +542,148 @@ pub fn create_client_tls_config(
     node_cert: &NodeCertificate,
     ca_cert_pem: &str,
 ) -> Result<Arc<tokio_rustls::rustls::ClientConfig>> {
-    use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+    create_client_tls_config_with_crls(node_cert, ca_cert_pem, &[])
+}
+
+pub fn create_client_tls_config_with_crls(
+    node_cert: &NodeCertificate,
+    ca_cert_pem: &str,
+    crl_pems: &[String],
+) -> Result<Arc<tokio_rustls::rustls::ClientConfig>> {
+    create_client_tls_config_with_revocations(node_cert, ca_cert_pem, crl_pems, &HashSet::new())
+}
+
+/// Like [`create_client_tls_config_with_crls`], but servers presenting a
+/// certificate whose serial is in `revoked_serials` are refused as well,
+/// whichever CA issued it.
+pub fn create_client_tls_config_with_revocations(
+    node_cert: &NodeCertificate,
+    ca_cert_pem: &str,
+    crl_pems: &[String],
+    revoked_serials: &HashSet<String>,
+) -> Result<Arc<tokio_rustls::rustls::ClientConfig>> {
+    use tokio_rustls::rustls::ClientConfig;
 
-    let cert_chain = vec![node_cert.cert_der()?];
+    let cert_chain = node_cert.cert_chain_der()?;
     let key = node_cert.key_der()?;
 
-    let mut root_store = RootCertStore::empty();
-    let ca_pem = pem::parse(ca_cert_pem)?;
-    let ca_der = CertificateDer::from(ca_pem.contents().to_vec());
-    root_store.add(ca_der)?;
+    let verifier = ClusterServerVerifier::with_crls(ca_cert_pem, crl_pems)?
+        .with_revoked_serials(revoked_serials.iter().cloned());
 
     let config = ClientConfig::builder()
-        .with_root_certificates(root_store)
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
         .with_client_auth_cert(cert_chain, key)
         .map_err(|e| anyhow!("Failed to build client config: {}", e))?;
 
     Ok(Arc::new(config))
 }
+
+/// Verifies that a peer's certificate chains to the cluster CA.
+///
+/// Peers are dialled by socket address, which rarely appears in their
+/// certificate, so the name checked is the node ID the certificate was
+/// issued for rather than the host we connected to.
+///
+/// Serials listed in the CRLs, or added with
+/// [`ClusterServerVerifier::with_revoked_serials`], are refused even when
+/// their issuer has no CRL, see [`parse_crls`].
+#[derive(Debug)]
+pub struct ClusterServerVerifier {
+    inner: Arc<tokio_rustls::rustls::client::WebPkiServerVerifier>,
+    revoked_serials: HashSet<String>,
+}
+
+impl ClusterServerVerifier {
+    pub fn new(ca_cert_pem: &str) -> Result<Self> {
+        Self::with_crls(ca_cert_pem, &[])
+    }
+
+    pub fn with_crls(ca_cert_pem: &str, crl_pems: &[String]) -> Result<Self> {
+        use tokio_rustls::rustls::client::WebPkiServerVerifier;
+
+        let mut builder = WebPkiServerVerifier::builder(Arc::new(root_store_from_pem(ca_cert_pem)?));
+        if !crl_pems.is_empty() {
+            builder = builder
+                .with_crls(parse_crls(crl_pems)?)
+                .only_check_end_entity_revocation()
+                .allow_unknown_revocation_status();
+        }
+        let inner = builder
+            .build()
+            .map_err(|e| anyhow!("Failed to build server verifier: {}", e))?;
+
+        let mut revoked_serials = HashSet::new();
+        for crl in crl_pems {
+            revoked_serials.extend(revoked_serials_from_crl(crl)?);
+        }
+
+        Ok(Self {
+            inner,
+            revoked_serials,
+        })
+    }
+
+    pub fn with_revoked_serials(mut self, serials: impl IntoIterator<Item = String>) -> Self {
+        self.revoked_serials.extend(serials);
+        self
+    }
+}
+
+impl tokio_rustls::rustls::client::danger::ServerCertVerifier for ClusterServerVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<tokio_rustls::rustls::client::danger::ServerCertVerified, tokio_rustls::rustls::Error>
+    {
+        let node_id = extract_cn_from_der(end_entity).map_err(|e| {
+            tokio_rustls::rustls::Error::General(format!("Invalid peer certificate: {}", e))
+        })?;
+        let node_name = ServerName::try_from(node_id).map_err(|e| {
+            tokio_rustls::rustls::Error::General(format!("Invalid peer node ID: {}", e))
+        })?;
+        let serial = extract_serial_from_der(end_entity).map_err(|e| {
+            tokio_rustls::rustls::Error::General(format!("Invalid peer certificate: {}", e))
+        })?;
+        if self.revoked_serials.contains(&serial) {
+            return Err(tokio_rustls::rustls::Error::InvalidCertificate(
+                tokio_rustls::rustls::CertificateError::Revoked,
+            ));
+        }
+
+        self.inner
+            .verify_server_cert(end_entity, intermediates, &node_name, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &tokio_rustls::rustls::DigitallySignedStruct,
+    ) -> Result<
+        tokio_rustls::rustls::client::danger::HandshakeSignatureValid,
+        tokio_rustls::rustls::Error,
+    > {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &tokio_rustls::rustls::DigitallySignedStruct,
+    ) -> Result<
+        tokio_rustls::rustls::client::danger::HandshakeSignatureValid,
+        tokio_rustls::rustls::Error,
+    > {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<tokio_rustls::rustls::SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
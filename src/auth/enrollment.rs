@@ -1,18 +1,25 @@
 use crate::auth::certs::{CaCertificate, NodeCertificate};
-use crate::types::EnrollmentToken;
+use crate::replicator::{HiveState, Replicator};
+use crate::types::{
+    ClusterCommand, EnrolledNode, EnrollmentToken, IssuedToken, RevokedCertificate, SignedCrl,
+};
 use anyhow::{anyhow, Result};
 use chrono::{Duration, Utc};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::Path;
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
+
+const RAFT_ID_ATTEMPTS: usize = 5;
+const REVOKE_ATTEMPTS: usize = 5;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EnrollmentRequest {
     pub token: String,
     pub node_id: String,
     pub hostname: String,
+    pub addr: String,
     pub hostnames: Vec<String>,
     pub ips: Vec<String>,
     pub tags: Vec<String>,
@@ -26,6 +33,8 @@ pub struct EnrollmentResponse {
     pub node_key_pem: String,
     pub ca_cert_pem: String,
     pub peers: Vec<PeerEndpoint>,
+    /// Raft ID the cluster allocated to the node.
+    pub raft_id: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,33 +43,47 @@ pub struct PeerEndpoint {
     pub addr: String,
 }
 
-pub struct EnrollmentManager {
-    cluster_id: String,
-    ca: CaCertificate,
-    tokens: Arc<RwLock<HashMap<String, EnrollmentToken>>>,
-    enrolled_nodes: Arc<RwLock<HashMap<String, EnrolledNode>>>,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenewalRequest {
+    pub hostnames: Vec<String>,
+    pub ips: Vec<String>,
 }
 
-#[derive(Debug, Clone)]
-pub struct EnrolledNode {
-    pub node_id: String,
-    pub hostname: String,
-    pub addr: String,
-    pub tags: Vec<String>,
-    pub enrolled_at: chrono::DateTime<Utc>,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenewalResponse {
+    pub node_cert_pem: String,
+    pub node_key_pem: String,
 }
 
-impl EnrollmentManager {
-    pub fn new(cluster_id: String, ca: CaCertificate) -> Self {
+pub struct EnrollmentManager<R: Replicator> {
+    cluster_id: String,
+    ca: CaCertificate,
+    trust_bundle_pem: String,
+    replicator: Arc<R>,
+}
+
+impl<R: Replicator> EnrollmentManager<R> {
+    pub fn new(cluster_id: String, ca: CaCertificate, replicator: Arc<R>) -> Self {
         Self {
             cluster_id,
+            trust_bundle_pem: ca.cert_pem.clone(),
             ca,
-            tokens: Arc::new(RwLock::new(HashMap::new())),
-            enrolled_nodes: Arc::new(RwLock::new(HashMap::new())),
+            replicator,
         }
     }
 
-    pub fn load_or_create<P: AsRef<Path>>(data_dir: P, cluster_id: &str) -> Result<Self> {
+    /// Sets the roots handed to enrolling nodes. Required when `ca` is an
+    /// intermediate, whose own certificate is not a trust anchor.
+    pub fn with_trust_bundle(mut self, trust_bundle_pem: String) -> Self {
+        self.trust_bundle_pem = trust_bundle_pem;
+        self
+    }
+
+    pub fn load_or_create<P: AsRef<Path>>(
+        data_dir: P,
+        cluster_id: &str,
+        replicator: Arc<R>,
+    ) -> Result<Self> {
         let ca_cert_path = data_dir.as_ref().join("ca.crt");
         let ca_key_path = data_dir.as_ref().join("ca.key");
 
@@ -74,42 +97,55 @@ impl EnrollmentManager {
             ca
         };
 
-        Ok(Self::new(cluster_id.to_string(), ca))
+        Ok(Self::new(cluster_id.to_string(), ca, replicator))
     }
 
-    pub fn generate_token(&self, valid_hours: i64, allowed_tags: Vec<String>) -> EnrollmentToken {
+    pub async fn generate_token(
+        &self,
+        valid_hours: i64,
+        allowed_tags: Vec<String>,
+    ) -> Result<EnrollmentToken> {
         let token: String = rand::thread_rng()
             .sample_iter(&rand::distributions::Alphanumeric)
             .take(32)
             .map(char::from)
             .collect();
 
+        let now = Utc::now();
         let enrollment_token = EnrollmentToken {
             token: token.clone(),
             cluster_id: self.cluster_id.clone(),
-            expires_at: Utc::now() + Duration::hours(valid_hours),
+            expires_at: now + Duration::hours(valid_hours),
             allowed_tags,
         };
 
-        self.tokens
-            .write()
-            .unwrap()
-            .insert(token, enrollment_token.clone());
+        self.replicator
+            .apply(ClusterCommand::IssueToken(IssuedToken {
+                token_hash: hash_token(&token),
+                cluster_id: self.cluster_id.clone(),
+                issued_at: now,
+                expires_at: enrollment_token.expires_at,
+                allowed_tags: enrollment_token.allowed_tags.clone(),
+                consumed: None,
+            }))
+            .await?;
 
-        enrollment_token
+        Ok(enrollment_token)
     }
 
-    pub fn enroll(&self, req: EnrollmentRequest) -> Result<EnrollmentResponse> {
+    pub async fn enroll(&self, req: EnrollmentRequest) -> Result<EnrollmentResponse> {
+        let token_hash = hash_token(&req.token);
         let token = self
+            .replicator
+            .hive_state()
             .tokens
-            .read()
-            .unwrap()
-            .get(&req.token)
+            .get(&token_hash)
             .cloned()
+            .filter(|t| t.consumed.is_none())
             .ok_or_else(|| anyhow!("Invalid enrollment token"))?;
 
-        if Utc::now() > token.expires_at {
-            self.tokens.write().unwrap().remove(&req.token);
+        let now = Utc::now();
+        if now > token.expires_at {
             return Err(anyhow!("Enrollment token has expired"));
         }
 
@@ -124,54 +160,250 @@ impl EnrollmentManager {
             }
         }
 
+        let request_id = uuid::Uuid::new_v4().to_string();
+        let result = self
+            .replicator
+            .apply(ClusterCommand::ConsumeToken {
+                token_hash: token_hash.clone(),
+                node_id: req.node_id.clone(),
+                request_id,
+                consumed_at: now,
+            })
+            .await?;
+        if !result.is_applied() {
+            return Err(anyhow!("Invalid enrollment token"));
+        }
+
+        let raft_id = self.assign_raft_id(&req.node_id).await?;
+
         let node_cert = self.ca.sign_node(&req.node_id, req.hostnames, req.ips)?;
+        let cert_serial = node_cert.serial()?;
+        let issuer_key_id = node_cert.issuer_key_id()?;
+        let cert_expiry = HashMap::from([(cert_serial.clone(), node_cert.not_after()?)]);
 
         let peers: Vec<PeerEndpoint> = self
-            .enrolled_nodes
-            .read()
-            .unwrap()
-            .values()
+            .get_enrolled_nodes()
+            .into_iter()
             .map(|n| PeerEndpoint {
-                node_id: n.node_id.clone(),
-                addr: n.addr.clone(),
+                node_id: n.node_id,
+                addr: n.addr,
             })
             .collect();
 
-        self.tokens.write().unwrap().remove(&req.token);
+        self.record_enrollment(EnrolledNode {
+            node_id: req.node_id.clone(),
+            hostname: req.hostname,
+            addr: req.addr,
+            tags: req.tags,
+            enrolled_at: Utc::now(),
+            cert_serial: Some(cert_serial),
+            superseded_serials: Vec::new(),
+            issuer_key_id,
+            cert_expiry,
+        })
+        .await?;
 
         Ok(EnrollmentResponse {
             node_id: req.node_id,
             cluster_id: self.cluster_id.clone(),
             node_cert_pem: node_cert.cert_pem,
             node_key_pem: node_cert.key_pem,
-            ca_cert_pem: self.ca.cert_pem.clone(),
+            ca_cert_pem: self.trust_bundle_pem.clone(),
             peers,
+            raft_id,
         })
     }
 
-    pub fn register_enrolled_node(&self, node_id: String, hostname: String, addr: String, tags: Vec<String>) {
-        self.enrolled_nodes.write().unwrap().insert(
-            node_id.clone(),
-            EnrolledNode {
-                node_id,
-                hostname,
-                addr,
-                tags,
-                enrolled_at: Utc::now(),
-            },
+    /// Allocates the next free Raft ID to `node_id`, or returns the one it
+    /// already holds. Retries when a concurrent enrollment took the ID.
+    pub async fn assign_raft_id(&self, node_id: &str) -> Result<u64> {
+        for _ in 0..RAFT_ID_ATTEMPTS {
+            let state = self.replicator.hive_state();
+            if let Some(raft_id) = state.raft_id_of(node_id) {
+                return Ok(raft_id);
+            }
+
+            let raft_id = state.next_raft_id();
+            let result = self
+                .replicator
+                .apply(ClusterCommand::AssignRaftId {
+                    node_id: node_id.to_string(),
+                    raft_id,
+                })
+                .await?;
+            if result.is_applied() {
+                return Ok(raft_id);
+            }
+        }
+
+        Err(anyhow!("Could not allocate a Raft ID for node {}", node_id))
+    }
+
+    pub async fn register_enrolled_node(
+        &self,
+        node_id: String,
+        hostname: String,
+        addr: String,
+        tags: Vec<String>,
+    ) -> Result<()> {
+        self.record_enrollment(EnrolledNode {
+            node_id,
+            hostname,
+            addr,
+            tags,
+            enrolled_at: Utc::now(),
+            cert_serial: None,
+            superseded_serials: Vec::new(),
+            issuer_key_id: None,
+            cert_expiry: HashMap::new(),
+        })
+        .await
+    }
+
+    pub async fn record_enrollment(&self, node: EnrolledNode) -> Result<()> {
+        self.replicator
+            .apply(ClusterCommand::RecordEnrollment(node))
+            .await?;
+        Ok(())
+    }
+
+    /// Issues a replacement certificate for an enrolled node. `current_serial`
+    /// is the serial of the certificate the node authenticated with.
+    pub async fn renew(
+        &self,
+        node_id: &str,
+        current_serial: &str,
+        hostnames: Vec<String>,
+        ips: Vec<String>,
+    ) -> Result<NodeCertificate> {
+        let state = self.replicator.hive_state();
+        let mut node = state
+            .enrollments
+            .get(node_id)
+            .cloned()
+            .ok_or_else(|| anyhow!("Node {} is not enrolled", node_id))?;
+
+        if state.revoked_certificates.contains_key(current_serial) {
+            return Err(anyhow!("Certificate {} has been revoked", current_serial));
+        }
+
+        let known = node.cert_serial.as_deref() == Some(current_serial)
+            || node.superseded_serials.iter().any(|s| s == current_serial);
+        if node.cert_serial.is_some() && !known {
+            return Err(anyhow!(
+                "Certificate {} was not issued to node {}",
+                current_serial,
+                node_id
+            ));
+        }
+
+        let node_cert = self.ca.sign_node(node_id, hostnames, ips)?;
+        node.replace_cert(
+            Some(node_cert.serial()?),
+            Some(node_cert.not_after()?),
+            Utc::now(),
         );
+        node.issuer_key_id = node_cert.issuer_key_id()?;
+        self.record_enrollment(node).await?;
+
+        Ok(node_cert)
+    }
+
+    /// Evicts a node: drops it from Raft membership, then revokes its
+    /// certificates and publishes a new CRL through the log. A revocation
+    /// committed in between invalidates the CRL, so it is signed again over
+    /// the fresh state.
+    pub async fn revoke_node(
+        &self,
+        node_id: &str,
+        reason: Option<String>,
+    ) -> Result<Vec<RevokedCertificate>> {
+        if node_serials(&self.replicator.hive_state(), node_id).is_empty() {
+            return Err(anyhow!("No certificate on record for node {}", node_id));
+        }
+
+        self.replicator.remove_peer(node_id).await?;
+
+        for _ in 0..REVOKE_ATTEMPTS {
+            self.replicator.ensure_linearizable().await?;
+            let state = self.replicator.hive_state();
+            let serials = node_serials(&state, node_id);
+            if serials.is_empty() {
+                return Err(anyhow!("No certificate on record for node {}", node_id));
+            }
+
+            let now = Utc::now();
+            let revoked: Vec<RevokedCertificate> = serials
+                .into_iter()
+                .filter(|serial| !state.revoked_certificates.contains_key(serial))
+                .map(|serial| RevokedCertificate {
+                    serial,
+                    node_id: node_id.to_string(),
+                    revoked_at: now,
+                    reason: reason.clone(),
+                })
+                .collect();
+
+            let mut all_revoked: Vec<RevokedCertificate> =
+                state.revoked_certificates.into_values().collect();
+            all_revoked.extend(revoked.iter().cloned());
+
+            let number = state.crl.map_or(1, |crl| crl.number + 1);
+            let crl = SignedCrl {
+                number,
+                pem: self.ca.sign_crl(&all_revoked, number)?,
+            };
+
+            let result = self
+                .replicator
+                .apply(ClusterCommand::RevokeNode {
+                    node_id: node_id.to_string(),
+                    revoked: revoked.clone(),
+                    crl,
+                })
+                .await?;
+            if result.is_applied() {
+                return Ok(revoked);
+            }
+        }
+
+        Err(anyhow!("Could not publish a CRL revoking node {}", node_id))
     }
 
     pub fn is_enrolled(&self, node_id: &str) -> bool {
-        self.enrolled_nodes.read().unwrap().contains_key(node_id)
+        self.replicator.hive_state().enrollments.contains_key(node_id)
+    }
+
+    pub fn enrolled_node(&self, node_id: &str) -> Option<EnrolledNode> {
+        self.replicator.hive_state().enrollments.get(node_id).cloned()
     }
 
     pub fn get_enrolled_nodes(&self) -> Vec<EnrolledNode> {
-        self.enrolled_nodes.read().unwrap().values().cloned().collect()
+        self.replicator
+            .hive_state()
+            .enrollments
+            .into_values()
+            .collect()
     }
 
     pub fn ca_cert_pem(&self) -> &str {
-        &self.ca.cert_pem
+        &self.trust_bundle_pem
+    }
+
+    pub fn signing_key_id(&self) -> Result<String> {
+        self.ca.key_id()
+    }
+
+    /// Enrolled nodes whose current certificate was issued by a CA other
+    /// than the one signing now. A CA rollover is complete once this is
+    /// empty.
+    pub fn nodes_pending_reissue(&self) -> Result<Vec<EnrolledNode>> {
+        let key_id = self.ca.key_id()?;
+        Ok(self
+            .get_enrolled_nodes()
+            .into_iter()
+            .filter(|n| n.issuer_key_id.as_deref() != Some(key_id.as_str()))
+            .collect())
     }
 
     pub fn cluster_id(&self) -> &str {
@@ -187,3 +419,18 @@ impl EnrollmentManager {
         self.ca.sign_node(node_id, hostnames, ips)
     }
 }
+
+/// Current and unexpired superseded certificate serials on record for
+/// `node_id`.
+fn node_serials(state: &HiveState, node_id: &str) -> Vec<String> {
+    state
+        .enrollments
+        .get(node_id)
+        .map(|n| n.live_serials(Utc::now()))
+        .unwrap_or_default()
+}
+
+pub fn hash_token(token: &str) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, token.as_bytes());
+    digest.as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -1,5 +1,7 @@
 pub mod certs;
 pub mod enrollment;
+pub mod tls;
 
 pub use certs::*;
 pub use enrollment::*;
+pub use tls::*;
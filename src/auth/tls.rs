@@ -0,0 +1,166 @@
+use crate::auth::certs::{
+    create_client_tls_config_with_revocations, create_tls_config_with_crls,
+    revoked_serials_from_crl, NodeCertificate,
+};
+use crate::types::{SignedCrl, TrustBundle};
+use anyhow::{anyhow, Result};
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use tokio_rustls::rustls::{ClientConfig, ServerConfig};
+
+/// TLS material for this node. The node certificate, trusted roots, CRL
+/// and revocation records can be swapped at runtime; new handshakes and
+/// requests pick up the rebuilt configs immediately.
+pub struct ClusterTls {
+    state: RwLock<TlsState>,
+}
+
+struct TlsState {
+    node_cert: NodeCertificate,
+    ca_cert_pem: String,
+    trust_version: Option<u64>,
+    crl: Option<SignedCrl>,
+    revoked_serials: HashSet<String>,
+    /// Serials revoked in replicated state. They normally match the CRL's,
+    /// but are checked too so a revocation the CRL missed, or one of a
+    /// certificate from an issuer without a CRL, still applies.
+    revoked_records: HashSet<String>,
+    server_config: Arc<ServerConfig>,
+    client_config: Arc<ClientConfig>,
+    http_client: reqwest::Client,
+}
+
+impl TlsState {
+    fn build(
+        node_cert: NodeCertificate,
+        ca_cert_pem: String,
+        crl: Option<SignedCrl>,
+        revoked_records: HashSet<String>,
+    ) -> Result<Self> {
+        let crl_pems: Vec<String> = crl.iter().map(|c| c.pem.clone()).collect();
+        let revoked_serials = match &crl {
+            Some(crl) => revoked_serials_from_crl(&crl.pem)?.into_iter().collect(),
+            None => HashSet::new(),
+        };
+
+        let server_config = create_tls_config_with_crls(&node_cert, &ca_cert_pem, &crl_pems)?;
+        let client_config = create_client_tls_config_with_revocations(
+            &node_cert,
+            &ca_cert_pem,
+            &crl_pems,
+            &revoked_records,
+        )?;
+        let http_client = reqwest::Client::builder()
+            .use_preconfigured_tls((*client_config).clone())
+            .build()
+            .map_err(|e| anyhow!("Failed to build HTTPS client: {}", e))?;
+
+        Ok(Self {
+            node_cert,
+            ca_cert_pem,
+            trust_version: None,
+            crl,
+            revoked_serials,
+            revoked_records,
+            server_config,
+            client_config,
+            http_client,
+        })
+    }
+}
+
+impl ClusterTls {
+    pub fn new(node_cert: NodeCertificate, ca_cert_pem: String) -> Result<Self> {
+        Ok(Self {
+            state: RwLock::new(TlsState::build(node_cert, ca_cert_pem, None, HashSet::new())?),
+        })
+    }
+
+    pub fn node_cert(&self) -> NodeCertificate {
+        self.state.read().unwrap().node_cert.clone()
+    }
+
+    pub fn ca_cert_pem(&self) -> String {
+        self.state.read().unwrap().ca_cert_pem.clone()
+    }
+
+    pub fn trust_version(&self) -> Option<u64> {
+        self.state.read().unwrap().trust_version
+    }
+
+    pub fn crl_number(&self) -> Option<u64> {
+        self.state.read().unwrap().crl.as_ref().map(|c| c.number)
+    }
+
+    pub fn is_revoked(&self, serial: &str) -> bool {
+        let state = self.state.read().unwrap();
+        state.revoked_serials.contains(serial) || state.revoked_records.contains(serial)
+    }
+
+    pub fn set_revoked_records(&self, serials: HashSet<String>) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        if state.revoked_records == serials {
+            return Ok(());
+        }
+        let mut rebuilt = TlsState::build(
+            state.node_cert.clone(),
+            state.ca_cert_pem.clone(),
+            state.crl.clone(),
+            serials,
+        )?;
+        rebuilt.trust_version = state.trust_version;
+        *state = rebuilt;
+        Ok(())
+    }
+
+    pub fn server_config(&self) -> Arc<ServerConfig> {
+        self.state.read().unwrap().server_config.clone()
+    }
+
+    pub fn client_config(&self) -> Arc<ClientConfig> {
+        self.state.read().unwrap().client_config.clone()
+    }
+
+    pub fn http_client(&self) -> reqwest::Client {
+        self.state.read().unwrap().http_client.clone()
+    }
+
+    pub fn update_crl(&self, crl: SignedCrl) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        let mut rebuilt = TlsState::build(
+            state.node_cert.clone(),
+            state.ca_cert_pem.clone(),
+            Some(crl),
+            state.revoked_records.clone(),
+        )?;
+        rebuilt.trust_version = state.trust_version;
+        *state = rebuilt;
+        Ok(())
+    }
+
+    pub fn update_node_cert(&self, node_cert: NodeCertificate) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        let mut rebuilt = TlsState::build(
+            node_cert,
+            state.ca_cert_pem.clone(),
+            state.crl.clone(),
+            state.revoked_records.clone(),
+        )?;
+        rebuilt.trust_version = state.trust_version;
+        *state = rebuilt;
+        Ok(())
+    }
+
+    pub fn update_trust_bundle(&self, bundle: &TrustBundle) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        let mut rebuilt = TlsState::build(
+            state.node_cert.clone(),
+            bundle.roots_pem.clone(),
+            state.crl.clone(),
+            state.revoked_records.clone(),
+        )?;
+        rebuilt.trust_version = Some(bundle.version);
+        *state = rebuilt;
+        Ok(())
+    }
+}
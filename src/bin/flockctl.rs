@@ -1,14 +1,33 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
+use flockmind::auth::{create_client_tls_config, NodeCertificate};
+use flockmind::replicator::{StateBackup, StateDiff};
+use flockmind::{MAX_KV_TTL_SECS, MAX_LOCK_TTL_SECS};
 use serde_json::Value;
+use std::io::Write;
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(name = "flockctl")]
 #[command(about = "CLI for FlockMind hive management")]
 struct Cli {
-    #[arg(short, long, default_value = "http://127.0.0.1:9000")]
+    #[arg(short, long, default_value = "https://127.0.0.1:9000")]
     addr: String,
 
+    #[arg(long, default_value = "/var/lib/flockmind/ca.crt")]
+    ca_cert: PathBuf,
+
+    #[arg(long, default_value = "/var/lib/flockmind/node.crt")]
+    cert: PathBuf,
+
+    #[arg(long, default_value = "/var/lib/flockmind/node.key")]
+    key: PathBuf,
+
+    /// Make cluster, task, goal and key listings reflect every committed write,
+    /// even when --addr points at a lagging follower
+    #[arg(long, global = true)]
+    linearizable: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -16,7 +35,28 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     Status,
-    Cluster,
+    Cluster {
+        /// Show the cluster as of a log index or RFC 3339 time, rebuilt
+        /// from the history of the node at --addr
+        #[arg(long)]
+        at: Option<String>,
+    },
+
+    /// List applied commands with their log index, term and time, as
+    /// recorded by the node at --addr
+    History {
+        /// First log index or RFC 3339 time to include
+        #[arg(long)]
+        from: Option<String>,
+
+        /// Last log index or RFC 3339 time to include
+        #[arg(long)]
+        to: Option<String>,
+
+        /// Only commands touching this node, task, attachment or goal ID
+        #[arg(long)]
+        entity: Option<String>,
+    },
 
     #[command(subcommand)]
     Task(TaskCommands),
@@ -25,6 +65,204 @@ enum Commands {
     Goal(GoalCommands),
 
     Attachments,
+
+    #[command(subcommand)]
+    Token(TokenCommands),
+
+    #[command(subcommand)]
+    Node(NodeCommands),
+
+    #[command(subcommand)]
+    Ca(CaCommands),
+
+    #[command(subcommand)]
+    Member(MemberCommands),
+
+    #[command(subcommand)]
+    Kv(KvCommands),
+
+    #[command(subcommand)]
+    Lock(LockCommands),
+
+    /// Print commands as the node at --addr applies them, one JSON object
+    /// per line
+    Watch {
+        /// Comma-separated entity types: node, task, attachment, goal,
+        /// token, enrollment, trust_bundle, kv, lock
+        #[arg(long)]
+        types: Option<String>,
+
+        #[arg(long)]
+        node: Option<String>,
+
+        #[arg(long)]
+        task: Option<String>,
+
+        /// Replay commands applied from this log index on first
+        #[arg(long)]
+        from_index: Option<u64>,
+    },
+
+    /// Show what changed in nodes, tasks, goals and attachments between two
+    /// log indexes or RFC 3339 times, or between two `backup` files
+    Diff {
+        #[arg(long)]
+        from: String,
+
+        /// Defaults to the current state of the node at --addr
+        #[arg(long)]
+        to: Option<String>,
+
+        /// Print the diff as JSON instead of one line per change
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Write a consistent copy of cluster state, taken by the leader, to
+    /// stdout; `flockmind restore --from` seeds a new cluster from it
+    Backup,
+}
+
+#[derive(Subcommand)]
+enum MemberCommands {
+    /// Show Raft members, their roles and replication lag (from the leader)
+    List,
+    /// Add an enrolled node as a learner, or as a voter with --voter
+    Add {
+        node_id: String,
+
+        #[arg(long)]
+        addr: String,
+
+        #[arg(long)]
+        voter: bool,
+    },
+    Remove {
+        node_id: String,
+    },
+    /// Turn a learner into a voter
+    Promote {
+        node_id: String,
+    },
+    /// Hand leadership to a caught-up voter
+    TransferLeader {
+        node_id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum KvCommands {
+    /// Print a key, or every key starting with it with --prefix
+    Get {
+        key: String,
+
+        #[arg(long)]
+        prefix: bool,
+    },
+    /// Set a key, failing if it was modified since `--revision` (0 means it
+    /// must not exist yet)
+    Put {
+        key: String,
+        value: String,
+
+        /// Expire the key after this many seconds
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..=MAX_KV_TTL_SECS))]
+        ttl: Option<u64>,
+
+        #[arg(long)]
+        revision: Option<u64>,
+    },
+    Del {
+        key: String,
+
+        #[arg(long)]
+        revision: Option<u64>,
+    },
+    /// Print writes to a key, or to every key starting with it with --prefix
+    Watch {
+        key: String,
+
+        #[arg(long)]
+        prefix: bool,
+
+        /// Replay writes applied from this log index on first
+        #[arg(long)]
+        from_index: Option<u64>,
+    },
+}
+
+#[derive(Subcommand)]
+enum LockCommands {
+    /// Show held locks, or only those starting with --prefix
+    List {
+        #[arg(long, default_value = "")]
+        prefix: String,
+    },
+    Get {
+        key: String,
+    },
+    /// Take a lock and print its lease, including the fencing token to pass
+    /// along with writes made while holding it
+    Acquire {
+        key: String,
+
+        #[arg(long)]
+        holder: String,
+
+        #[arg(
+            long,
+            default_value = "30",
+            value_parser = clap::value_parser!(u64).range(1..=MAX_LOCK_TTL_SECS)
+        )]
+        ttl: u64,
+    },
+    /// Extend a held lock to --ttl seconds from now
+    Renew {
+        key: String,
+
+        #[arg(long)]
+        holder: String,
+
+        #[arg(
+            long,
+            default_value = "30",
+            value_parser = clap::value_parser!(u64).range(1..=MAX_LOCK_TTL_SECS)
+        )]
+        ttl: u64,
+    },
+    Release {
+        key: String,
+
+        #[arg(long)]
+        holder: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum CaCommands {
+    /// Show the signing CA and nodes still holding certificates from another CA
+    Status,
+}
+
+#[derive(Subcommand)]
+enum NodeCommands {
+    Revoke {
+        node_id: String,
+
+        #[arg(short, long)]
+        reason: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum TokenCommands {
+    Create {
+        #[arg(long, default_value = "24")]
+        valid_hours: i64,
+
+        #[arg(short, long)]
+        tag: Vec<String>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -42,6 +280,10 @@ enum TaskCommands {
 
         #[arg(short, long, default_value = "5")]
         priority: u8,
+
+        /// Never run at the same time as another task with this lock
+        #[arg(long)]
+        lock: Option<String>,
     },
 }
 
@@ -55,13 +297,30 @@ enum GoalCommands {
         #[arg(short, long, default_value = "5")]
         priority: u8,
     },
+    /// Change a goal, failing if it was modified since `--revision`
+    Update {
+        goal_id: String,
+
+        #[arg(short, long)]
+        description: Option<String>,
+
+        #[arg(short, long)]
+        priority: Option<u8>,
+
+        #[arg(long)]
+        active: Option<bool>,
+
+        #[arg(long)]
+        revision: Option<u64>,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
-    let client = reqwest::Client::new();
+    let client = build_client(&cli)?;
     let base_url = cli.addr;
+    let consistency = if cli.linearizable { "linearizable" } else { "local" };
 
     match cli.command {
         Commands::Status => {
@@ -73,9 +332,26 @@ async fn main() -> Result<()> {
                 .await?;
             println!("{}", serde_json::to_string_pretty(&resp)?);
         }
-        Commands::Cluster => {
+        Commands::Cluster { at } => {
+            let mut query = vec![("consistency", consistency.to_string())];
+            query.extend(at.map(|at| ("at", at)));
             let resp: Value = client
                 .get(format!("{}/cluster", base_url))
+                .query(&query)
+                .send()
+                .await?
+                .json()
+                .await?;
+            println!("{}", serde_json::to_string_pretty(&resp)?);
+        }
+        Commands::History { from, to, entity } => {
+            let query: Vec<_> = [("from", from), ("to", to), ("entity", entity)]
+                .into_iter()
+                .filter_map(|(name, value)| Some((name, value?)))
+                .collect();
+            let resp: Value = client
+                .get(format!("{}/history", base_url))
+                .query(&query)
                 .send()
                 .await?
                 .json()
@@ -86,6 +362,7 @@ async fn main() -> Result<()> {
             TaskCommands::List => {
                 let resp: Value = client
                     .get(format!("{}/tasks", base_url))
+                    .query(&[("consistency", consistency)])
                     .send()
                     .await?
                     .json()
@@ -97,6 +374,7 @@ async fn main() -> Result<()> {
                 echo,
                 check_service,
                 priority,
+                lock,
             } => {
                 let payload = if let Some(msg) = echo {
                     serde_json::json!({
@@ -114,6 +392,7 @@ async fn main() -> Result<()> {
                     "target_node": node,
                     "payload": payload,
                     "priority": priority,
+                    "lock": lock,
                 });
 
                 let resp: Value = client
@@ -130,6 +409,7 @@ async fn main() -> Result<()> {
             GoalCommands::List => {
                 let resp: Value = client
                     .get(format!("{}/goals", base_url))
+                    .query(&[("consistency", consistency)])
                     .send()
                     .await?
                     .json()
@@ -154,6 +434,29 @@ async fn main() -> Result<()> {
                     .await?;
                 println!("{}", serde_json::to_string_pretty(&resp)?);
             }
+            GoalCommands::Update {
+                goal_id,
+                description,
+                priority,
+                active,
+                revision,
+            } => {
+                let body = serde_json::json!({
+                    "description": description,
+                    "priority": priority,
+                    "active": active,
+                    "expected_revision": revision,
+                });
+
+                let resp: Value = client
+                    .put(format!("{}/goals/{}", base_url, goal_id))
+                    .json(&body)
+                    .send()
+                    .await?
+                    .json()
+                    .await?;
+                println!("{}", serde_json::to_string_pretty(&resp)?);
+            }
         },
         Commands::Attachments => {
             let resp: Value = client
@@ -164,7 +467,289 @@ async fn main() -> Result<()> {
                 .await?;
             println!("{}", serde_json::to_string_pretty(&resp)?);
         }
+        Commands::Token(cmd) => match cmd {
+            TokenCommands::Create { valid_hours, tag } => {
+                let body = serde_json::json!({
+                    "valid_hours": valid_hours,
+                    "allowed_tags": tag,
+                });
+
+                let resp: Value = client
+                    .post(format!("{}/enroll/tokens", base_url))
+                    .json(&body)
+                    .send()
+                    .await?
+                    .json()
+                    .await?;
+                println!("{}", serde_json::to_string_pretty(&resp)?);
+            }
+        },
+        Commands::Ca(cmd) => match cmd {
+            CaCommands::Status => {
+                let resp: Value = client
+                    .get(format!("{}/admin/ca", base_url))
+                    .send()
+                    .await?
+                    .json()
+                    .await?;
+                println!("{}", serde_json::to_string_pretty(&resp)?);
+            }
+        },
+        Commands::Member(cmd) => {
+            let request = match cmd {
+                MemberCommands::List => client.get(format!("{}/admin/members", base_url)),
+                MemberCommands::Add {
+                    node_id,
+                    addr,
+                    voter,
+                } => client
+                    .post(format!("{}/admin/members", base_url))
+                    .json(&serde_json::json!({
+                        "node_id": node_id,
+                        "addr": addr,
+                        "is_voter": voter,
+                    })),
+                MemberCommands::Remove { node_id } => {
+                    client.delete(format!("{}/admin/members/{}", base_url, node_id))
+                }
+                MemberCommands::Promote { node_id } => {
+                    client.post(format!("{}/admin/members/{}/promote", base_url, node_id))
+                }
+                MemberCommands::TransferLeader { node_id } => client
+                    .post(format!("{}/admin/leader", base_url))
+                    .json(&serde_json::json!({ "node_id": node_id })),
+            };
+
+            let resp: Value = request.send().await?.json().await?;
+            println!("{}", serde_json::to_string_pretty(&resp)?);
+        }
+        Commands::Watch {
+            types,
+            node,
+            task,
+            from_index,
+        } => {
+            let query: Vec<_> = [
+                ("types", types),
+                ("node", node),
+                ("task", task),
+                ("from_index", from_index.map(|i| i.to_string())),
+            ]
+            .into_iter()
+            .filter_map(|(name, value)| Some((name, value?)))
+            .collect();
+            print_applied(&client, &base_url, &query).await?;
+        }
+        Commands::Kv(cmd) => match cmd {
+            KvCommands::Get { key, prefix } => {
+                let request = if prefix {
+                    client
+                        .get(format!("{}/kv", base_url))
+                        .query(&[("prefix", key.as_str()), ("consistency", consistency)])
+                } else {
+                    client
+                        .get(format!("{}/kv/{}", base_url, key))
+                        .query(&[("consistency", consistency)])
+                };
+                let resp: Value = request.send().await?.json().await?;
+                println!("{}", serde_json::to_string_pretty(&resp)?);
+            }
+            KvCommands::Put {
+                key,
+                value,
+                ttl,
+                revision,
+            } => {
+                let body = serde_json::json!({
+                    "value": value,
+                    "ttl_secs": ttl,
+                    "expected_revision": revision,
+                });
+
+                let resp: Value = client
+                    .put(format!("{}/kv/{}", base_url, key))
+                    .json(&body)
+                    .send()
+                    .await?
+                    .json()
+                    .await?;
+                println!("{}", serde_json::to_string_pretty(&resp)?);
+            }
+            KvCommands::Del { key, revision } => {
+                let mut request = client.delete(format!("{}/kv/{}", base_url, key));
+                if let Some(revision) = revision {
+                    request = request.query(&[("expected_revision", revision)]);
+                }
+                let resp = request.send().await?;
+                if !resp.status().is_success() {
+                    let status = resp.status();
+                    let body: Value = resp.json().await?;
+                    anyhow::bail!("Delete failed ({}): {}", status, body);
+                }
+            }
+            KvCommands::Watch {
+                key,
+                prefix,
+                from_index,
+            } => {
+                let mut query = vec![
+                    ("types", "kv".to_string()),
+                    ("key", key),
+                    ("prefix", prefix.to_string()),
+                ];
+                query.extend(from_index.map(|i| ("from_index", i.to_string())));
+                print_applied(&client, &base_url, &query).await?;
+            }
+        },
+        Commands::Lock(cmd) => {
+            let request = match cmd {
+                LockCommands::List { prefix } => client
+                    .get(format!("{}/locks", base_url))
+                    .query(&[("prefix", prefix.as_str()), ("consistency", consistency)]),
+                LockCommands::Get { key } => client
+                    .get(format!("{}/locks/{}", base_url, key))
+                    .query(&[("consistency", consistency)]),
+                LockCommands::Acquire { key, holder, ttl } => client
+                    .post(format!("{}/locks/{}", base_url, key))
+                    .json(&serde_json::json!({ "holder": holder, "ttl_secs": ttl })),
+                LockCommands::Renew { key, holder, ttl } => client
+                    .put(format!("{}/locks/{}", base_url, key))
+                    .json(&serde_json::json!({ "holder": holder, "ttl_secs": ttl })),
+                LockCommands::Release { key, holder } => client
+                    .delete(format!("{}/locks/{}", base_url, key))
+                    .query(&[("holder", holder)]),
+            };
+
+            let resp = request.send().await?;
+            let status = resp.status();
+            if status == reqwest::StatusCode::NO_CONTENT {
+                return Ok(());
+            }
+            let body: Value = resp.json().await?;
+            if !status.is_success() {
+                anyhow::bail!("Lock request failed ({}): {}", status, body);
+            }
+            println!("{}", serde_json::to_string_pretty(&body)?);
+        }
+        Commands::Diff { from, to, json } => {
+            let diff = if std::path::Path::new(&from).is_file() {
+                let Some(to) = to else {
+                    anyhow::bail!("Comparing with a backup file needs --to <backup file>");
+                };
+                StateDiff::between(&read_backup(&from)?.state, &read_backup(&to)?.state)
+            } else {
+                let mut query = vec![("from", from)];
+                query.extend(to.map(|to| ("to", to)));
+                let resp = client
+                    .get(format!("{}/diff", base_url))
+                    .query(&query)
+                    .send()
+                    .await?;
+                if !resp.status().is_success() {
+                    let status = resp.status();
+                    let body: Value = resp.json().await?;
+                    anyhow::bail!("Diff failed ({}): {}", status, body);
+                }
+                resp.json().await?
+            };
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&diff)?);
+            } else {
+                print!("{}", diff);
+            }
+        }
+        Commands::Backup => {
+            let resp = client
+                .get(format!("{}/admin/backup", base_url))
+                .send()
+                .await?;
+            if !resp.status().is_success() {
+                let status = resp.status();
+                let body: Value = resp.json().await?;
+                anyhow::bail!("Backup failed ({}): {}", status, body);
+            }
+            let data = resp.bytes().await?;
+            std::io::stdout().write_all(&data)?;
+        }
+        Commands::Node(cmd) => match cmd {
+            NodeCommands::Revoke { node_id, reason } => {
+                let body = serde_json::json!({ "reason": reason });
+
+                let resp: Value = client
+                    .post(format!("{}/admin/nodes/{}/revoke", base_url, node_id))
+                    .json(&body)
+                    .send()
+                    .await?
+                    .json()
+                    .await?;
+                println!("{}", serde_json::to_string_pretty(&resp)?);
+            }
+        },
     }
 
     Ok(())
 }
+
+/// Streams `/watch` with `query`, printing each applied command as one
+/// JSON object per line until the server closes the stream.
+async fn print_applied(
+    client: &reqwest::Client,
+    base_url: &str,
+    query: &[(&str, String)],
+) -> Result<()> {
+    let mut resp = client
+        .get(format!("{}/watch", base_url))
+        .query(query)
+        .send()
+        .await?;
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body: Value = resp.json().await?;
+        anyhow::bail!("Watch failed ({}): {}", status, body);
+    }
+
+    // Server-sent events are separated by blank lines; print the data of
+    // each and report lagging on stderr.
+    let mut buffer = String::new();
+    while let Some(chunk) = resp.chunk().await? {
+        buffer.push_str(&String::from_utf8_lossy(&chunk));
+        while let Some(end) = buffer.find("\n\n") {
+            let event: String = buffer.drain(..end + 2).collect();
+            let data = event.lines().find_map(|line| line.strip_prefix("data:"));
+            let lagged = event
+                .lines()
+                .any(|line| line.strip_prefix("event:").map(str::trim) == Some("lagged"));
+            match (lagged, data) {
+                (true, Some(missed)) => eprintln!(
+                    "Missed {} commands; rerun with --from-index to replay them",
+                    missed.trim()
+                ),
+                (false, Some(data)) => println!("{}", data.trim()),
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_backup(path: &str) -> Result<StateBackup> {
+    let data = std::fs::read(path).with_context(|| format!("Cannot read {}", path))?;
+    StateBackup::decode(&data).with_context(|| format!("{} is not a backup file", path))
+}
+
+fn build_client(cli: &Cli) -> Result<reqwest::Client> {
+    if !cli.addr.starts_with("https://") {
+        return Ok(reqwest::Client::new());
+    }
+
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let node_cert = NodeCertificate::load(&cli.cert, &cli.key)?;
+    let ca_cert_pem = std::fs::read_to_string(&cli.ca_cert)?;
+    let tls_config = create_client_tls_config(&node_cert, &ca_cert_pem)?;
+
+    Ok(reqwest::Client::builder()
+        .use_preconfigured_tls((*tls_config).clone())
+        .build()?)
+}
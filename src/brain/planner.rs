@@ -3,6 +3,7 @@ use crate::types::*;
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use tracing::{debug, warn};
 
 const SYSTEM_PROMPT: &str = r#"You are the planning brain for a distributed hive system called FlockMind.
@@ -15,6 +16,8 @@ IMPORTANT CONSTRAINTS:
 4. Prioritize stability and safety over speed
 5. When unsure, emit a RequestHumanApproval action
 
+The state may include "kv": shared configuration such as feature flags and deployment versions. Respect it when planning.
+
 Available action types:
 - ScheduleTask: Schedule a task on a specific node
 - RebalanceTask: Move a task from one node to another
@@ -51,6 +54,8 @@ struct PlannerInput {
     goals: Vec<GoalSummary>,
     cluster: ClusterSummary,
     attachments: Vec<AttachmentSummary>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    kv: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -105,15 +110,27 @@ struct RawAction {
 
 pub struct LlmPlanner {
     client: LlmClient,
+    kv_keys: Vec<String>,
 }
 
 impl LlmPlanner {
     pub fn new(config: LlmConfig) -> Result<Self> {
         let client = LlmClient::new(config)?;
-        Ok(Self { client })
+        Ok(Self {
+            client,
+            kv_keys: Vec::new(),
+        })
+    }
+
+    /// Includes these key-value entries in the planner input. A key ending
+    /// in `/` selects every key under it.
+    pub fn with_kv_keys(mut self, kv_keys: Vec<String>) -> Self {
+        self.kv_keys = kv_keys;
+        self
     }
 
     fn build_input(
+        &self,
         goals: &[Goal],
         cluster: &ClusterView,
         attachments: &[Attachment],
@@ -187,10 +204,22 @@ impl LlmPlanner {
             })
             .collect();
 
+        let kv = cluster
+            .kv
+            .iter()
+            .filter(|e| {
+                self.kv_keys.iter().any(|selected| {
+                    e.key == *selected || (selected.ends_with('/') && e.key.starts_with(selected))
+                })
+            })
+            .map(|e| (e.key.clone(), e.value.clone()))
+            .collect();
+
         PlannerInput {
             goals: goal_summaries,
             cluster: cluster_summary,
             attachments: attachment_summaries,
+            kv,
         }
     }
 
@@ -337,7 +366,7 @@ impl Brain for LlmPlanner {
             }]);
         }
 
-        let input = Self::build_input(goals, cluster, attachments);
+        let input = self.build_input(goals, cluster, attachments);
         let input_json = serde_json::to_string_pretty(&input)?;
 
         let user_msg = format!(
@@ -1,7 +1,10 @@
 use crate::brain::LlmConfig;
 use crate::executor::ExecutionPolicy;
+use crate::replicator::backend::{EncryptionKey, DEFAULT_HISTORY_ENTRIES};
+use crate::replicator::{BackendKind, SnapshotConfig, StorageConfig};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodeConfig {
@@ -9,13 +12,38 @@ pub struct NodeConfig {
     pub hostname: Option<String>,
     pub tags: Vec<String>,
 
+    /// Raft ID allocated to this node at enrollment.
+    #[serde(default)]
+    pub raft_id: Option<u64>,
+
     pub bind_addr: String,
     pub bind_port: u16,
 
+    #[serde(default)]
+    pub advertise_addr: Option<String>,
+
     pub data_dir: PathBuf,
 
+    #[serde(default = "default_cluster_id")]
+    pub cluster_id: String,
+
     pub peers: Vec<PeerConfig>,
 
+    /// Form a new cluster from the voters in `peers` instead of joining an
+    /// existing one. Set on each founding voter; ignored once the node holds
+    /// Raft state.
+    #[serde(default)]
+    pub bootstrap: bool,
+
+    #[serde(default)]
+    pub tls: TlsSettings,
+
+    #[serde(default)]
+    pub snapshot: SnapshotSettings,
+
+    #[serde(default)]
+    pub storage: StorageSettings,
+
     pub llm: LlmSettings,
 
     pub policy: PolicySettings,
@@ -24,11 +52,57 @@ pub struct NodeConfig {
     pub planning_interval_secs: u64,
 }
 
+/// A cluster member. An entry naming this node itself sets whether it
+/// joins as a voter or a learner.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PeerConfig {
     pub node_id: String,
     pub addr: String,
     pub is_voter: bool,
+    /// Required for the voters of a cluster formed with `bootstrap`.
+    #[serde(default)]
+    pub raft_id: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsSettings {
+    pub enabled: bool,
+    pub ca_cert: Option<PathBuf>,
+    pub node_cert: Option<PathBuf>,
+    pub node_key: Option<PathBuf>,
+    #[serde(default = "default_renew_before_hours")]
+    pub renew_before_hours: i64,
+}
+
+/// When to compact the Raft log into a state snapshot. Followers further
+/// behind than the retained log are caught up from the snapshot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotSettings {
+    pub logs_since_last: u64,
+    pub logs_to_keep: u64,
+}
+
+/// Where the Raft log and its metadata are kept under `data_dir`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageSettings {
+    #[serde(default)]
+    pub backend: BackendKind,
+    #[serde(default)]
+    pub encryption: Option<EncryptionSettings>,
+    /// Applied entries kept under `data_dir/history` for history queries;
+    /// 0 turns history off.
+    #[serde(default = "default_history_entries")]
+    pub history_entries: u64,
+}
+
+/// Encrypts the Raft log and snapshots with a key read from `key_file` or
+/// from the environment variable named by `key_env`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptionSettings {
+    #[serde(default)]
+    pub key_file: Option<PathBuf>,
+    #[serde(default)]
+    pub key_env: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -39,6 +113,10 @@ pub struct LlmSettings {
     pub model: String,
     pub max_tokens: u16,
     pub temperature: f32,
+    /// Keys from the replicated key-value namespace to show the planner. A
+    /// key ending in `/` selects every key under it.
+    #[serde(default)]
+    pub planner_kv_keys: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -57,10 +135,17 @@ impl Default for NodeConfig {
             node_id: None,
             hostname: None,
             tags: Vec::new(),
+            raft_id: None,
             bind_addr: "0.0.0.0".to_string(),
             bind_port: 9000,
+            advertise_addr: None,
             data_dir: PathBuf::from("/var/lib/flockmind"),
+            cluster_id: default_cluster_id(),
             peers: Vec::new(),
+            bootstrap: false,
+            tls: TlsSettings::default(),
+            snapshot: SnapshotSettings::default(),
+            storage: StorageSettings::default(),
             llm: LlmSettings::default(),
             policy: PolicySettings::default(),
             heartbeat_interval_secs: 10,
@@ -69,6 +154,38 @@ impl Default for NodeConfig {
     }
 }
 
+impl Default for TlsSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ca_cert: None,
+            node_cert: None,
+            node_key: None,
+            renew_before_hours: default_renew_before_hours(),
+        }
+    }
+}
+
+impl Default for SnapshotSettings {
+    fn default() -> Self {
+        let defaults = SnapshotConfig::default();
+        Self {
+            logs_since_last: defaults.logs_since_last,
+            logs_to_keep: defaults.logs_to_keep,
+        }
+    }
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        Self {
+            backend: BackendKind::default(),
+            encryption: None,
+            history_entries: default_history_entries(),
+        }
+    }
+}
+
 impl Default for LlmSettings {
     fn default() -> Self {
         Self {
@@ -78,6 +195,7 @@ impl Default for LlmSettings {
             model: "gpt-4o-mini".to_string(),
             max_tokens: 2048,
             temperature: 0.1,
+            planner_kv_keys: Vec::new(),
         }
     }
 }
@@ -115,6 +233,41 @@ impl LlmSettings {
     }
 }
 
+impl SnapshotSettings {
+    pub fn to_snapshot_config(&self) -> SnapshotConfig {
+        SnapshotConfig {
+            logs_since_last: self.logs_since_last,
+            logs_to_keep: self.logs_to_keep,
+        }
+    }
+}
+
+impl StorageSettings {
+    pub fn to_storage_config(&self) -> anyhow::Result<StorageConfig> {
+        let encryption_key = self
+            .encryption
+            .as_ref()
+            .map(EncryptionSettings::load_key)
+            .transpose()?
+            .map(Arc::new);
+        Ok(StorageConfig {
+            backend: self.backend,
+            encryption_key,
+            history_entries: self.history_entries,
+        })
+    }
+}
+
+impl EncryptionSettings {
+    pub fn load_key(&self) -> anyhow::Result<EncryptionKey> {
+        match (&self.key_file, &self.key_env) {
+            (Some(path), None) => EncryptionKey::load_file(path),
+            (None, Some(var)) => EncryptionKey::from_env(var),
+            _ => anyhow::bail!("[storage.encryption] needs exactly one of key_file and key_env"),
+        }
+    }
+}
+
 impl PolicySettings {
     pub fn to_execution_policy(&self) -> ExecutionPolicy {
         ExecutionPolicy {
@@ -158,4 +311,55 @@ impl NodeConfig {
     pub fn listen_addr(&self) -> String {
         format!("{}:{}", self.bind_addr, self.bind_port)
     }
+
+    pub fn advertise_addr(&self) -> String {
+        self.advertise_addr
+            .clone()
+            .unwrap_or_else(|| self.listen_addr())
+    }
+
+    pub fn ca_cert_path(&self) -> PathBuf {
+        self.tls
+            .ca_cert
+            .clone()
+            .unwrap_or_else(|| self.data_dir.join("ca.crt"))
+    }
+
+    pub fn ca_key_path(&self) -> PathBuf {
+        self.data_dir.join("ca.key")
+    }
+
+    pub fn intermediate_cert_path(&self) -> PathBuf {
+        self.data_dir.join("intermediate.crt")
+    }
+
+    pub fn intermediate_key_path(&self) -> PathBuf {
+        self.data_dir.join("intermediate.key")
+    }
+
+    pub fn node_cert_path(&self) -> PathBuf {
+        self.tls
+            .node_cert
+            .clone()
+            .unwrap_or_else(|| self.data_dir.join("node.crt"))
+    }
+
+    pub fn node_key_path(&self) -> PathBuf {
+        self.tls
+            .node_key
+            .clone()
+            .unwrap_or_else(|| self.data_dir.join("node.key"))
+    }
+}
+
+fn default_cluster_id() -> String {
+    "flockmind".to_string()
+}
+
+fn default_history_entries() -> u64 {
+    DEFAULT_HISTORY_ENTRIES
+}
+
+fn default_renew_before_hours() -> i64 {
+    24 * 30
 }
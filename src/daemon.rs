@@ -1,15 +1,27 @@
 use crate::attachments::AttachmentRegistry;
+use crate::auth::{
+    CaCertificate, ClusterTls, EnrollmentManager, NodeCertificate, RenewalRequest,
+    RenewalResponse,
+};
 use crate::brain::{ActionTracker, Brain, LlmPlanner, NoOpBrain};
 use crate::config::NodeConfig;
 use crate::executor::{Executor, HiveExecutor};
-use crate::replicator::{RaftReplicator, Replicator};
+use crate::raft_api::JoinRequest;
+use crate::replicator::{
+    AppliedCommand, HiveNode, HiveStorage, RaftReplicator, Replicator, SharedState, StateBackup,
+};
 use crate::types::*;
 use anyhow::Result;
 use chrono::Utc;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
-use tokio::sync::watch;
+use tokio::sync::{broadcast, watch};
 use tracing::{debug, error, info, warn};
 
+/// How often tasks waiting for a lock are retried, in case the lease they
+/// wait on expired instead of being released.
+const LOCK_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
 pub struct HiveDaemon {
     node_id: String,
     hostname: String,
@@ -19,6 +31,8 @@ pub struct HiveDaemon {
     executor: Arc<HiveExecutor<RaftReplicator>>,
     attachments: AttachmentRegistry,
     tracker: Arc<ActionTracker>,
+    tls: Option<Arc<ClusterTls>>,
+    enrollment: Option<Arc<EnrollmentManager<RaftReplicator>>>,
     config: NodeConfig,
     shutdown_tx: watch::Sender<bool>,
     shutdown_rx: watch::Receiver<bool>,
@@ -31,34 +45,37 @@ impl HiveDaemon {
 
         info!("Initializing HiveDaemon node_id={} hostname={}", node_id, hostname);
 
-        let raft_node_id: u64 = {
-            let bytes = node_id.as_bytes();
-            let mut arr = [0u8; 8];
-            for (i, b) in bytes.iter().take(8).enumerate() {
-                arr[i] = *b;
-            }
-            u64::from_le_bytes(arr)
-        };
-
         std::fs::create_dir_all(&config.data_dir)?;
-        
+
+        let raft_id = resolve_raft_id(&config)?;
+
+        let tls = load_cluster_tls(&config, &node_id, &hostname)?.map(Arc::new);
+
         let replicator = Arc::new(
             RaftReplicator::new(
-                raft_node_id,
-                config.listen_addr(),
+                raft_id,
+                config.advertise_addr(),
                 hostname.clone(),
                 &config.data_dir,
+                tls.clone(),
+                config.snapshot.to_snapshot_config(),
+                config.storage.to_storage_config()?,
             )
             .await?,
         );
 
+        let enrollment = load_enrollment_manager(&config, replicator.clone())?.map(Arc::new);
+
         let brain: Arc<dyn Brain> = if config.llm.enabled {
             let llm_config = config.llm.to_llm_config();
             if llm_config.api_key.is_empty() {
                 warn!("LLM enabled but API key is empty, using NoOpBrain");
                 Arc::new(NoOpBrain)
             } else {
-                Arc::new(LlmPlanner::new(llm_config)?)
+                Arc::new(
+                    LlmPlanner::new(llm_config)?
+                        .with_kv_keys(config.llm.planner_kv_keys.clone()),
+                )
             }
         } else {
             Arc::new(NoOpBrain)
@@ -85,23 +102,110 @@ impl HiveDaemon {
             executor,
             attachments,
             tracker,
+            tls,
+            enrollment,
             config,
             shutdown_tx,
             shutdown_rx,
         })
     }
 
+    /// Seeds this node's empty `data_dir` with `backup`, so that once
+    /// started it is the only voter of a new cluster holding that state.
+    /// A node the backup does not know is allocated the next free Raft ID,
+    /// recorded in the restored registry. Returns the Raft ID it will run as.
+    pub fn restore(config: &NodeConfig, backup: &StateBackup) -> Result<u64> {
+        std::fs::create_dir_all(&config.data_dir)?;
+
+        let node_id = config.effective_node_id();
+        let mut config = config.clone();
+        if config.raft_id.is_none() {
+            config.raft_id = Some(
+                backup
+                    .state
+                    .raft_id_of(&node_id)
+                    .unwrap_or_else(|| backup.state.next_raft_id()),
+            );
+        }
+        let raft_id = resolve_raft_id(&config)?;
+
+        let mut backup = backup.clone();
+        let assign = ClusterCommand::AssignRaftId {
+            node_id: node_id.clone(),
+            raft_id,
+        };
+        if !backup.state.apply(&assign).is_applied() {
+            anyhow::bail!(
+                "Raft ID {} belongs to another node in the backup; restore {} with its own",
+                raft_id,
+                node_id
+            );
+        }
+
+        let backend = config.storage.to_storage_config()?.open(&config.data_dir)?;
+        let store = HiveStorage::new(backend, SharedState::new())?;
+        let node = HiveNode {
+            addr: config.advertise_addr(),
+            hostname: config.effective_hostname(),
+        };
+        store.restore_backup(&backup, raft_id, node)?;
+        Ok(raft_id)
+    }
+
     pub async fn run(&self) -> Result<()> {
         info!("Starting HiveDaemon...");
 
+        for peer in &self.config.peers {
+            if let Some(raft_id) = peer.raft_id {
+                self.replicator.network().register_node(raft_id, peer.addr.clone());
+            }
+        }
+
         if self.config.peers.is_empty() {
             info!("No peers configured, initializing as single-node cluster");
-            self.replicator.initialize_single().await?;
+            self.replicator.bootstrap(BTreeMap::new()).await?;
+        } else if self.config.bootstrap {
+            self.replicator.bootstrap(self.bootstrap_voters()?).await?;
+        } else if !self.replicator.is_member() {
+            self.spawn_join_loop();
         }
 
-        self.register_self().await?;
+        if let Err(e) = self.register_self().await {
+            warn!("Failed to register node, will retry on heartbeat: {}", e);
+        }
+
+        if let Some(manager) = &self.enrollment {
+            let node_cert = self.tls.as_ref().map(|tls| tls.node_cert());
+            let cert_serial = node_cert.as_ref().and_then(|cert| cert.serial().ok());
+            let issuer_key_id = node_cert
+                .as_ref()
+                .and_then(|cert| cert.issuer_key_id().ok())
+                .flatten();
+            let mut node = manager.enrolled_node(&self.node_id).unwrap_or_else(|| EnrolledNode {
+                node_id: self.node_id.clone(),
+                hostname: self.hostname.clone(),
+                addr: self.config.advertise_addr(),
+                tags: self.tags.clone(),
+                enrolled_at: Utc::now(),
+                cert_serial: None,
+                superseded_serials: Vec::new(),
+                issuer_key_id: None,
+                cert_expiry: HashMap::new(),
+            });
+
+            if !manager.is_enrolled(&self.node_id) || node.cert_serial != cert_serial {
+                let not_after = node_cert.as_ref().and_then(|cert| cert.not_after().ok());
+                node.replace_cert(cert_serial, not_after, Utc::now());
+                node.issuer_key_id = issuer_key_id;
+                if let Err(e) = manager.record_enrollment(node).await {
+                    warn!("Failed to record own enrollment: {}", e);
+                }
+            }
+        }
 
         let heartbeat_handle = self.spawn_heartbeat_loop();
+        let trust_handle = self.spawn_trust_refresh_loop();
+        let rotation_handle = self.spawn_cert_rotation_loop();
         let task_runner_handle = self.spawn_task_runner_loop();
         let planner_handle = self.spawn_planner_loop();
 
@@ -117,6 +221,12 @@ impl HiveDaemon {
             _ = planner_handle => {
                 error!("Planner loop exited unexpectedly");
             }
+            _ = trust_handle => {
+                error!("Trust refresh loop exited unexpectedly");
+            }
+            _ = rotation_handle => {
+                error!("Certificate rotation loop exited unexpectedly");
+            }
             _ = self.wait_for_shutdown() => {
                 info!("Shutdown signal received");
             }
@@ -125,8 +235,83 @@ impl HiveDaemon {
         Ok(())
     }
 
-    async fn register_self(&self) -> Result<()> {
-        let status = NodeStatus {
+    /// Voters other than this node listed in `peers`, for bootstrapping.
+    fn bootstrap_voters(&self) -> Result<BTreeMap<u64, HiveNode>> {
+        let mut voters = BTreeMap::new();
+        for peer in &self.config.peers {
+            if !peer.is_voter || peer.node_id == self.node_id {
+                continue;
+            }
+            let raft_id = peer.raft_id.ok_or_else(|| {
+                anyhow::anyhow!("Peer {} needs a raft_id to bootstrap a cluster", peer.node_id)
+            })?;
+            voters.insert(
+                raft_id,
+                HiveNode {
+                    addr: peer.addr.clone(),
+                    hostname: peer.node_id.clone(),
+                },
+            );
+        }
+        Ok(voters)
+    }
+
+    /// Asks the configured peers in turn to add this node to the cluster,
+    /// as a voter if the node's own `peers` entry says so.
+    fn spawn_join_loop(&self) {
+        let network = self.replicator.network().clone();
+        let replicator = self.replicator.clone();
+        let targets: Vec<String> = self
+            .config
+            .peers
+            .iter()
+            .filter(|p| p.node_id != self.node_id)
+            .map(|p| p.addr.clone())
+            .collect();
+        let req = JoinRequest {
+            node_id: self.node_id.clone(),
+            addr: self.config.advertise_addr(),
+            is_voter: self
+                .config
+                .peers
+                .iter()
+                .any(|p| p.node_id == self.node_id && p.is_voter),
+            raft_id: Some(replicator.node_id()),
+        };
+        let mut shutdown_rx = self.shutdown_rx.clone();
+
+        tokio::spawn(async move {
+            let client = network.http_client();
+            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(2));
+
+            for target in targets.iter().cycle() {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = shutdown_rx.changed() => return,
+                }
+                if replicator.is_member() {
+                    return;
+                }
+
+                let url = format!("{}://{}/raft/join", network.scheme(), target);
+                match client.post(&url).json(&req).send().await {
+                    Ok(resp) if resp.status().is_success() => {
+                        info!("Joined cluster via {}", target);
+                        return;
+                    }
+                    Ok(resp) => {
+                        let status = resp.status();
+                        let body = resp.text().await.unwrap_or_default();
+                        debug!("Join via {} failed ({}): {}", target, status, body);
+                    }
+                    Err(e) => debug!("Join via {} failed: {}", target, e),
+                }
+            }
+        });
+    }
+
+    fn self_status(&self) -> NodeStatus {
+        NodeStatus {
             node_id: self.node_id.clone(),
             hostname: self.hostname.clone(),
             tags: self.tags.clone(),
@@ -135,10 +320,35 @@ impl HiveDaemon {
             cpu_usage: 0.0,
             memory_usage: 0.0,
             disk_usage: 0.0,
-        };
+            revision: 0,
+        }
+    }
+
+    async fn register_self(&self) -> Result<()> {
+        let raft_id = self.replicator.node_id();
+        let registered = self
+            .replicator
+            .shared_state()
+            .read(|state| state.raft_id_of(&self.node_id));
+        if registered != Some(raft_id) {
+            let result = self
+                .replicator
+                .apply(ClusterCommand::AssignRaftId {
+                    node_id: self.node_id.clone(),
+                    raft_id,
+                })
+                .await?;
+            if !result.is_applied() {
+                anyhow::bail!(
+                    "Raft ID {} of node {} conflicts with the cluster registry",
+                    raft_id,
+                    self.node_id
+                );
+            }
+        }
 
         self.replicator
-            .apply(ClusterCommand::RegisterNode(status))
+            .apply(ClusterCommand::RegisterNode(self.self_status()))
             .await?;
 
         info!("Registered node {} in cluster", self.node_id);
@@ -148,6 +358,7 @@ impl HiveDaemon {
     fn spawn_heartbeat_loop(&self) -> tokio::task::JoinHandle<()> {
         let replicator = self.replicator.clone();
         let node_id = self.node_id.clone();
+        let self_status = self.self_status();
         let interval = self.config.heartbeat_interval_secs;
         let mut shutdown_rx = self.shutdown_rx.clone();
 
@@ -157,18 +368,179 @@ impl HiveDaemon {
             loop {
                 tokio::select! {
                     _ = ticker.tick() => {
-                        let metrics = collect_node_metrics();
-                        if let Err(e) = replicator
-                            .apply(ClusterCommand::UpdateNodeHealth {
+                        let command = if replicator.snapshot().node_by_id(&node_id).is_none() {
+                            ClusterCommand::RegisterNode(NodeStatus {
+                                last_heartbeat: Utc::now(),
+                                ..self_status.clone()
+                            })
+                        } else {
+                            ClusterCommand::UpdateNodeHealth {
                                 node_id: node_id.clone(),
                                 health: NodeHealth::Healthy,
-                                metrics,
-                            })
-                            .await
-                        {
-                            warn!("Failed to send heartbeat: {}", e);
+                                metrics: collect_node_metrics(),
+                                expected_revision: None,
+                            }
+                        };
+
+                        match replicator.apply(command).await {
+                            Ok(CommandResult::Applied { .. }) => debug!("Heartbeat sent"),
+                            Ok(result) => {
+                                debug!("Heartbeat not applied ({:?}), re-registering", result)
+                            }
+                            Err(e) => warn!("Failed to send heartbeat: {}", e),
+                        }
+                    }
+                    _ = shutdown_rx.changed() => {
+                        break;
+                    }
+                }
+            }
+        })
+    }
+
+    /// Keeps the TLS verifiers in step with the replicated trust bundle and
+    /// CRL so that CA rollovers and revocations take effect on every node,
+    /// not just the one holding the CA. The CA holder also publishes its
+    /// trust bundle here whenever it leads and the replicated copy is stale.
+    fn spawn_trust_refresh_loop(&self) -> tokio::task::JoinHandle<()> {
+        let replicator = self.replicator.clone();
+        let enrollment = self.enrollment.clone();
+        let tls = self.tls.clone();
+        let ca_cert_path = self.config.ca_cert_path();
+        let mut shutdown_rx = self.shutdown_rx.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(2));
+
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {
+                        let Some(tls) = &tls else {
+                            continue;
+                        };
+
+                        if let Some(manager) = enrollment.as_deref().filter(|_| replicator.is_leader()) {
+                            if let Err(e) = publish_trust_bundle(&replicator, manager).await {
+                                warn!("Failed to publish trust bundle: {}", e);
+                            }
+                        }
+
+                        let bundle = replicator.shared_state().read(|state| {
+                            state
+                                .trust_bundle
+                                .as_ref()
+                                .filter(|bundle| Some(bundle.version) != tls.trust_version())
+                                .cloned()
+                        });
+                        if let Some(bundle) = bundle {
+                            let applied = tls
+                                .update_trust_bundle(&bundle)
+                                .and_then(|()| Ok(std::fs::write(&ca_cert_path, &bundle.roots_pem)?));
+                            match applied {
+                                Ok(()) => info!("Loaded trust bundle v{}", bundle.version),
+                                Err(e) => error!("Failed to load trust bundle v{}: {}", bundle.version, e),
+                            }
+                        }
+
+                        let revoked = replicator.shared_state().read(|state| {
+                            state.revoked_certificates.keys().cloned().collect()
+                        });
+                        if let Err(e) = tls.set_revoked_records(revoked) {
+                            error!("Failed to load revocation records: {}", e);
+                        }
+
+                        let crl = replicator.shared_state().read(|state| {
+                            state
+                                .crl
+                                .as_ref()
+                                .filter(|crl| Some(crl.number) != tls.crl_number())
+                                .cloned()
+                        });
+                        let Some(crl) = crl else {
+                            continue;
+                        };
+
+                        let number = crl.number;
+                        match tls.update_crl(crl) {
+                            Ok(()) => info!("Loaded certificate revocation list #{}", number),
+                            Err(e) => error!("Failed to load CRL #{}: {}", number, e),
+                        }
+
+                        if let Ok(serial) = tls.node_cert().serial() {
+                            if tls.is_revoked(&serial) {
+                                error!("This node's certificate has been revoked");
+                            }
+                        }
+                    }
+                    _ = shutdown_rx.changed() => {
+                        break;
+                    }
+                }
+            }
+        })
+    }
+
+    fn spawn_cert_rotation_loop(&self) -> tokio::task::JoinHandle<()> {
+        let replicator = self.replicator.clone();
+        let enrollment = self.enrollment.clone();
+        let tls = self.tls.clone();
+        let node_id = self.node_id.clone();
+        let cert_path = self.config.node_cert_path();
+        let key_path = self.config.node_key_path();
+        let renew_before = chrono::Duration::hours(self.config.tls.renew_before_hours);
+        let mut shutdown_rx = self.shutdown_rx.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(300));
+
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {
+                        let Some(tls) = &tls else {
+                            continue;
+                        };
+
+                        let current = tls.node_cert();
+                        let not_after = match current.not_after() {
+                            Ok(not_after) => not_after,
+                            Err(e) => {
+                                warn!("Cannot read node certificate expiry: {}", e);
+                                continue;
+                            }
+                        };
+                        let signing_key_id = replicator.shared_state().read(|state| {
+                            state.trust_bundle.as_ref().map(|b| b.signing_key_id.clone())
+                        });
+                        let reissued_by_new_ca = signing_key_id.is_some()
+                            && current.issuer_key_id().ok().flatten() != signing_key_id;
+
+                        if reissued_by_new_ca {
+                            info!("Node certificate was issued by a retiring CA, requesting renewal");
+                        } else if not_after - Utc::now() <= renew_before {
+                            info!("Node certificate expires at {}, requesting renewal", not_after);
                         } else {
-                            debug!("Heartbeat sent");
+                            continue;
+                        }
+
+                        let renewed = renew_node_certificate(
+                            &replicator,
+                            enrollment.as_deref(),
+                            tls,
+                            &node_id,
+                        )
+                        .await
+                        .and_then(|cert| {
+                            cert.save(&cert_path, &key_path)?;
+                            tls.update_node_cert(cert.clone())?;
+                            Ok(cert)
+                        });
+
+                        match renewed {
+                            Ok(cert) => info!(
+                                "Rotated node certificate, new expiry {}",
+                                cert.not_after().map(|t| t.to_rfc3339()).unwrap_or_default()
+                            ),
+                            Err(e) => warn!("Certificate renewal failed, will retry: {}", e),
                         }
                     }
                     _ = shutdown_rx.changed() => {
@@ -179,6 +551,9 @@ impl HiveDaemon {
         })
     }
 
+    /// Runs this node's pending tasks as they are written. All pending
+    /// tasks are checked on start, after the state is replaced from a
+    /// snapshot, and after falling behind the applied commands.
     fn spawn_task_runner_loop(&self) -> tokio::task::JoinHandle<()> {
         let replicator = self.replicator.clone();
         let executor = self.executor.clone();
@@ -186,33 +561,57 @@ impl HiveDaemon {
         let mut shutdown_rx = self.shutdown_rx.clone();
 
         tokio::spawn(async move {
-            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(2));
+            let mut applied = replicator.subscribe();
+            let mut restored = replicator.shared_state().watch_restores();
+            let mut lock_retry = tokio::time::interval(LOCK_RETRY_INTERVAL);
+            lock_retry.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            let executor = executor.as_ref();
+            run_pending_tasks(&replicator, executor, &node_id, |_| true).await;
 
             loop {
                 tokio::select! {
-                    _ = ticker.tick() => {
-                        let view = replicator.snapshot();
-
-                        let my_pending_tasks: Vec<_> = view
-                            .tasks
-                            .iter()
-                            .filter(|t| {
-                                t.target_node == node_id && t.status == TaskStatus::Pending
-                            })
-                            .cloned()
-                            .collect();
-
-                        for task in my_pending_tasks {
-                            info!("Executing task {}: {:?}", task.id, task.payload);
-                            match executor.run_task(&task).await {
-                                Ok(result) => {
-                                    info!("Task {} completed: {:?}", task.id, result);
+                    event = applied.recv() => match event {
+                        Ok(AppliedCommand { command, result, .. }) if result.is_applied() => {
+                            match &command {
+                                ClusterCommand::PutTask(task)
+                                | ClusterCommand::PutTaskIf { task, .. } => {
+                                    run_pending_tasks(&replicator, executor, &node_id, |t| {
+                                        t.id == task.id
+                                    })
+                                    .await
+                                }
+                                ClusterCommand::UpdateTaskStatus {
+                                    task_id,
+                                    status: TaskStatus::Pending,
+                                    ..
+                                } => {
+                                    run_pending_tasks(&replicator, executor, &node_id, |t| {
+                                        t.id == *task_id
+                                    })
+                                    .await
                                 }
-                                Err(e) => {
-                                    error!("Task {} failed: {}", task.id, e);
+                                ClusterCommand::ReleaseLock { key, .. } => {
+                                    run_pending_tasks(&replicator, executor, &node_id, |t| {
+                                        t.lock.as_ref() == Some(key)
+                                    })
+                                    .await
                                 }
+                                _ => {}
                             }
                         }
+                        Ok(_) => {}
+                        Err(broadcast::error::RecvError::Lagged(missed)) => {
+                            debug!("Task runner missed {} applied commands, rescanning", missed);
+                            run_pending_tasks(&replicator, executor, &node_id, |_| true).await;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                    _ = restored.changed() => {
+                        run_pending_tasks(&replicator, executor, &node_id, |_| true).await;
+                    }
+                    _ = lock_retry.tick() => {
+                        run_pending_tasks(&replicator, executor, &node_id, |t| t.lock.is_some())
+                            .await;
                     }
                     _ = shutdown_rx.changed() => {
                         break;
@@ -340,6 +739,215 @@ impl HiveDaemon {
     pub fn attachments(&self) -> &AttachmentRegistry {
         &self.attachments
     }
+
+    pub fn tls(&self) -> Option<&Arc<ClusterTls>> {
+        self.tls.as_ref()
+    }
+
+    pub fn enrollment(&self) -> Option<&Arc<EnrollmentManager<RaftReplicator>>> {
+        self.enrollment.as_ref()
+    }
+
+    pub fn config(&self) -> &NodeConfig {
+        &self.config
+    }
+}
+
+/// Loads the CA that signs node certificates on this node, if any: the
+/// online intermediate when one is installed, otherwise a single-tier CA
+/// whose key sits next to `ca.crt`.
+fn load_signing_ca(config: &NodeConfig) -> Result<Option<CaCertificate>> {
+    let intermediate_cert_path = config.intermediate_cert_path();
+    let intermediate_key_path = config.intermediate_key_path();
+    if intermediate_cert_path.exists() && intermediate_key_path.exists() {
+        return Ok(Some(CaCertificate::load(
+            &intermediate_cert_path,
+            &intermediate_key_path,
+        )?));
+    }
+
+    let ca_cert_path = config.ca_cert_path();
+    let ca_key_path = config.ca_key_path();
+    if ca_cert_path.exists() && ca_key_path.exists() {
+        return Ok(Some(CaCertificate::load(&ca_cert_path, &ca_key_path)?));
+    }
+
+    Ok(None)
+}
+
+fn load_enrollment_manager(
+    config: &NodeConfig,
+    replicator: Arc<RaftReplicator>,
+) -> Result<Option<EnrollmentManager<RaftReplicator>>> {
+    let Some(ca) = load_signing_ca(config)? else {
+        return Ok(None);
+    };
+
+    let trust_bundle_pem = std::fs::read_to_string(config.ca_cert_path())?;
+    info!(
+        "{} CA key present, this node can enroll new members",
+        if ca.is_root() { "Root" } else { "Intermediate" }
+    );
+    Ok(Some(
+        EnrollmentManager::new(config.cluster_id.clone(), ca, replicator)
+            .with_trust_bundle(trust_bundle_pem),
+    ))
+}
+
+/// Takes the Raft ID allocated at enrollment from the config, or from the
+/// copy kept in the data directory. Raft storage without that copy predates
+/// allocated IDs, so the node keeps the ID derived from its name that its
+/// votes, membership and log refer to; `register_self` then records it in
+/// the registry. A node bootstrapping a new cluster without one is the
+/// first member and takes 1.
+fn resolve_raft_id(config: &NodeConfig) -> Result<u64> {
+    let path = config.data_dir.join("raft_id");
+    let raft_id = match config.raft_id {
+        Some(raft_id) => raft_id,
+        None => match std::fs::read_to_string(&path) {
+            Ok(saved) => saved.trim().parse()?,
+            Err(_) if config.data_dir.join("raft").exists() => {
+                let raft_id = legacy_raft_id(&config.effective_node_id());
+                info!("Keeping Raft ID {} from existing Raft storage", raft_id);
+                raft_id
+            }
+            Err(_) if config.peers.is_empty() => 1,
+            Err(_) => anyhow::bail!(
+                "No Raft ID assigned to this node; enroll it with `flockmind join`"
+            ),
+        },
+    };
+    std::fs::write(&path, raft_id.to_string())?;
+    Ok(raft_id)
+}
+
+/// The Raft ID nodes ran as before IDs were allocated: the first eight
+/// bytes of the node ID, little-endian.
+fn legacy_raft_id(node_id: &str) -> u64 {
+    let mut bytes = [0u8; 8];
+    for (i, b) in node_id.as_bytes().iter().take(8).enumerate() {
+        bytes[i] = *b;
+    }
+    u64::from_le_bytes(bytes)
+}
+
+fn load_cluster_tls(config: &NodeConfig, node_id: &str, hostname: &str) -> Result<Option<ClusterTls>> {
+    if !config.tls.enabled {
+        warn!("TLS disabled, Raft and API traffic is unauthenticated");
+        return Ok(None);
+    }
+
+    let ca_cert_path = config.ca_cert_path();
+    let node_cert_path = config.node_cert_path();
+    let node_key_path = config.node_key_path();
+
+    if !node_cert_path.exists() || !node_key_path.exists() || !ca_cert_path.exists() {
+        if !config.peers.is_empty() {
+            anyhow::bail!(
+                "Node certificate not found at {:?}; provision ca.crt, node.crt and node.key before joining a cluster",
+                node_cert_path
+            );
+        }
+
+        let ca = match load_signing_ca(config)? {
+            Some(ca) => ca,
+            None if ca_cert_path.exists() => anyhow::bail!(
+                "{:?} exists but no CA key or intermediate was found to issue this node's certificate",
+                ca_cert_path
+            ),
+            None => {
+                info!("Generating new CA certificate for cluster {}", config.cluster_id);
+                let ca = CaCertificate::generate(&config.cluster_id)?;
+                ca.save(&ca_cert_path, &config.ca_key_path())?;
+                ca
+            }
+        };
+
+        let mut ips = vec!["127.0.0.1".to_string()];
+        if config.bind_addr != "0.0.0.0" {
+            ips.push(config.bind_addr.clone());
+        }
+        let node_cert = ca.sign_node(
+            node_id,
+            vec![hostname.to_string(), "localhost".to_string()],
+            ips,
+        )?;
+        node_cert.save(&node_cert_path, &node_key_path)?;
+        info!("Issued node certificate for {} at {:?}", node_id, node_cert_path);
+    }
+
+    let node_cert = NodeCertificate::load(&node_cert_path, &node_key_path)?;
+    let ca_cert_pem = std::fs::read_to_string(&ca_cert_path)?;
+
+    Ok(Some(ClusterTls::new(node_cert, ca_cert_pem)?))
+}
+
+async fn publish_trust_bundle(
+    replicator: &RaftReplicator,
+    manager: &EnrollmentManager<RaftReplicator>,
+) -> Result<()> {
+    let signing_key_id = manager.signing_key_id()?;
+    let current = replicator.shared_state().read(|state| state.trust_bundle.clone());
+
+    if current.as_ref().is_some_and(|bundle| {
+        bundle.roots_pem == manager.ca_cert_pem() && bundle.signing_key_id == signing_key_id
+    }) {
+        return Ok(());
+    }
+
+    let version = current.map_or(1, |bundle| bundle.version + 1);
+    replicator
+        .apply(ClusterCommand::SetTrustBundle(TrustBundle {
+            version,
+            roots_pem: manager.ca_cert_pem().to_string(),
+            signing_key_id,
+        }))
+        .await?;
+
+    info!("Published trust bundle v{}", version);
+    Ok(())
+}
+
+/// Obtains a fresh certificate with the same names as the current one.
+/// The leader signs it locally if it holds the CA; any other node asks the
+/// leader over mTLS, authenticating with the certificate being replaced.
+async fn renew_node_certificate(
+    replicator: &RaftReplicator,
+    enrollment: Option<&EnrollmentManager<RaftReplicator>>,
+    tls: &ClusterTls,
+    node_id: &str,
+) -> Result<NodeCertificate> {
+    let current = tls.node_cert();
+    let (hostnames, ips) = current.subject_alt_names()?;
+
+    if let Some(manager) = enrollment.filter(|_| replicator.is_leader()) {
+        return manager
+            .renew(node_id, &current.serial()?, hostnames, ips)
+            .await;
+    }
+
+    let leader = replicator
+        .leader_addr()
+        .ok_or_else(|| anyhow::anyhow!("No leader known"))?;
+    let resp = tls
+        .http_client()
+        .post(format!("https://{}/enroll/renew", leader))
+        .json(&RenewalRequest { hostnames, ips })
+        .send()
+        .await?;
+
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        anyhow::bail!("Renewal rejected by {} ({}): {}", leader, status, body);
+    }
+
+    let resp: RenewalResponse = resp.json().await?;
+    Ok(NodeCertificate {
+        cert_pem: resp.node_cert_pem,
+        key_pem: resp.node_key_pem,
+        node_id: node_id.to_string(),
+    })
 }
 
 fn collect_node_metrics() -> NodeMetrics {
@@ -361,3 +969,39 @@ fn is_recently_failed(action: &BrainAction, recent_failures: &[crate::brain::Tra
     use crate::brain::tracker::is_similar_action;
     recent_failures.iter().any(|f| is_similar_action(&f.action, action))
 }
+
+/// Runs the pending tasks targeted at `node_id` that `select` picks.
+/// Tasks are read from state rather than from the command that wrote them,
+/// so they carry the revision they were stored at.
+async fn run_pending_tasks(
+    replicator: &RaftReplicator,
+    executor: &impl Executor,
+    node_id: &str,
+    select: impl Fn(&Task) -> bool,
+) {
+    // Tasks whose lock is held elsewhere stay pending until it is released
+    // or expires.
+    let now = Utc::now();
+    let pending: Vec<Task> = replicator.shared_state().read(|state| {
+        state
+            .tasks
+            .values()
+            .filter(|t| t.target_node == node_id && t.status == TaskStatus::Pending)
+            .filter(|t| select(t))
+            .filter(|t| t.lock.as_deref().is_none_or(|key| state.lease(key, now).is_none()))
+            .cloned()
+            .collect()
+    });
+
+    for task in pending {
+        info!("Executing task {}: {:?}", task.id, task.payload);
+        match executor.run_task(&task).await {
+            Ok(result) => {
+                info!("Task {} completed: {:?}", task.id, result);
+            }
+            Err(e) => {
+                error!("Task {} failed: {}", task.id, e);
+            }
+        }
+    }
+}
@@ -9,6 +9,11 @@ use crate::types::*;
 use anyhow::Result;
 use async_trait::async_trait;
 use std::sync::Arc;
+use std::time::Duration;
+
+/// How long a task's lock outlives the node running it if that node dies.
+/// Running tasks renew it at a third of this.
+pub const TASK_LOCK_TTL_SECS: u64 = 30;
 
 #[async_trait]
 pub trait Executor: Send + Sync {
@@ -32,12 +37,83 @@ impl<R: Replicator + 'static> HiveExecutor<R> {
             runner: TaskRunner::new(),
         }
     }
+
+    /// Runs a task already marked running and records how it ended.
+    async fn finish_task(
+        &self,
+        task: &Task,
+        holder: &str,
+        running_revision: u64,
+    ) -> Result<serde_json::Value> {
+        let result = match &task.lock {
+            Some(key) => self.run_renewing(&task.payload, key, holder).await,
+            None => self.runner.run(&task.payload).await,
+        };
+
+        let (status, result_value) = match result {
+            Ok(value) => (TaskStatus::Completed, Some(value)),
+            Err(e) => (
+                TaskStatus::Failed {
+                    error: e.to_string(),
+                },
+                None,
+            ),
+        };
+
+        let finished = self
+            .replicator
+            .apply(ClusterCommand::UpdateTaskStatus {
+                task_id: task.id.clone(),
+                status,
+                result: result_value.clone(),
+                expected_revision: Some(running_revision),
+            })
+            .await?;
+        ensure_applied(finished, "finish task", &task.id)?;
+
+        result_value.ok_or_else(|| anyhow::anyhow!("Task failed"))
+    }
+
+    /// Runs `payload` while renewing `holder`'s lease on `key`. Losing the
+    /// lease stops the run, since another node may now hold it.
+    async fn run_renewing(
+        &self,
+        payload: &TaskPayload,
+        key: &str,
+        holder: &str,
+    ) -> Result<serde_json::Value> {
+        let run = self.runner.run(payload);
+        tokio::pin!(run);
+        let mut renew = tokio::time::interval(Duration::from_secs(TASK_LOCK_TTL_SECS / 3));
+        renew.tick().await;
+
+        loop {
+            tokio::select! {
+                result = &mut run => return result,
+                _ = renew.tick() => {
+                    let renewed = self
+                        .replicator
+                        .apply(ClusterCommand::RenewLock {
+                            key: key.to_string(),
+                            holder: holder.to_string(),
+                            ttl_secs: TASK_LOCK_TTL_SECS,
+                            requested_at: chrono::Utc::now(),
+                        })
+                        .await?;
+                    ensure_applied(renewed, "renew lock", key)?;
+                }
+            }
+        }
+    }
 }
 
 #[async_trait]
 impl<R: Replicator + 'static> Executor for HiveExecutor<R> {
     async fn execute(&self, action: BrainAction) -> Result<()> {
-        self.validator.validate(&action, &self.replicator.snapshot())?;
+        // Writes below expect the revisions in this view, so an action
+        // planned against stale state is rejected instead of clobbering it.
+        let view = self.replicator.snapshot();
+        self.validator.validate(&action, &view)?;
 
         match action {
             BrainAction::ScheduleTask {
@@ -54,40 +130,63 @@ impl<R: Replicator + 'static> Executor for HiveExecutor<R> {
                     created_at: chrono::Utc::now(),
                     updated_at: chrono::Utc::now(),
                     result: None,
+                    revision: 0,
+                    lock: None,
                 };
-                self.replicator
-                    .apply(ClusterCommand::PutTask(task))
+                let id = task.id.clone();
+                let result = self
+                    .replicator
+                    .apply(ClusterCommand::PutTaskIf {
+                        task,
+                        expected_revision: 0,
+                    })
                     .await?;
+                ensure_applied(result, "schedule task", &id)?;
             }
             BrainAction::CancelTask { task_id } => {
-                self.replicator
+                let expected_revision = view
+                    .tasks
+                    .iter()
+                    .find(|t| t.id == task_id)
+                    .map(|t| t.revision);
+                let result = self
+                    .replicator
                     .apply(ClusterCommand::UpdateTaskStatus {
-                        task_id,
+                        task_id: task_id.clone(),
                         status: TaskStatus::Cancelled,
                         result: None,
+                        expected_revision,
                     })
                     .await?;
+                ensure_applied(result, "cancel task", &task_id)?;
             }
             BrainAction::RebalanceTask { task_id, to_node } => {
-                let view = self.replicator.snapshot();
                 if let Some(task) = view.tasks.iter().find(|t| t.id == task_id) {
                     let mut new_task = task.clone();
                     new_task.target_node = to_node;
                     new_task.status = TaskStatus::Pending;
                     new_task.updated_at = chrono::Utc::now();
-                    self.replicator
-                        .apply(ClusterCommand::PutTask(new_task))
+                    let result = self
+                        .replicator
+                        .apply(ClusterCommand::PutTaskIf {
+                            task: new_task,
+                            expected_revision: task.revision,
+                        })
                         .await?;
+                    ensure_applied(result, "rebalance task", &task_id)?;
                 }
             }
             BrainAction::MarkNodeDegraded { node_id, reason } => {
-                self.replicator
+                let result = self
+                    .replicator
                     .apply(ClusterCommand::UpdateNodeHealth {
-                        node_id,
+                        node_id: node_id.clone(),
                         health: NodeHealth::Degraded { reason },
                         metrics: NodeMetrics::default(),
+                        expected_revision: None,
                     })
                     .await?;
+                ensure_applied(result, "mark degraded", &node_id)?;
             }
             BrainAction::CreateAttachment {
                 node_id,
@@ -101,15 +200,26 @@ impl<R: Replicator + 'static> Executor for HiveExecutor<R> {
                     capabilities,
                     metadata: std::collections::HashMap::new(),
                     created_at: chrono::Utc::now(),
+                    revision: 0,
                 };
-                self.replicator
-                    .apply(ClusterCommand::PutAttachment(attachment))
+                let id = attachment.id.clone();
+                let result = self
+                    .replicator
+                    .apply(ClusterCommand::PutAttachmentIf {
+                        attachment,
+                        expected_revision: 0,
+                    })
                     .await?;
+                ensure_applied(result, "create attachment", &id)?;
             }
             BrainAction::RemoveAttachment { attachment_id } => {
-                self.replicator
-                    .apply(ClusterCommand::RemoveAttachment { attachment_id })
+                let result = self
+                    .replicator
+                    .apply(ClusterCommand::RemoveAttachment {
+                        attachment_id: attachment_id.clone(),
+                    })
                     .await?;
+                ensure_applied(result, "remove attachment", &attachment_id)?;
             }
             BrainAction::UpdateGoalProgress { goal_id, .. } => {
                 tracing::info!(
@@ -145,34 +255,61 @@ impl<R: Replicator + 'static> Executor for HiveExecutor<R> {
             );
         }
 
-        self.replicator
+        // Take the lock before marking the task running, so a task that has
+        // to wait for it stays pending.
+        let holder = format!("{}/{}", self.node_id, task.id);
+        if let Some(key) = &task.lock {
+            let acquired = self
+                .replicator
+                .apply(ClusterCommand::AcquireLock {
+                    key: key.clone(),
+                    holder: holder.clone(),
+                    ttl_secs: TASK_LOCK_TTL_SECS,
+                    requested_at: chrono::Utc::now(),
+                })
+                .await?;
+            ensure_applied(acquired, "acquire lock", key)?;
+        }
+
+        let started = self
+            .replicator
             .apply(ClusterCommand::UpdateTaskStatus {
                 task_id: task.id.clone(),
                 status: TaskStatus::Running,
                 result: None,
+                expected_revision: Some(task.revision),
             })
-            .await?;
-
-        let result = self.runner.run(&task.payload).await;
-
-        let (status, result_value) = match result {
-            Ok(value) => (TaskStatus::Completed, Some(value)),
-            Err(e) => (
-                TaskStatus::Failed {
-                    error: e.to_string(),
-                },
-                None,
-            ),
+            .await;
+        let result = match started.and_then(|r| ensure_applied(r, "start task", &task.id)) {
+            Ok(running_revision) => self.finish_task(task, &holder, running_revision).await,
+            Err(e) => Err(e),
         };
 
-        self.replicator
-            .apply(ClusterCommand::UpdateTaskStatus {
-                task_id: task.id.clone(),
-                status,
-                result: result_value.clone(),
-            })
-            .await?;
+        if let Some(key) = &task.lock {
+            let released = self
+                .replicator
+                .apply(ClusterCommand::ReleaseLock {
+                    key: key.clone(),
+                    holder,
+                })
+                .await;
+            if let Err(e) = released.and_then(|r| ensure_applied(r, "release lock", key)) {
+                tracing::warn!("Task {} did not release lock: {}", task.id, e);
+            }
+        }
+        result
+    }
+}
 
-        result_value.ok_or_else(|| anyhow::anyhow!("Task failed"))
+/// Turns a write that left cluster state unchanged into an error, returning
+/// the revision the write was assigned otherwise.
+fn ensure_applied(result: CommandResult, action: &str, target: &str) -> Result<u64> {
+    match result {
+        CommandResult::Applied { revision }
+        | CommandResult::Leased(Lease { revision, .. }) => Ok(revision),
+        CommandResult::NotFound => anyhow::bail!("Cannot {}: {} not found", action, target),
+        CommandResult::Conflict => {
+            anyhow::bail!("Cannot {}: {} conflicts with current state", action, target)
+        }
     }
 }
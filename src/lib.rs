@@ -7,11 +7,12 @@ pub mod daemon;
 pub mod executor;
 pub mod raft_api;
 pub mod replicator;
+pub mod server;
 pub mod types;
 
 pub use api::create_router;
 pub use attachments::AttachmentRegistry;
-pub use auth::{CaCertificate, EnrollmentManager, NodeCertificate};
+pub use auth::{CaCertificate, ClusterTls, EnrollmentManager, NodeCertificate};
 pub use brain::{Brain, LlmPlanner, NoOpBrain};
 pub use config::NodeConfig;
 pub use daemon::HiveDaemon;
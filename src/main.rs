@@ -1,6 +1,10 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
-use flockmind::{create_raft_router, create_router, HiveDaemon, NodeConfig};
+use flockmind::auth::{CaCertificate, ClusterServerVerifier, EnrollmentRequest, EnrollmentResponse, NodeCertificate};
+use flockmind::config::PeerConfig;
+use flockmind::replicator::backend::{open_backend, rotate_key, EncryptionKey};
+use flockmind::replicator::StateBackup;
+use flockmind::{create_raft_router, create_router, server, HiveDaemon, NodeConfig};
 use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::net::TcpListener;
@@ -25,6 +29,87 @@ enum Commands {
         #[arg(short, long, default_value = "flockmind.toml")]
         config: PathBuf,
     },
+    Join {
+        #[arg(short, long, default_value = "flockmind.toml")]
+        config: PathBuf,
+
+        #[arg(long)]
+        token: String,
+
+        /// Address (host:port) of the leader that holds the cluster CA
+        #[arg(long)]
+        leader: String,
+
+        /// Cluster CA used to verify the leader before the token is sent to
+        /// it, e.g. a copy of `ca.crt` from the leader's data_dir
+        #[arg(long)]
+        ca_cert: PathBuf,
+
+        #[arg(long)]
+        advertise_addr: Option<String>,
+    },
+
+    /// Manage a two-tier CA: an offline root and an online intermediate
+    #[command(subcommand)]
+    Ca(CaCommands),
+
+    /// Seed an empty data_dir from `flockctl backup` output; the node then
+    /// starts as the only voter of a new cluster
+    Restore {
+        #[arg(short, long, default_value = "flockmind.toml")]
+        config: PathBuf,
+
+        #[arg(long)]
+        from: PathBuf,
+    },
+
+    /// Manage the key that encrypts Raft storage
+    #[command(subcommand)]
+    Storage(StorageCommands),
+}
+
+#[derive(Subcommand)]
+enum CaCommands {
+    /// Create a root CA; keep root.key off the cluster
+    InitRoot {
+        #[arg(long, default_value = "flockmind")]
+        cluster_id: String,
+
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Sign an intermediate with the root and write it for a node's data_dir
+    IssueIntermediate {
+        #[arg(long, default_value = "flockmind")]
+        cluster_id: String,
+
+        #[arg(long)]
+        root_cert: PathBuf,
+
+        #[arg(long)]
+        root_key: PathBuf,
+
+        #[arg(long)]
+        out: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum StorageCommands {
+    /// Write a new random storage encryption key
+    GenKey {
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Re-encrypt this node's Raft storage under a new key; the daemon must
+    /// be stopped. Unencrypted storage is encrypted for the first time.
+    RotateKey {
+        #[arg(short, long, default_value = "flockmind.toml")]
+        config: PathBuf,
+
+        #[arg(long)]
+        new_key_file: PathBuf,
+    },
 }
 
 #[tokio::main]
@@ -37,6 +122,8 @@ async fn main() -> Result<()> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
     let cli = Cli::parse();
 
     match cli.command {
@@ -46,32 +133,97 @@ async fn main() -> Result<()> {
         Commands::Init { config: config_path } => {
             init_config(config_path)?;
         }
+        Commands::Join {
+            config: config_path,
+            token,
+            leader,
+            ca_cert,
+            advertise_addr,
+        } => {
+            join_cluster(config_path, token, leader, ca_cert, advertise_addr).await?;
+        }
+        Commands::Restore {
+            config: config_path,
+            from,
+        } => {
+            restore_state(config_path, from)?;
+        }
+        Commands::Ca(cmd) => match cmd {
+            CaCommands::InitRoot { cluster_id, out } => {
+                std::fs::create_dir_all(&out)?;
+                let root = CaCertificate::generate_root(&cluster_id)?;
+                root.save(out.join("root.crt"), out.join("root.key"))?;
+                info!("Wrote root CA to {:?}; move root.key to offline storage", out);
+            }
+            CaCommands::IssueIntermediate {
+                cluster_id,
+                root_cert,
+                root_key,
+                out,
+            } => {
+                std::fs::create_dir_all(&out)?;
+                let root = CaCertificate::load(&root_cert, &root_key)?;
+                let intermediate = root.issue_intermediate(&cluster_id)?;
+                intermediate.save(out.join("intermediate.crt"), out.join("intermediate.key"))?;
+                info!(
+                    "Wrote intermediate CA to {:?}; make sure ca.crt on every node trusts {:?}",
+                    out, root_cert
+                );
+            }
+        },
+        Commands::Storage(cmd) => match cmd {
+            StorageCommands::GenKey { out } => {
+                if out.exists() {
+                    anyhow::bail!("Key file already exists: {:?}", out);
+                }
+                std::fs::write(&out, EncryptionKey::generate_hex()?)?;
+                info!("Wrote storage key to {:?}", out);
+            }
+            StorageCommands::RotateKey {
+                config: config_path,
+                new_key_file,
+            } => {
+                rotate_storage_key(config_path, new_key_file)?;
+            }
+        },
     }
 
     Ok(())
 }
 
-async fn run_daemon(config_path: PathBuf) -> Result<()> {
-    let config = if config_path.exists() {
+fn load_config(config_path: &PathBuf) -> Result<NodeConfig> {
+    if config_path.exists() {
         info!("Loading config from {:?}", config_path);
-        NodeConfig::load(&config_path)?
+        NodeConfig::load(config_path)
     } else {
         info!("Config file not found, using defaults");
-        NodeConfig::default()
-    };
+        Ok(NodeConfig::default())
+    }
+}
+
+async fn run_daemon(config_path: PathBuf) -> Result<()> {
+    let config = load_config(&config_path)?;
+    start_daemon(config).await
+}
 
+async fn start_daemon(config: NodeConfig) -> Result<()> {
     let daemon = Arc::new(HiveDaemon::new(config.clone()).await?);
 
     let api_router = create_router(daemon.clone());
     let raft_router = create_raft_router(daemon.replicator().clone());
     let router = api_router.merge(raft_router);
-    
+
     let listener = TcpListener::bind(&config.listen_addr()).await?;
-    info!("API server listening on {}", config.listen_addr());
+    let tls = daemon.tls().cloned();
+    info!(
+        "API server listening on {} ({})",
+        config.listen_addr(),
+        if tls.is_some() { "mTLS" } else { "plaintext" }
+    );
 
     let daemon_clone = daemon.clone();
     let api_handle = tokio::spawn(async move {
-        if let Err(e) = axum::serve(listener, router).await {
+        if let Err(e) = server::serve(listener, router, tls).await {
             error!("API server error: {}", e);
         }
     });
@@ -98,6 +250,56 @@ async fn run_daemon(config_path: PathBuf) -> Result<()> {
     Ok(())
 }
 
+fn restore_state(config_path: PathBuf, from: PathBuf) -> Result<()> {
+    let config = load_config(&config_path)?;
+    let data = std::fs::read(&from)?;
+    let backup = StateBackup::decode(&data)
+        .map_err(|e| anyhow::anyhow!("{:?} is not a backup: {}", from, e))?;
+
+    let raft_id = HiveDaemon::restore(&config, &backup)?;
+    info!(
+        "Restored state as of log index {} into {:?} as Raft node {}",
+        backup.last_log_id.map(|id| id.index).unwrap_or(0),
+        config.data_dir,
+        raft_id
+    );
+    println!("Start the restored node with `flockmind run`.");
+    Ok(())
+}
+
+fn rotate_storage_key(config_path: PathBuf, new_key_file: PathBuf) -> Result<()> {
+    let config = load_config(&config_path)?;
+    let old_key = config
+        .storage
+        .encryption
+        .as_ref()
+        .map(|encryption| encryption.load_key())
+        .transpose()?;
+    let new_key = EncryptionKey::load_file(&new_key_file)?;
+
+    // History is kept under the same key as the Raft log.
+    let history_dir = config.data_dir.join("history");
+    let dirs = std::iter::once(config.data_dir.clone())
+        .chain(history_dir.exists().then_some(history_dir));
+    for dir in dirs {
+        let backend = open_backend(config.storage.backend, &dir)?;
+        let summary = rotate_key(backend.as_ref(), old_key.as_ref(), &new_key)
+            .with_context(|| format!("Cannot rotate the key of {:?}", dir))?;
+        info!(
+            "Re-encrypted {} log entries and {} metadata records in {:?} under key {}",
+            summary.log_entries,
+            summary.meta_records,
+            dir,
+            new_key.id()
+        );
+    }
+    println!(
+        "Point [storage.encryption] in {:?} at {:?} before starting the daemon.",
+        config_path, new_key_file
+    );
+    Ok(())
+}
+
 fn init_config(config_path: PathBuf) -> Result<()> {
     if config_path.exists() {
         anyhow::bail!("Config file already exists: {:?}", config_path);
@@ -114,3 +316,108 @@ fn init_config(config_path: PathBuf) -> Result<()> {
 
     Ok(())
 }
+
+async fn join_cluster(
+    config_path: PathBuf,
+    token: String,
+    leader: String,
+    ca_cert: PathBuf,
+    advertise_addr: Option<String>,
+) -> Result<()> {
+    let mut config = load_config(&config_path)?;
+    if config.node_id.is_none() {
+        config.node_id = Some(config.effective_node_id());
+    }
+    if advertise_addr.is_some() {
+        config.advertise_addr = advertise_addr;
+    }
+
+    if config.node_cert_path().exists() {
+        anyhow::bail!(
+            "Node certificate already exists at {:?}, this node is already enrolled",
+            config.node_cert_path()
+        );
+    }
+
+    std::fs::create_dir_all(&config.data_dir)?;
+
+    let node_id = config.effective_node_id();
+    let hostname = config.effective_hostname();
+    let addr = config.advertise_addr();
+
+    let mut ips = vec!["127.0.0.1".to_string()];
+    if let Ok(sock) = addr.parse::<std::net::SocketAddr>() {
+        ips.push(sock.ip().to_string());
+    }
+
+    let req = EnrollmentRequest {
+        token,
+        node_id: node_id.clone(),
+        hostname: hostname.clone(),
+        addr,
+        hostnames: vec![hostname, "localhost".to_string()],
+        ips,
+        tags: config.tags.clone(),
+    };
+
+    // The token is a bearer secret: only hand it to a leader that proves
+    // it belongs to the cluster.
+    if !config.tls.enabled {
+        anyhow::bail!("Joining sends the enrollment token to the leader and needs tls.enabled");
+    }
+    let client = enrollment_client(&ca_cert)?;
+    let resp = client
+        .post(format!("https://{}/enroll", leader))
+        .json(&req)
+        .send()
+        .await?;
+
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        anyhow::bail!("Enrollment rejected ({}): {}", status, body);
+    }
+
+    let resp: EnrollmentResponse = resp.json().await?;
+    info!("Enrolled as {} in cluster {}", resp.node_id, resp.cluster_id);
+
+    std::fs::write(config.ca_cert_path(), &resp.ca_cert_pem)?;
+    NodeCertificate {
+        cert_pem: resp.node_cert_pem,
+        key_pem: resp.node_key_pem,
+        node_id: resp.node_id,
+    }
+    .save(config.node_cert_path(), config.node_key_path())?;
+
+    config.cluster_id = resp.cluster_id;
+    config.raft_id = Some(resp.raft_id);
+    config.peers = resp
+        .peers
+        .into_iter()
+        .filter(|p| p.node_id != node_id)
+        .map(|p| PeerConfig {
+            node_id: p.node_id,
+            addr: p.addr,
+            is_voter: false,
+            raft_id: None,
+        })
+        .collect();
+    config.save(&config_path)?;
+    info!("Saved enrollment material to {:?} and config to {:?}", config.data_dir, config_path);
+
+    start_daemon(config).await
+}
+
+fn enrollment_client(ca_cert: &PathBuf) -> Result<reqwest::Client> {
+    let ca_cert_pem = std::fs::read_to_string(ca_cert)
+        .with_context(|| format!("Cannot read cluster CA {:?}", ca_cert))?;
+    let verifier = ClusterServerVerifier::new(&ca_cert_pem)?;
+    let tls_config = rustls::ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+
+    Ok(reqwest::Client::builder()
+        .use_preconfigured_tls(tls_config)
+        .build()?)
+}
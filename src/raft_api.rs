@@ -1,5 +1,7 @@
 use crate::replicator::storage::TypeConfig;
-use crate::replicator::RaftReplicator;
+use crate::replicator::{RaftReplicator, ReadIndexResponse, Replicator};
+use crate::server::ClientIdentity;
+use crate::types::{ClusterCommand, PeerInfo};
 use axum::{
     extract::State,
     http::StatusCode,
@@ -11,6 +13,7 @@ use openraft::raft::{
     AppendEntriesRequest, InstallSnapshotRequest, InstallSnapshotResponse,
     VoteRequest,
 };
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
 pub fn create_raft_router(replicator: Arc<RaftReplicator>) -> Router {
@@ -18,6 +21,10 @@ pub fn create_raft_router(replicator: Arc<RaftReplicator>) -> Router {
         .route("/raft/vote", post(handle_vote))
         .route("/raft/append_entries", post(handle_append_entries))
         .route("/raft/install_snapshot", post(handle_install_snapshot))
+        .route("/raft/join", post(handle_join))
+        .route("/raft/forward", post(handle_forward))
+        .route("/raft/read_index", post(handle_read_index))
+        .route("/raft/campaign", post(handle_campaign))
         .with_state(replicator)
 }
 
@@ -63,3 +70,128 @@ async fn handle_install_snapshot(
             .into_response(),
     }
 }
+
+/// Accepts a write forwarded by a follower. Answers 421 when this node is
+/// not leader so the sender can look the leader up again.
+async fn handle_forward(
+    State(replicator): State<Arc<RaftReplicator>>,
+    Json(command): Json<ClusterCommand>,
+) -> impl IntoResponse {
+    match replicator.raft().client_write(command).await {
+        Ok(response) => (StatusCode::OK, Json(response.data)).into_response(),
+        Err(e) => {
+            let status = if e.forward_to_leader().is_some() {
+                StatusCode::MISDIRECTED_REQUEST
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            (status, Json(serde_json::json!({ "error": e.to_string() }))).into_response()
+        }
+    }
+}
+
+/// Serves the read index to a follower performing a linearizable read.
+async fn handle_read_index(State(replicator): State<Arc<RaftReplicator>>) -> impl IntoResponse {
+    match replicator.raft().ensure_linearizable().await {
+        Ok(log_id) => (
+            StatusCode::OK,
+            Json(ReadIndexResponse {
+                index: log_id.map(|id| id.index),
+            }),
+        )
+            .into_response(),
+        Err(e) => {
+            let status = if e.forward_to_leader().is_some() {
+                StatusCode::MISDIRECTED_REQUEST
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            (status, Json(serde_json::json!({ "error": e.to_string() }))).into_response()
+        }
+    }
+}
+
+/// Starts an election on this node, sent by a leader handing over
+/// leadership.
+async fn handle_campaign(State(replicator): State<Arc<RaftReplicator>>) -> impl IntoResponse {
+    match replicator.raft().trigger().elect().await {
+        Ok(()) => (StatusCode::OK, Json(serde_json::json!({}))).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JoinRequest {
+    pub node_id: String,
+    pub addr: String,
+    #[serde(default)]
+    pub is_voter: bool,
+    /// Raft ID from the node's config, recorded if the registry has none
+    /// for it yet. Enrolled nodes already have one allocated.
+    #[serde(default)]
+    pub raft_id: Option<u64>,
+}
+
+async fn handle_join(
+    State(replicator): State<Arc<RaftReplicator>>,
+    identity: Option<axum::Extension<ClientIdentity>>,
+    Json(req): Json<JoinRequest>,
+) -> impl IntoResponse {
+    if let Some(axum::Extension(identity)) = identity {
+        if identity.node_id != req.node_id {
+            return (
+                StatusCode::FORBIDDEN,
+                Json(serde_json::json!({
+                    "error": format!("Certificate for {} cannot join as {}", identity.node_id, req.node_id)
+                })),
+            )
+                .into_response();
+        }
+    }
+
+    if let Some(raft_id) = req.raft_id {
+        let registered = replicator
+            .shared_state()
+            .read(|state| state.raft_id_of(&req.node_id));
+        if registered.is_none() {
+            let result = replicator
+                .apply(ClusterCommand::AssignRaftId {
+                    node_id: req.node_id.clone(),
+                    raft_id,
+                })
+                .await;
+            let error = match result {
+                Ok(result) if result.is_applied() => None,
+                Ok(_) => Some(format!("Raft ID {} is already taken", raft_id)),
+                Err(e) => Some(e.to_string()),
+            };
+            if let Some(error) = error {
+                return (StatusCode::CONFLICT, Json(serde_json::json!({ "error": error })))
+                    .into_response();
+            }
+        }
+    }
+
+    let peer = PeerInfo {
+        node_id: req.node_id.clone(),
+        addr: req.addr,
+        is_voter: req.is_voter,
+    };
+    let role = if peer.is_voter { "voter" } else { "learner" };
+
+    match replicator.add_peer(peer).await {
+        Ok(()) => {
+            tracing::info!("Added {} as {}", req.node_id, role);
+            (StatusCode::OK, Json(serde_json::json!({ "joined": req.node_id }))).into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
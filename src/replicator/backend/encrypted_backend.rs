@@ -0,0 +1,305 @@
+use super::{MetaBatch, StorageBackend};
+use anyhow::{anyhow, Context, Result};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::path::Path;
+use std::sync::Arc;
+
+const MAGIC: &[u8; 4] = b"FLKE";
+const KEY_ID_LEN: usize = 4;
+const HEADER_LEN: usize = MAGIC.len() + KEY_ID_LEN + NONCE_LEN;
+const KEY_LEN: usize = 32;
+
+#[derive(Debug, thiserror::Error)]
+pub enum EncryptionError {
+    #[error("record is not encrypted; run `flockmind storage rotate-key` to encrypt it")]
+    NotEncrypted,
+    #[error("record was encrypted with key {record}, not the configured key {configured}")]
+    WrongKey { record: String, configured: String },
+    #[error("record failed authentication; it was modified or moved")]
+    Tampered,
+}
+
+impl From<EncryptionError> for std::io::Error {
+    fn from(e: EncryptionError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// A 256-bit AES-GCM key for Raft storage, stored as 64 hex characters.
+/// Records carry a short id derived from the key, so data written under
+/// another key is reported as such rather than as corruption.
+pub struct EncryptionKey {
+    key: LessSafeKey,
+    id: [u8; KEY_ID_LEN],
+}
+
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptionKey").field("id", &self.id()).finish()
+    }
+}
+
+impl EncryptionKey {
+    pub fn from_hex(text: &str) -> Result<Self> {
+        let bytes = key_from_hex(text.trim())?;
+        let digest = ring::digest::digest(&ring::digest::SHA256, &bytes);
+        let key = UnboundKey::new(&AES_256_GCM, &bytes)
+            .map_err(|_| anyhow!("Invalid storage encryption key"))?;
+        Ok(Self {
+            key: LessSafeKey::new(key),
+            id: digest.as_ref()[..KEY_ID_LEN].try_into().unwrap(),
+        })
+    }
+
+    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read storage key {:?}", path))?;
+        Self::from_hex(&text).with_context(|| format!("Invalid storage key in {:?}", path))
+    }
+
+    pub fn from_env(var: &str) -> Result<Self> {
+        let text = std::env::var(var).with_context(|| format!("{} is not set", var))?;
+        Self::from_hex(&text).with_context(|| format!("Invalid storage key in {}", var))
+    }
+
+    /// A new random key in the form `from_hex` reads.
+    pub fn generate_hex() -> Result<String> {
+        let mut bytes = [0u8; KEY_LEN];
+        SystemRandom::new()
+            .fill(&mut bytes)
+            .map_err(|_| anyhow!("Cannot generate a storage key"))?;
+        Ok(to_hex(&bytes))
+    }
+
+    pub fn id(&self) -> String {
+        to_hex(&self.id)
+    }
+
+    /// `aad` ties the record to where it is stored, so records cannot be
+    /// swapped between log indexes or metadata keys.
+    fn seal(&self, aad: &[u8], plaintext: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| std::io::Error::other("Cannot generate a nonce"))?;
+
+        let mut record = Vec::with_capacity(HEADER_LEN + plaintext.len() + AES_256_GCM.tag_len());
+        record.extend_from_slice(MAGIC);
+        record.extend_from_slice(&self.id);
+        record.extend_from_slice(&nonce);
+        let mut body = plaintext.to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(aad),
+                &mut body,
+            )
+            .map_err(|_| std::io::Error::other("Cannot encrypt record"))?;
+        record.extend_from_slice(&body);
+        Ok(record)
+    }
+
+    fn open(&self, aad: &[u8], record: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        let record_id = key_id(record).ok_or(EncryptionError::NotEncrypted)?;
+        if record_id != self.id {
+            return Err(EncryptionError::WrongKey {
+                record: to_hex(&record_id),
+                configured: self.id(),
+            });
+        }
+
+        let nonce = &record[MAGIC.len() + KEY_ID_LEN..HEADER_LEN];
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| EncryptionError::Tampered)?;
+        let mut body = record[HEADER_LEN..].to_vec();
+        let plaintext = self
+            .key
+            .open_in_place(nonce, Aad::from(aad), &mut body)
+            .map_err(|_| EncryptionError::Tampered)?;
+        Ok(plaintext.to_vec())
+    }
+}
+
+/// Whether `record` was written by an `EncryptedBackend`.
+pub fn is_encrypted(record: &[u8]) -> bool {
+    key_id(record).is_some()
+}
+
+fn key_id(record: &[u8]) -> Option<[u8; KEY_ID_LEN]> {
+    if record.len() < HEADER_LEN || !record.starts_with(MAGIC) {
+        return None;
+    }
+    record[MAGIC.len()..MAGIC.len() + KEY_ID_LEN].try_into().ok()
+}
+
+fn log_aad(index: u64) -> Vec<u8> {
+    [b"log:".as_slice(), &index.to_be_bytes()].concat()
+}
+
+fn meta_aad(key: &[u8]) -> Vec<u8> {
+    [b"meta:".as_slice(), key].concat()
+}
+
+/// Encrypts every record on its way into `inner` and authenticates it on
+/// the way out. Log indexes and metadata key names stay readable.
+pub struct EncryptedBackend {
+    inner: Arc<dyn StorageBackend>,
+    key: Arc<EncryptionKey>,
+}
+
+impl EncryptedBackend {
+    pub fn new(inner: Arc<dyn StorageBackend>, key: Arc<EncryptionKey>) -> Self {
+        Self { inner, key }
+    }
+
+    fn open_log(&self, (index, record): (u64, Vec<u8>)) -> std::io::Result<(u64, Vec<u8>)> {
+        let plaintext = self.key.open(&log_aad(index), &record).map_err(|e| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, format!("log entry {}: {}", index, e))
+        })?;
+        Ok((index, plaintext))
+    }
+}
+
+impl StorageBackend for EncryptedBackend {
+    fn append_log(&self, records: Vec<(u64, Vec<u8>)>) -> std::io::Result<()> {
+        let records = records
+            .into_iter()
+            .map(|(index, record)| Ok((index, self.key.seal(&log_aad(index), &record)?)))
+            .collect::<std::io::Result<_>>()?;
+        self.inner.append_log(records)
+    }
+
+    fn read_log(&self, start: u64, end: Option<u64>) -> std::io::Result<Vec<(u64, Vec<u8>)>> {
+        self.inner
+            .read_log(start, end)?
+            .into_iter()
+            .map(|record| self.open_log(record))
+            .collect()
+    }
+
+    fn last_log(&self) -> std::io::Result<Option<(u64, Vec<u8>)>> {
+        self.inner
+            .last_log()?
+            .map(|record| self.open_log(record))
+            .transpose()
+    }
+
+    fn truncate_log(&self, index: u64) -> std::io::Result<()> {
+        self.inner.truncate_log(index)
+    }
+
+    fn purge_log(&self, index: u64) -> std::io::Result<()> {
+        self.inner.purge_log(index)
+    }
+
+    fn get_meta(&self, key: &[u8]) -> std::io::Result<Option<Vec<u8>>> {
+        let Some(record) = self.inner.get_meta(key)? else {
+            return Ok(None);
+        };
+        let plaintext = self.key.open(&meta_aad(key), &record).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{}: {}", String::from_utf8_lossy(key), e),
+            )
+        })?;
+        Ok(Some(plaintext))
+    }
+
+    fn meta_keys(&self) -> std::io::Result<Vec<Vec<u8>>> {
+        self.inner.meta_keys()
+    }
+
+    fn write_meta(&self, batch: MetaBatch, durable: bool) -> std::io::Result<()> {
+        let batch = batch
+            .into_iter()
+            .map(|(key, value)| {
+                let value = match value {
+                    Some(value) => Some(self.key.seal(&meta_aad(&key), &value)?),
+                    None => None,
+                };
+                Ok((key, value))
+            })
+            .collect::<std::io::Result<_>>()?;
+        self.inner.write_meta(batch, durable)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RotationSummary {
+    pub log_entries: usize,
+    pub meta_records: usize,
+}
+
+/// Re-encrypts every record in `inner` under `new`. Records are read with
+/// `old`, or taken as plaintext when `old` is `None`; records already under
+/// `new` are left alone, so an interrupted rotation can be run again.
+pub fn rotate_key(
+    inner: &dyn StorageBackend,
+    old: Option<&EncryptionKey>,
+    new: &EncryptionKey,
+) -> Result<RotationSummary> {
+    let decrypt = |aad: &[u8], record: &[u8]| -> Result<Option<Vec<u8>>> {
+        if key_id(record) == Some(new.id) {
+            return Ok(None);
+        }
+        match old {
+            Some(old) => Ok(Some(old.open(aad, record)?)),
+            None if is_encrypted(record) => Err(anyhow!(
+                "Storage is encrypted with key {}; configure it to rotate away from it",
+                to_hex(&key_id(record).unwrap())
+            )),
+            None => Ok(Some(record.to_vec())),
+        }
+    };
+
+    let mut log = Vec::new();
+    for (index, record) in inner.read_log(0, None)? {
+        let aad = log_aad(index);
+        if let Some(plaintext) = decrypt(&aad, &record).with_context(|| format!("log entry {}", index))? {
+            log.push((index, new.seal(&aad, &plaintext)?));
+        }
+    }
+
+    let mut meta = Vec::new();
+    for key in inner.meta_keys()? {
+        let Some(record) = inner.get_meta(&key)? else {
+            continue;
+        };
+        let aad = meta_aad(&key);
+        let plaintext = decrypt(&aad, &record)
+            .with_context(|| format!("{}", String::from_utf8_lossy(&key)))?;
+        if let Some(plaintext) = plaintext {
+            meta.push((key, Some(new.seal(&aad, &plaintext)?)));
+        }
+    }
+
+    let summary = RotationSummary {
+        log_entries: log.len(),
+        meta_records: meta.len(),
+    };
+    if !log.is_empty() {
+        inner.append_log(log)?;
+    }
+    if !meta.is_empty() {
+        inner.write_meta(meta, true)?;
+    }
+    Ok(summary)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn key_from_hex(text: &str) -> Result<Vec<u8>> {
+    if text.len() != KEY_LEN * 2 || !text.is_ascii() {
+        return Err(anyhow!("Expected {} hex characters", KEY_LEN * 2));
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&text[i..i + 2], 16)
+                .map_err(|_| anyhow!("Expected {} hex characters", KEY_LEN * 2))
+        })
+        .collect()
+}
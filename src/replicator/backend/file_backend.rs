@@ -0,0 +1,270 @@
+use super::{MetaBatch, StorageBackend};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Journals are rewritten with only their live records once they are this
+/// large and more than half of what they hold has been superseded.
+const COMPACT_MIN_BYTES: u64 = 4 * 1024 * 1024;
+const FRAME_HEADER_LEN: usize = 8;
+
+/// Raft log and metadata in two append-only journal files. Each write
+/// appends one checksummed frame; the current contents are kept in memory
+/// and rebuilt by replaying the journal on open. The log is bounded by the
+/// snapshot policy, so it stays small enough to hold.
+pub struct FileBackend {
+    log: Mutex<Journal>,
+    meta: Mutex<Journal>,
+}
+
+impl FileBackend {
+    pub fn open<P: AsRef<Path>>(dir: P) -> anyhow::Result<Self> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        Ok(Self {
+            log: Mutex::new(Journal::open(dir.join("log.journal"))?),
+            meta: Mutex::new(Journal::open(dir.join("meta.journal"))?),
+        })
+    }
+
+    fn log_key(index: u64) -> Vec<u8> {
+        index.to_be_bytes().to_vec()
+    }
+
+    fn log_record((key, record): (&Vec<u8>, &Vec<u8>)) -> (u64, Vec<u8>) {
+        let index = key
+            .as_slice()
+            .try_into()
+            .map(u64::from_be_bytes)
+            .unwrap_or_default();
+        (index, record.clone())
+    }
+}
+
+impl StorageBackend for FileBackend {
+    fn append_log(&self, records: Vec<(u64, Vec<u8>)>) -> std::io::Result<()> {
+        let ops = records
+            .into_iter()
+            .map(|(index, record)| JournalOp::Put(Self::log_key(index), record))
+            .collect();
+        self.log.lock().unwrap().write(ops, true)
+    }
+
+    fn read_log(&self, start: u64, end: Option<u64>) -> std::io::Result<Vec<(u64, Vec<u8>)>> {
+        let log = self.log.lock().unwrap();
+        let records = match end {
+            Some(end) => log
+                .live
+                .range(Self::log_key(start)..Self::log_key(end))
+                .map(Self::log_record)
+                .collect(),
+            None => log
+                .live
+                .range(Self::log_key(start)..)
+                .map(Self::log_record)
+                .collect(),
+        };
+        Ok(records)
+    }
+
+    fn last_log(&self) -> std::io::Result<Option<(u64, Vec<u8>)>> {
+        let log = self.log.lock().unwrap();
+        Ok(log.live.last_key_value().map(Self::log_record))
+    }
+
+    fn truncate_log(&self, index: u64) -> std::io::Result<()> {
+        let op = JournalOp::RemoveFrom(Self::log_key(index));
+        self.log.lock().unwrap().write(vec![op], true)
+    }
+
+    fn purge_log(&self, index: u64) -> std::io::Result<()> {
+        let op = JournalOp::RemoveUpTo(Self::log_key(index));
+        self.log.lock().unwrap().write(vec![op], true)
+    }
+
+    fn get_meta(&self, key: &[u8]) -> std::io::Result<Option<Vec<u8>>> {
+        Ok(self.meta.lock().unwrap().live.get(key).cloned())
+    }
+
+    fn meta_keys(&self) -> std::io::Result<Vec<Vec<u8>>> {
+        Ok(self.meta.lock().unwrap().live.keys().cloned().collect())
+    }
+
+    fn write_meta(&self, batch: MetaBatch, durable: bool) -> std::io::Result<()> {
+        let ops = batch
+            .into_iter()
+            .map(|(key, value)| match value {
+                Some(value) => JournalOp::Put(key, value),
+                None => JournalOp::Remove(key),
+            })
+            .collect();
+        self.meta.lock().unwrap().write(ops, durable)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+enum JournalOp {
+    Put(Vec<u8>, Vec<u8>),
+    Remove(Vec<u8>),
+    RemoveFrom(Vec<u8>),
+    RemoveUpTo(Vec<u8>),
+}
+
+enum Frame {
+    Whole(Vec<JournalOp>, usize),
+    /// Fewer bytes than the header, or than the length it declares.
+    Short,
+    Corrupt,
+}
+
+/// One append-only file. A frame is `[len: u32][crc32: u32][ops]`, with
+/// the ops of one write encoded together so they apply all or nothing.
+struct Journal {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    live: BTreeMap<Vec<u8>, Vec<u8>>,
+    live_bytes: u64,
+}
+
+impl Journal {
+    fn open(path: PathBuf) -> std::io::Result<Self> {
+        let mut contents = Vec::new();
+        if path.exists() {
+            File::open(&path)?.read_to_end(&mut contents)?;
+        }
+
+        let mut journal = Self {
+            file: OpenOptions::new().create(true).append(true).open(&path)?,
+            path,
+            len: 0,
+            live: BTreeMap::new(),
+            live_bytes: 0,
+        };
+
+        let mut offset = 0;
+        loop {
+            match Self::read_frame(&contents[offset..]) {
+                Frame::Whole(ops, frame_len) => {
+                    for op in ops {
+                        journal.apply(op);
+                    }
+                    offset += frame_len;
+                }
+                Frame::Short => break,
+                // A whole frame that does not check out is damage, not an
+                // interrupted write; dropping it and everything after it
+                // would silently lose committed records.
+                Frame::Corrupt => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("{} is corrupt at byte {}", journal.path.display(), offset),
+                    ))
+                }
+            }
+        }
+
+        // Only the last frame can be short: it is a write that was cut short.
+        if offset < contents.len() {
+            tracing::warn!(
+                "Discarding {} bytes of incomplete writes at the end of {}",
+                contents.len() - offset,
+                journal.path.display()
+            );
+            journal.file.set_len(offset as u64)?;
+            journal.file.sync_all()?;
+        }
+        journal.len = offset as u64;
+        Ok(journal)
+    }
+
+    fn read_frame(bytes: &[u8]) -> Frame {
+        let Some(header) = bytes.get(..FRAME_HEADER_LEN) else {
+            return Frame::Short;
+        };
+        let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+        let Some(body) = bytes.get(FRAME_HEADER_LEN..FRAME_HEADER_LEN + len) else {
+            return Frame::Short;
+        };
+        if crc32fast::hash(body) != crc {
+            return Frame::Corrupt;
+        }
+        match bincode::deserialize(body) {
+            Ok(ops) => Frame::Whole(ops, FRAME_HEADER_LEN + len),
+            Err(_) => Frame::Corrupt,
+        }
+    }
+
+    fn frame(ops: &[JournalOp]) -> std::io::Result<Vec<u8>> {
+        let body = bincode::serialize(ops)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + body.len());
+        frame.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
+        frame.extend_from_slice(&body);
+        Ok(frame)
+    }
+
+    fn write(&mut self, ops: Vec<JournalOp>, durable: bool) -> std::io::Result<()> {
+        let frame = Self::frame(&ops)?;
+        self.file.write_all(&frame)?;
+        if durable {
+            self.file.sync_data()?;
+        }
+        self.len += frame.len() as u64;
+
+        for op in ops {
+            self.apply(op);
+        }
+
+        if self.len > COMPACT_MIN_BYTES && self.len > 2 * self.live_bytes {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, op: JournalOp) {
+        let removed: Vec<Vec<u8>> = match op {
+            JournalOp::Put(key, value) => {
+                self.live_bytes += (key.len() + value.len()) as u64;
+                if let Some(old) = self.live.insert(key.clone(), value) {
+                    self.live_bytes -= (key.len() + old.len()) as u64;
+                }
+                return;
+            }
+            JournalOp::Remove(key) => vec![key],
+            JournalOp::RemoveFrom(key) => self.live.range(key..).map(|(k, _)| k.clone()).collect(),
+            JournalOp::RemoveUpTo(key) => self.live.range(..=key).map(|(k, _)| k.clone()).collect(),
+        };
+        for key in removed {
+            if let Some(old) = self.live.remove(&key) {
+                self.live_bytes -= (key.len() + old.len()) as u64;
+            }
+        }
+    }
+
+    /// Replaces the journal with one holding only the live records.
+    fn compact(&mut self) -> std::io::Result<()> {
+        let tmp_path = self.path.with_extension("compact");
+        let mut tmp = File::create(&tmp_path)?;
+        let mut len = 0;
+        for (key, value) in &self.live {
+            let frame = Self::frame(&[JournalOp::Put(key.clone(), value.clone())])?;
+            tmp.write_all(&frame)?;
+            len += frame.len() as u64;
+        }
+        tmp.sync_all()?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        if let Some(dir) = self.path.parent() {
+            File::open(dir)?.sync_all()?;
+        }
+
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.len = len;
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+use super::{MetaBatch, StorageBackend};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+#[derive(Default)]
+pub struct MemoryBackend {
+    log: Mutex<BTreeMap<u64, Vec<u8>>>,
+    meta: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl StorageBackend for MemoryBackend {
+    fn append_log(&self, records: Vec<(u64, Vec<u8>)>) -> std::io::Result<()> {
+        self.log.lock().unwrap().extend(records);
+        Ok(())
+    }
+
+    fn read_log(&self, start: u64, end: Option<u64>) -> std::io::Result<Vec<(u64, Vec<u8>)>> {
+        let log = self.log.lock().unwrap();
+        Ok(log
+            .range(start..)
+            .take_while(|(index, _)| end.is_none_or(|end| **index < end))
+            .map(|(index, record)| (*index, record.clone()))
+            .collect())
+    }
+
+    fn last_log(&self) -> std::io::Result<Option<(u64, Vec<u8>)>> {
+        let log = self.log.lock().unwrap();
+        Ok(log
+            .last_key_value()
+            .map(|(index, record)| (*index, record.clone())))
+    }
+
+    fn truncate_log(&self, index: u64) -> std::io::Result<()> {
+        self.log.lock().unwrap().split_off(&index);
+        Ok(())
+    }
+
+    fn purge_log(&self, index: u64) -> std::io::Result<()> {
+        self.log.lock().unwrap().retain(|i, _| *i > index);
+        Ok(())
+    }
+
+    fn get_meta(&self, key: &[u8]) -> std::io::Result<Option<Vec<u8>>> {
+        Ok(self.meta.lock().unwrap().get(key).cloned())
+    }
+
+    fn meta_keys(&self) -> std::io::Result<Vec<Vec<u8>>> {
+        Ok(self.meta.lock().unwrap().keys().cloned().collect())
+    }
+
+    fn write_meta(&self, batch: MetaBatch, _durable: bool) -> std::io::Result<()> {
+        let mut meta = self.meta.lock().unwrap();
+        for (key, value) in batch {
+            match value {
+                Some(value) => meta.insert(key, value),
+                None => meta.remove(&key),
+            };
+        }
+        Ok(())
+    }
+}
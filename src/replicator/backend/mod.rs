@@ -0,0 +1,135 @@
+//! Where `HiveStorage` keeps the Raft log and its metadata. Backends store
+//! opaque records; encoding and versioning happen in `HiveStorage`.
+
+mod encrypted_backend;
+mod file_backend;
+mod memory_backend;
+mod sled_backend;
+
+pub use encrypted_backend::{
+    is_encrypted, rotate_key, EncryptedBackend, EncryptionError, EncryptionKey, RotationSummary,
+};
+pub use file_backend::FileBackend;
+pub use memory_backend::MemoryBackend;
+pub use sled_backend::SledBackend;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+
+/// A batch of metadata writes applied atomically. `None` removes the key.
+pub type MetaBatch = Vec<(Vec<u8>, Option<Vec<u8>>)>;
+
+pub trait StorageBackend: Send + Sync {
+    /// Stores log records by index, replacing any already present. The
+    /// records are on disk when this returns.
+    fn append_log(&self, records: Vec<(u64, Vec<u8>)>) -> std::io::Result<()>;
+
+    /// Records with `start <= index < end`, in index order.
+    fn read_log(&self, start: u64, end: Option<u64>) -> std::io::Result<Vec<(u64, Vec<u8>)>>;
+
+    fn last_log(&self) -> std::io::Result<Option<(u64, Vec<u8>)>>;
+
+    /// Removes the records from `index` onwards.
+    fn truncate_log(&self, index: u64) -> std::io::Result<()>;
+
+    /// Removes the records up to and including `index`.
+    fn purge_log(&self, index: u64) -> std::io::Result<()>;
+
+    fn get_meta(&self, key: &[u8]) -> std::io::Result<Option<Vec<u8>>>;
+
+    fn meta_keys(&self) -> std::io::Result<Vec<Vec<u8>>>;
+
+    /// Applies `batch` atomically, and waits for it to reach disk when
+    /// `durable` is set.
+    fn write_meta(&self, batch: MetaBatch, durable: bool) -> std::io::Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    #[default]
+    Sled,
+    /// Append-only journal files, fsynced on every log write.
+    File,
+    /// Keeps nothing across restarts. Meant for tests.
+    Memory,
+}
+
+impl BackendKind {
+    fn dir_name(self) -> Option<&'static str> {
+        match self {
+            BackendKind::Sled => Some("raft"),
+            BackendKind::File => Some("raft-file"),
+            BackendKind::Memory => None,
+        }
+    }
+}
+
+/// Applied entries of history kept by default.
+pub const DEFAULT_HISTORY_ENTRIES: u64 = 100_000;
+
+/// How a node stores Raft state: which backend, the key to encrypt it with,
+/// if any, and how much history to keep.
+#[derive(Debug, Clone)]
+pub struct StorageConfig {
+    pub backend: BackendKind,
+    pub encryption_key: Option<Arc<EncryptionKey>>,
+    /// Applied entries to keep for history queries; 0 turns history off.
+    pub history_entries: u64,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: BackendKind::default(),
+            encryption_key: None,
+            history_entries: DEFAULT_HISTORY_ENTRIES,
+        }
+    }
+}
+
+impl StorageConfig {
+    pub fn open(&self, data_dir: &Path) -> Result<Arc<dyn StorageBackend>> {
+        let backend = open_backend(self.backend, data_dir)?;
+        Ok(match &self.encryption_key {
+            Some(key) => Arc::new(EncryptedBackend::new(backend, key.clone())),
+            None => backend,
+        })
+    }
+}
+
+/// Opens the `kind` backend under `data_dir`. Refuses to start when another
+/// backend already holds Raft state there, since starting over with an
+/// empty log would forget this node's vote.
+pub fn open_backend(kind: BackendKind, data_dir: &Path) -> Result<Arc<dyn StorageBackend>> {
+    for other in [BackendKind::Sled, BackendKind::File] {
+        let Some(dir) = other.dir_name() else {
+            continue;
+        };
+        let path = data_dir.join(dir);
+        if other != kind
+            && path
+                .read_dir()
+                .is_ok_and(|mut entries| entries.next().is_some())
+        {
+            anyhow::bail!(
+                "{} holds Raft state from the {:?} storage backend, but {:?} is configured",
+                path.display(),
+                other,
+                kind
+            );
+        }
+    }
+
+    let backend: Arc<dyn StorageBackend> = match kind {
+        BackendKind::Sled => Arc::new(SledBackend::open(data_dir.join("raft"))?),
+        BackendKind::File => Arc::new(FileBackend::open(data_dir.join("raft-file"))?),
+        BackendKind::Memory => {
+            tracing::warn!("Raft state is kept in memory and will be lost on restart");
+            Arc::new(MemoryBackend::default())
+        }
+    };
+    Ok(backend)
+}
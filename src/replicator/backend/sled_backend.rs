@@ -0,0 +1,112 @@
+use super::{MetaBatch, StorageBackend};
+use std::path::Path;
+
+pub struct SledBackend {
+    log_tree: sled::Tree,
+    meta_tree: sled::Tree,
+}
+
+impl SledBackend {
+    pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        Self::with_db(sled::open(path)?)
+    }
+
+    pub fn with_db(db: sled::Db) -> anyhow::Result<Self> {
+        Ok(Self {
+            log_tree: db.open_tree("raft_log")?,
+            meta_tree: db.open_tree("raft_meta")?,
+        })
+    }
+
+    fn log_key(index: u64) -> [u8; 8] {
+        index.to_be_bytes()
+    }
+
+    fn log_record((key, value): (sled::IVec, sled::IVec)) -> std::io::Result<(u64, Vec<u8>)> {
+        let index = key
+            .as_ref()
+            .try_into()
+            .map(u64::from_be_bytes)
+            .map_err(|_| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed log key")
+            })?;
+        Ok((index, value.to_vec()))
+    }
+}
+
+impl StorageBackend for SledBackend {
+    fn append_log(&self, records: Vec<(u64, Vec<u8>)>) -> std::io::Result<()> {
+        for (index, record) in records {
+            self.log_tree.insert(Self::log_key(index), record)?;
+        }
+        self.log_tree.flush()?;
+        Ok(())
+    }
+
+    fn read_log(&self, start: u64, end: Option<u64>) -> std::io::Result<Vec<(u64, Vec<u8>)>> {
+        let mut records = Vec::new();
+        for item in self.log_tree.range(Self::log_key(start)..) {
+            let (index, record) = Self::log_record(item?)?;
+            if end.is_some_and(|end| index >= end) {
+                break;
+            }
+            records.push((index, record));
+        }
+        Ok(records)
+    }
+
+    fn last_log(&self) -> std::io::Result<Option<(u64, Vec<u8>)>> {
+        self.log_tree.last()?.map(Self::log_record).transpose()
+    }
+
+    fn truncate_log(&self, index: u64) -> std::io::Result<()> {
+        let keys: Vec<_> = self
+            .log_tree
+            .range(Self::log_key(index)..)
+            .keys()
+            .collect::<Result<_, _>>()?;
+        for key in keys {
+            self.log_tree.remove(key)?;
+        }
+        Ok(())
+    }
+
+    fn purge_log(&self, index: u64) -> std::io::Result<()> {
+        let keys: Vec<_> = self
+            .log_tree
+            .range(..=Self::log_key(index))
+            .keys()
+            .collect::<Result<_, _>>()?;
+        for key in keys {
+            self.log_tree.remove(key)?;
+        }
+        Ok(())
+    }
+
+    fn get_meta(&self, key: &[u8]) -> std::io::Result<Option<Vec<u8>>> {
+        Ok(self.meta_tree.get(key)?.map(|v| v.to_vec()))
+    }
+
+    fn meta_keys(&self) -> std::io::Result<Vec<Vec<u8>>> {
+        self.meta_tree
+            .iter()
+            .keys()
+            .map(|key| Ok(key?.to_vec()))
+            .collect()
+    }
+
+    fn write_meta(&self, batch: MetaBatch, durable: bool) -> std::io::Result<()> {
+        let mut sled_batch = sled::Batch::default();
+        for (key, value) in batch {
+            match value {
+                Some(value) => sled_batch.insert(key, value),
+                None => sled_batch.remove(key),
+            }
+        }
+        self.meta_tree.apply_batch(sled_batch)?;
+        if durable {
+            self.meta_tree.flush()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+//! Envelope around every record `HiveStorage` writes: a magic prefix, the
+//! storage format version and the codec of the body that follows.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+const MAGIC: &[u8; 4] = b"FLKM";
+const HEADER_LEN: usize = MAGIC.len() + 2;
+
+/// Bumped whenever the layout of a stored record changes. Storage written
+/// with an older version is migrated when it is opened.
+pub const FORMAT_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Codec {
+    Bincode = 1,
+    Json = 2,
+}
+
+impl Codec {
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Codec::Bincode),
+            2 => Some(Codec::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RecordError {
+    #[error("record has no format header")]
+    MissingHeader,
+    #[error("record has format version {0}, this build reads up to {FORMAT_VERSION}")]
+    UnsupportedVersion(u8),
+    #[error("record uses unknown codec {0}")]
+    UnknownCodec(u8),
+    #[error("bincode: {0}")]
+    Bincode(#[from] bincode::Error),
+    #[error("json: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+impl From<RecordError> for std::io::Error {
+    fn from(e: RecordError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    }
+}
+
+pub fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, RecordError> {
+    encode_with(Codec::Bincode, value)
+}
+
+pub fn encode_with<T: Serialize>(codec: Codec, value: &T) -> Result<Vec<u8>, RecordError> {
+    let mut record = Vec::with_capacity(64);
+    record.extend_from_slice(MAGIC);
+    record.push(FORMAT_VERSION);
+    record.push(codec as u8);
+    match codec {
+        Codec::Bincode => bincode::serialize_into(&mut record, value)?,
+        Codec::Json => serde_json::to_writer(&mut record, value)?,
+    }
+    Ok(record)
+}
+
+pub fn decode<T: DeserializeOwned>(record: &[u8]) -> Result<T, RecordError> {
+    if !has_header(record) {
+        return Err(RecordError::MissingHeader);
+    }
+
+    let version = record[MAGIC.len()];
+    if version == 0 || version > FORMAT_VERSION {
+        return Err(RecordError::UnsupportedVersion(version));
+    }
+
+    let codec_id = record[MAGIC.len() + 1];
+    let body = &record[HEADER_LEN..];
+    match Codec::from_id(codec_id) {
+        Some(Codec::Bincode) => Ok(bincode::deserialize(body)?),
+        Some(Codec::Json) => Ok(serde_json::from_slice(body)?),
+        None => Err(RecordError::UnknownCodec(codec_id)),
+    }
+}
+
+/// Whether `record` carries a format header. Records written before the
+/// envelope was introduced do not.
+pub fn has_header(record: &[u8]) -> bool {
+    record.len() >= HEADER_LEN && record.starts_with(MAGIC)
+}
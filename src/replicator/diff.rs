@@ -0,0 +1,312 @@
+//! Structural differences between two `HiveState`s, so two points in time
+//! can be compared without reading whole cluster dumps side by side.
+
+use crate::replicator::state_machine::HiveState;
+use crate::types::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum NodeChange {
+    Added { node_id: NodeId, health: NodeHealth },
+    Removed { node_id: NodeId },
+    HealthChanged { node_id: NodeId, from: NodeHealth, to: NodeHealth },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum TaskChange {
+    Added { task_id: TaskId, target_node: NodeId, status: TaskStatus },
+    Removed { task_id: TaskId, status: TaskStatus },
+    StatusChanged { task_id: TaskId, from: TaskStatus, to: TaskStatus },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum GoalChange {
+    Added { goal_id: GoalId, description: String, active: bool },
+    Removed { goal_id: GoalId },
+    Toggled { goal_id: GoalId, active: bool },
+    /// Description, constraints or priority changed.
+    Changed { goal_id: GoalId, fields: Vec<String> },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum AttachmentChange {
+    Added { attachment_id: AttachmentId, node_id: NodeId },
+    Removed { attachment_id: AttachmentId },
+    Changed { attachment_id: AttachmentId, fields: Vec<String> },
+}
+
+/// What changed from one state to another. Heartbeats and metrics are left
+/// out; only health, task status, goal and attachment edits are reported.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StateDiff {
+    pub nodes: Vec<NodeChange>,
+    pub tasks: Vec<TaskChange>,
+    pub goals: Vec<GoalChange>,
+    pub attachments: Vec<AttachmentChange>,
+}
+
+impl StateDiff {
+    pub fn between(old: &HiveState, new: &HiveState) -> Self {
+        Self {
+            nodes: diff_map(&old.nodes, &new.nodes, |id, old, new| match (old, new) {
+                (None, Some(node)) => Some(NodeChange::Added {
+                    node_id: id.clone(),
+                    health: node.health.clone(),
+                }),
+                (Some(_), None) => Some(NodeChange::Removed { node_id: id.clone() }),
+                (Some(old), Some(new)) if old.health != new.health => {
+                    Some(NodeChange::HealthChanged {
+                        node_id: id.clone(),
+                        from: old.health.clone(),
+                        to: new.health.clone(),
+                    })
+                }
+                _ => None,
+            }),
+            tasks: diff_map(&old.tasks, &new.tasks, |id, old, new| match (old, new) {
+                (None, Some(task)) => Some(TaskChange::Added {
+                    task_id: id.clone(),
+                    target_node: task.target_node.clone(),
+                    status: task.status.clone(),
+                }),
+                (Some(task), None) => Some(TaskChange::Removed {
+                    task_id: id.clone(),
+                    status: task.status.clone(),
+                }),
+                (Some(old), Some(new)) if old.status != new.status => {
+                    Some(TaskChange::StatusChanged {
+                        task_id: id.clone(),
+                        from: old.status.clone(),
+                        to: new.status.clone(),
+                    })
+                }
+                _ => None,
+            }),
+            goals: diff_map(&old.goals, &new.goals, |id, old, new| match (old, new) {
+                (None, Some(goal)) => Some(vec![GoalChange::Added {
+                    goal_id: id.clone(),
+                    description: goal.description.clone(),
+                    active: goal.active,
+                }]),
+                (Some(_), None) => Some(vec![GoalChange::Removed { goal_id: id.clone() }]),
+                (Some(old), Some(new)) => {
+                    let mut changes = Vec::new();
+                    if old.active != new.active {
+                        changes.push(GoalChange::Toggled {
+                            goal_id: id.clone(),
+                            active: new.active,
+                        });
+                    }
+                    let fields = changed_fields([
+                        ("description", old.description != new.description),
+                        ("constraints", old.constraints != new.constraints),
+                        ("priority", old.priority != new.priority),
+                    ]);
+                    if !fields.is_empty() {
+                        changes.push(GoalChange::Changed {
+                            goal_id: id.clone(),
+                            fields,
+                        });
+                    }
+                    Some(changes)
+                }
+                (None, None) => None,
+            })
+            .into_iter()
+            .flatten()
+            .collect(),
+            attachments: diff_map(&old.attachments, &new.attachments, |id, old, new| {
+                match (old, new) {
+                    (None, Some(attachment)) => Some(AttachmentChange::Added {
+                        attachment_id: id.clone(),
+                        node_id: attachment.node_id.clone(),
+                    }),
+                    (Some(_), None) => Some(AttachmentChange::Removed {
+                        attachment_id: id.clone(),
+                    }),
+                    (Some(old), Some(new)) => {
+                        let fields = changed_fields([
+                            ("node_id", old.node_id != new.node_id),
+                            (
+                                "kind",
+                                serde_json::to_value(&old.kind).ok()
+                                    != serde_json::to_value(&new.kind).ok(),
+                            ),
+                            ("capabilities", old.capabilities != new.capabilities),
+                            ("metadata", old.metadata != new.metadata),
+                        ]);
+                        (!fields.is_empty()).then(|| AttachmentChange::Changed {
+                            attachment_id: id.clone(),
+                            fields,
+                        })
+                    }
+                    (None, None) => None,
+                }
+            }),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+            && self.tasks.is_empty()
+            && self.goals.is_empty()
+            && self.attachments.is_empty()
+    }
+}
+
+/// Calls `change` for every key in either map, in key order.
+fn diff_map<V, C>(
+    old: &HashMap<String, V>,
+    new: &HashMap<String, V>,
+    change: impl Fn(&String, Option<&V>, Option<&V>) -> Option<C>,
+) -> Vec<C> {
+    let ids: BTreeSet<_> = old.keys().chain(new.keys()).collect();
+    ids.into_iter()
+        .filter_map(|id| change(id, old.get(id), new.get(id)))
+        .collect()
+}
+
+fn changed_fields<const N: usize>(fields: [(&str, bool); N]) -> Vec<String> {
+    fields
+        .into_iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+fn health_label(health: &NodeHealth) -> String {
+    match health {
+        NodeHealth::Healthy => "healthy".to_string(),
+        NodeHealth::Degraded { reason } => format!("degraded ({})", reason),
+        NodeHealth::Unreachable => "unreachable".to_string(),
+        NodeHealth::Unknown => "unknown".to_string(),
+    }
+}
+
+fn status_label(status: &TaskStatus) -> String {
+    match status {
+        TaskStatus::Pending => "pending".to_string(),
+        TaskStatus::Scheduled => "scheduled".to_string(),
+        TaskStatus::Running => "running".to_string(),
+        TaskStatus::Completed => "completed".to_string(),
+        TaskStatus::Failed { error } => format!("failed ({})", error),
+        TaskStatus::Cancelled => "cancelled".to_string(),
+    }
+}
+
+fn active_label(active: bool) -> &'static str {
+    if active {
+        "active"
+    } else {
+        "inactive"
+    }
+}
+
+/// One line per change, `+` for added, `-` for removed and `~` for changed.
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No changes");
+        }
+
+        if !self.nodes.is_empty() {
+            writeln!(f, "Nodes:")?;
+            for change in &self.nodes {
+                match change {
+                    NodeChange::Added { node_id, health } => {
+                        writeln!(f, "  + {} ({})", node_id, health_label(health))?
+                    }
+                    NodeChange::Removed { node_id } => writeln!(f, "  - {}", node_id)?,
+                    NodeChange::HealthChanged { node_id, from, to } => writeln!(
+                        f,
+                        "  ~ {}: {} -> {}",
+                        node_id,
+                        health_label(from),
+                        health_label(to)
+                    )?,
+                }
+            }
+        }
+
+        if !self.tasks.is_empty() {
+            writeln!(f, "Tasks:")?;
+            for change in &self.tasks {
+                match change {
+                    TaskChange::Added {
+                        task_id,
+                        target_node,
+                        status,
+                    } => writeln!(
+                        f,
+                        "  + {} on {} ({})",
+                        task_id,
+                        target_node,
+                        status_label(status)
+                    )?,
+                    TaskChange::Removed { task_id, status } => {
+                        writeln!(f, "  - {} ({})", task_id, status_label(status))?
+                    }
+                    TaskChange::StatusChanged { task_id, from, to } => writeln!(
+                        f,
+                        "  ~ {}: {} -> {}",
+                        task_id,
+                        status_label(from),
+                        status_label(to)
+                    )?,
+                }
+            }
+        }
+
+        if !self.goals.is_empty() {
+            writeln!(f, "Goals:")?;
+            for change in &self.goals {
+                match change {
+                    GoalChange::Added {
+                        goal_id,
+                        description,
+                        active,
+                    } => writeln!(
+                        f,
+                        "  + {} \"{}\" ({})",
+                        goal_id,
+                        description,
+                        active_label(*active)
+                    )?,
+                    GoalChange::Removed { goal_id } => writeln!(f, "  - {}", goal_id)?,
+                    GoalChange::Toggled { goal_id, active } => {
+                        writeln!(f, "  ~ {}: now {}", goal_id, active_label(*active))?
+                    }
+                    GoalChange::Changed { goal_id, fields } => {
+                        writeln!(f, "  ~ {}: {} changed", goal_id, fields.join(", "))?
+                    }
+                }
+            }
+        }
+
+        if !self.attachments.is_empty() {
+            writeln!(f, "Attachments:")?;
+            for change in &self.attachments {
+                match change {
+                    AttachmentChange::Added {
+                        attachment_id,
+                        node_id,
+                    } => writeln!(f, "  + {} on {}", attachment_id, node_id)?,
+                    AttachmentChange::Removed { attachment_id } => {
+                        writeln!(f, "  - {}", attachment_id)?
+                    }
+                    AttachmentChange::Changed {
+                        attachment_id,
+                        fields,
+                    } => writeln!(f, "  ~ {}: {} changed", attachment_id, fields.join(", "))?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
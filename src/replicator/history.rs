@@ -0,0 +1,313 @@
+//! A per-node record of every applied log entry and when it was applied,
+//! plus saved states at snapshot points, so the cluster can be inspected as
+//! of an earlier log index or time.
+
+use crate::replicator::backend::StorageBackend;
+use crate::replicator::codec::{self, RecordError};
+use crate::replicator::state_machine::HiveState;
+use crate::replicator::storage::{NodeIdType, TypeConfig};
+use crate::types::{ClusterCommand, CommandResult};
+use chrono::{DateTime, Utc};
+use openraft::{CommittedLeaderId, Entry, EntryPayload, LogId};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+const KEY_CHECKPOINTS: &[u8] = b"checkpoints";
+
+fn checkpoint_key(index: u64) -> Vec<u8> {
+    [b"checkpoint/".as_slice(), &index.to_be_bytes()].concat()
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum HistoryError {
+    #[error("history is turned off on this node")]
+    Disabled,
+    #[error("`{0}` is neither a log index nor an RFC 3339 time")]
+    InvalidPoint(String),
+    #[error("log index {0} has not been applied on this node")]
+    NotApplied(u64),
+    #[error("{0} is older than the history kept on this node")]
+    NotRetained(String),
+    #[error("history from log index {from} to {to} is missing on this node")]
+    Incomplete { from: u64, to: u64 },
+    #[error(transparent)]
+    Storage(#[from] std::io::Error),
+}
+
+impl From<RecordError> for HistoryError {
+    fn from(e: RecordError) -> Self {
+        HistoryError::Storage(e.into())
+    }
+}
+
+/// A log index, or the last entry applied at or before a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryPoint {
+    Index(u64),
+    Time(DateTime<Utc>),
+}
+
+impl FromStr for HistoryPoint {
+    type Err = HistoryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(index) = s.parse() {
+            return Ok(HistoryPoint::Index(index));
+        }
+        DateTime::parse_from_rfc3339(s)
+            .map(|time| HistoryPoint::Time(time.with_timezone(&Utc)))
+            .map_err(|_| HistoryError::InvalidPoint(s.to_string()))
+    }
+}
+
+/// One applied log entry. `command` is `None` for the blank and membership
+/// entries Raft appends itself; they are kept so gaps can be told apart
+/// from entries without a command.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub index: u64,
+    pub term: u64,
+    pub leader: NodeIdType,
+    pub applied_at: DateTime<Utc>,
+    pub command: Option<ClusterCommand>,
+    pub result: Option<CommandResult>,
+}
+
+impl HistoryEntry {
+    fn new(entry: &Entry<TypeConfig>, result: &CommandResult, applied_at: DateTime<Utc>) -> Self {
+        let command = match &entry.payload {
+            EntryPayload::Normal(command) => Some(command.clone()),
+            _ => None,
+        };
+        Self {
+            index: entry.log_id.index,
+            term: entry.log_id.leader_id.term,
+            leader: entry.log_id.leader_id.node_id,
+            applied_at,
+            result: command.as_ref().map(|_| result.clone()),
+            command,
+        }
+    }
+
+    pub fn log_id(&self) -> LogId<NodeIdType> {
+        LogId::new(CommittedLeaderId::new(self.term, self.leader), self.index)
+    }
+}
+
+/// History kept on its own `StorageBackend`: one log record per applied
+/// entry, and the state as of each snapshot under `checkpoint/<index>`.
+pub struct HistoryStore {
+    backend: Arc<dyn StorageBackend>,
+    keep_entries: u64,
+    /// Log ids with a saved state, oldest first.
+    checkpoints: Mutex<Vec<LogId<NodeIdType>>>,
+    last_recorded: Mutex<Option<u64>>,
+}
+
+impl HistoryStore {
+    /// Opens history on `backend`, keeping at least the last `keep_entries`
+    /// entries before the newest checkpoint.
+    pub fn new(backend: Arc<dyn StorageBackend>, keep_entries: u64) -> Result<Self, HistoryError> {
+        let checkpoints = match backend.get_meta(KEY_CHECKPOINTS)? {
+            Some(record) => codec::decode(&record)?,
+            None => Vec::new(),
+        };
+        let last_recorded = backend.last_log()?.map(|(index, _)| index);
+        Ok(Self {
+            backend,
+            keep_entries,
+            checkpoints: Mutex::new(checkpoints),
+            last_recorded: Mutex::new(last_recorded),
+        })
+    }
+
+    /// Records `entries`, which gave `results`, as applied at `applied_at`.
+    /// Entries recorded before are skipped, so the ones openraft re-applies
+    /// after a restart keep their original time.
+    pub fn record(
+        &self,
+        entries: &[Entry<TypeConfig>],
+        results: &[CommandResult],
+        applied_at: DateTime<Utc>,
+    ) -> Result<(), HistoryError> {
+        let mut last_recorded = self.last_recorded.lock().unwrap();
+        let records = entries
+            .iter()
+            .zip(results)
+            .filter(|(entry, _)| last_recorded.is_none_or(|last| entry.log_id.index > last))
+            .map(|(entry, result)| {
+                let record = codec::encode(&HistoryEntry::new(entry, result, applied_at))?;
+                Ok((entry.log_id.index, record))
+            })
+            .collect::<Result<Vec<_>, RecordError>>()?;
+
+        let Some(&(last, _)) = records.last() else {
+            return Ok(());
+        };
+        self.backend.append_log(records)?;
+        *last_recorded = Some(last);
+        Ok(())
+    }
+
+    pub fn has_checkpoints(&self) -> bool {
+        !self.checkpoints.lock().unwrap().is_empty()
+    }
+
+    /// Saves `state` as of `log_id`, then drops checkpoints and entries no
+    /// longer needed to rebuild the last `keep_entries` entries.
+    pub fn checkpoint(
+        &self,
+        log_id: LogId<NodeIdType>,
+        state: &HiveState,
+    ) -> Result<(), HistoryError> {
+        let mut checkpoints = self.checkpoints.lock().unwrap();
+        if checkpoints.last().is_some_and(|last| last.index >= log_id.index) {
+            return Ok(());
+        }
+
+        let mut kept = checkpoints.clone();
+        kept.push(log_id);
+        let cutoff = log_id.index.saturating_sub(self.keep_entries);
+        let oldest = kept.iter().rposition(|c| c.index <= cutoff);
+        let dropped: Vec<_> = kept.drain(..oldest.unwrap_or(0)).collect();
+
+        let mut batch = vec![
+            (checkpoint_key(log_id.index), Some(codec::encode(state)?)),
+            (KEY_CHECKPOINTS.to_vec(), Some(codec::encode(&kept)?)),
+        ];
+        batch.extend(dropped.iter().map(|c| (checkpoint_key(c.index), None)));
+        self.backend.write_meta(batch, true)?;
+        if oldest.is_some() {
+            self.backend.purge_log(kept[0].index)?;
+        }
+
+        *checkpoints = kept;
+        Ok(())
+    }
+
+    /// Entries with a command between `from` and `to` inclusive, limited to
+    /// those touching `entity` when set.
+    pub fn entries(
+        &self,
+        from: Option<HistoryPoint>,
+        to: Option<HistoryPoint>,
+        entity: Option<&str>,
+    ) -> Result<Vec<HistoryEntry>, HistoryError> {
+        let start = match from {
+            Some(HistoryPoint::Index(index)) => index,
+            _ => 0,
+        };
+        let end = match to {
+            Some(HistoryPoint::Index(index)) => Some(index.saturating_add(1)),
+            _ => None,
+        };
+
+        Ok(self
+            .read(start, end)?
+            .into_iter()
+            .filter(|entry| match from {
+                Some(HistoryPoint::Time(time)) => entry.applied_at >= time,
+                _ => true,
+            })
+            .filter(|entry| match to {
+                Some(HistoryPoint::Time(time)) => entry.applied_at <= time,
+                _ => true,
+            })
+            .filter(|entry| {
+                entry.command.as_ref().is_some_and(|command| {
+                    entity.is_none_or(|entity| command.entity_ids().contains(&entity))
+                })
+            })
+            .collect())
+    }
+
+    /// Every entry from log index `index` on, failing when history no
+    /// longer reaches back that far.
+    pub fn since(&self, index: u64) -> Result<Vec<HistoryEntry>, HistoryError> {
+        let entries = self.read(index, None)?;
+        let last_recorded = *self.last_recorded.lock().unwrap();
+        if last_recorded.is_some_and(|last| index <= last)
+            && entries.first().is_none_or(|first| first.index != index)
+        {
+            return Err(HistoryError::NotRetained(format!("log index {}", index)));
+        }
+        Ok(entries)
+    }
+
+    /// Rebuilds the state as of `at` by replaying entries from the nearest
+    /// checkpoint before it, with the times they were first applied.
+    pub fn state_at(
+        &self,
+        at: HistoryPoint,
+    ) -> Result<(HiveState, LogId<NodeIdType>), HistoryError> {
+        let index = self.resolve(at)?;
+        let base = self
+            .checkpoints
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|c| c.index <= index)
+            .copied();
+
+        let (mut state, mut log_id) = match base {
+            Some(log_id) => {
+                let record = self
+                    .backend
+                    .get_meta(&checkpoint_key(log_id.index))?
+                    .ok_or_else(|| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::NotFound,
+                            format!("checkpoint at log index {} is missing", log_id.index),
+                        )
+                    })?;
+                (codec::decode(&record)?, Some(log_id))
+            }
+            None => (HiveState::new(), None),
+        };
+
+        let from = log_id.map_or(0, |l| l.index + 1);
+        let entries = self.read(from, Some(index + 1))?;
+        if entries.len() as u64 != index + 1 - from {
+            return Err(match base {
+                Some(_) => HistoryError::Incomplete { from, to: index },
+                None => HistoryError::NotRetained(format!("log index {}", index)),
+            });
+        }
+
+        for entry in entries {
+            if let Some(command) = &entry.command {
+                state.apply_entry(entry.index, command, entry.applied_at);
+            }
+            log_id = Some(entry.log_id());
+        }
+        // `index` is at or after `from`, or `base` itself.
+        Ok((state, log_id.unwrap()))
+    }
+
+    fn resolve(&self, at: HistoryPoint) -> Result<u64, HistoryError> {
+        let last_recorded = *self.last_recorded.lock().unwrap();
+        match at {
+            HistoryPoint::Index(index) if last_recorded.is_none_or(|last| index > last) => {
+                Err(HistoryError::NotApplied(index))
+            }
+            HistoryPoint::Index(index) => Ok(index),
+            HistoryPoint::Time(time) => self
+                .read(0, None)?
+                .iter()
+                .take_while(|entry| entry.applied_at <= time)
+                .last()
+                .map(|entry| entry.index)
+                .ok_or_else(|| HistoryError::NotRetained(time.to_rfc3339())),
+        }
+    }
+
+    fn read(&self, start: u64, end: Option<u64>) -> Result<Vec<HistoryEntry>, HistoryError> {
+        self.backend
+            .read_log(start, end)?
+            .iter()
+            .map(|(_, record)| Ok(codec::decode(record)?))
+            .collect()
+    }
+}
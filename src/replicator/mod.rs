@@ -1,21 +1,37 @@
+pub mod backend;
+pub mod codec;
+pub mod diff;
+pub mod history;
 mod network;
 mod raft_node;
 pub mod state_machine;
 pub mod storage;
+mod v0;
+pub mod watch;
 
+pub use backend::{BackendKind, StorageBackend, StorageConfig};
+pub use diff::StateDiff;
+pub use history::{HistoryEntry, HistoryError, HistoryPoint, HistoryStore};
 pub use network::*;
 pub use raft_node::*;
 pub use state_machine::*;
 pub use storage::*;
+pub use watch::{AppliedCommand, WatchFilter};
 
 use crate::types::*;
 use async_trait::async_trait;
 
 #[async_trait]
 pub trait Replicator: Send + Sync {
-    async fn apply(&self, command: ClusterCommand) -> anyhow::Result<()>;
+    /// Replicates `command` and reports what it did to the state machine.
+    async fn apply(&self, command: ClusterCommand) -> anyhow::Result<CommandResult>;
+    /// Waits until local state includes every write committed before the
+    /// call, so that a following `snapshot` is linearizable.
+    async fn ensure_linearizable(&self) -> anyhow::Result<()>;
     fn snapshot(&self) -> ClusterView;
+    fn hive_state(&self) -> HiveState;
     fn is_leader(&self) -> bool;
     fn leader_id(&self) -> Option<NodeId>;
     async fn add_peer(&self, peer: PeerInfo) -> anyhow::Result<()>;
+    async fn remove_peer(&self, node_id: &str) -> anyhow::Result<()>;
 }
@@ -1,3 +1,4 @@
+use crate::auth::ClusterTls;
 use crate::replicator::storage::{HiveNode, NodeIdType, TypeConfig};
 use openraft::error::{InstallSnapshotError, NetworkError, RPCError, RaftError};
 use openraft::network::{RPCOption, RaftNetwork, RaftNetworkFactory};
@@ -11,12 +12,39 @@ use std::sync::{Arc, RwLock};
 #[derive(Clone)]
 pub struct HiveNetworkFactory {
     connections: Arc<RwLock<HashMap<NodeIdType, String>>>,
+    tls: Option<Arc<ClusterTls>>,
+    client: reqwest::Client,
 }
 
 impl HiveNetworkFactory {
     pub fn new() -> Self {
         Self {
             connections: Arc::new(RwLock::new(HashMap::new())),
+            tls: None,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn with_tls(tls: Arc<ClusterTls>) -> Self {
+        Self {
+            connections: Arc::new(RwLock::new(HashMap::new())),
+            client: tls.http_client(),
+            tls: Some(tls),
+        }
+    }
+
+    pub fn scheme(&self) -> &'static str {
+        if self.tls.is_some() {
+            "https"
+        } else {
+            "http"
+        }
+    }
+
+    pub fn http_client(&self) -> reqwest::Client {
+        match &self.tls {
+            Some(tls) => tls.http_client(),
+            None => self.client.clone(),
         }
     }
 
@@ -40,15 +68,22 @@ pub struct HiveNetwork {
     #[allow(dead_code)]
     target: NodeIdType,
     target_addr: String,
+    scheme: &'static str,
     client: reqwest::Client,
 }
 
 impl HiveNetwork {
-    pub fn new(target: NodeIdType, target_addr: String) -> Self {
+    pub fn new(
+        target: NodeIdType,
+        target_addr: String,
+        scheme: &'static str,
+        client: reqwest::Client,
+    ) -> Self {
         Self {
             target,
             target_addr,
-            client: reqwest::Client::new(),
+            scheme,
+            client,
         }
     }
 
@@ -62,7 +97,7 @@ impl HiveNetwork {
         Resp: serde::de::DeserializeOwned,
         E: std::error::Error,
     {
-        let url = format!("http://{}/raft/{}", self.target_addr, path);
+        let url = format!("{}://{}/raft/{}", self.scheme, self.target_addr, path);
 
         let response = self
             .client
@@ -89,7 +124,7 @@ impl RaftNetworkFactory<TypeConfig> for HiveNetworkFactory {
     type Network = HiveNetwork;
 
     async fn new_client(&mut self, target: NodeIdType, node: &HiveNode) -> Self::Network {
-        HiveNetwork::new(target, node.addr.clone())
+        HiveNetwork::new(target, node.addr.clone(), self.scheme(), self.http_client())
     }
 }
 
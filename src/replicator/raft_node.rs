@@ -1,22 +1,82 @@
+use crate::auth::ClusterTls;
+use crate::replicator::backend::StorageConfig;
+use crate::replicator::diff::StateDiff;
+use crate::replicator::history::{HistoryEntry, HistoryError, HistoryPoint, HistoryStore};
 use crate::replicator::network::HiveNetworkFactory;
-use crate::replicator::state_machine::SharedState;
-use crate::replicator::storage::{create_storage, HiveNode, NodeIdType, TypeConfig};
+use crate::replicator::state_machine::{HiveState, SharedState};
+use crate::replicator::storage::{HiveNode, HiveStorage, NodeIdType, StateBackup, TypeConfig};
+use crate::replicator::watch::AppliedCommand;
 use crate::replicator::Replicator;
 use crate::types::*;
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use openraft::{ChangeMembers, Config, Raft};
-use std::collections::BTreeMap;
+use openraft::storage::Adaptor;
+use openraft::{ChangeMembers, Config, LogId, Raft, SnapshotPolicy};
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::Path;
 use std::sync::Arc;
-use tracing::info;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, info};
+
+/// Attempts made to reach the leader before giving up, covering elections
+/// that happen while a request is being forwarded.
+const LEADER_ATTEMPTS: usize = 10;
+const LEADER_RETRY_DELAY: Duration = Duration::from_millis(300);
+const READ_APPLY_TIMEOUT: Duration = Duration::from_secs(5);
+const LEADER_TRANSFER_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a transfer target is given to win an election before it is
+/// asked to campaign again.
+const CAMPAIGN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Log index a linearizable read has to observe, as confirmed by the leader.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadIndexResponse {
+    pub index: Option<u64>,
+}
+
+/// A Raft member as seen by this node. Replication progress is only known
+/// on the leader; elsewhere `matched_index` and `lag` are `None`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemberStatus {
+    pub node_id: NodeId,
+    pub raft_id: NodeIdType,
+    pub addr: String,
+    pub is_voter: bool,
+    pub is_leader: bool,
+    pub matched_index: Option<u64>,
+    pub lag: Option<u64>,
+}
+
+/// Snapshot and log purge policy for the Raft log.
+#[derive(Debug, Clone)]
+pub struct SnapshotConfig {
+    /// Build a snapshot once this many entries were applied since the last.
+    pub logs_since_last: u64,
+    /// Entries already covered by a snapshot that are kept for followers
+    /// that are only slightly behind; older ones are purged.
+    pub logs_to_keep: u64,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            logs_since_last: 5000,
+            logs_to_keep: 1000,
+        }
+    }
+}
 
 pub type HiveRaft = Raft<TypeConfig>;
 
 pub struct RaftReplicator {
     node_id: NodeIdType,
+    addr: String,
+    hostname: String,
     raft: HiveRaft,
     state: SharedState,
+    store: HiveStorage,
     network: HiveNetworkFactory,
 }
 
@@ -24,50 +84,158 @@ impl RaftReplicator {
     pub async fn new<P: AsRef<Path>>(
         node_id: NodeIdType,
         addr: String,
-        _hostname: String,
+        hostname: String,
         data_dir: P,
+        tls: Option<Arc<ClusterTls>>,
+        snapshot: SnapshotConfig,
+        storage: StorageConfig,
     ) -> Result<Self> {
         let config = Config {
             heartbeat_interval: 500,
             election_timeout_min: 1500,
             election_timeout_max: 3000,
+            snapshot_policy: SnapshotPolicy::LogsSinceLast(snapshot.logs_since_last),
+            max_in_snapshot_log_to_keep: snapshot.logs_to_keep,
             ..Default::default()
         };
         let config = Arc::new(config.validate()?);
 
         let state = SharedState::new();
-        let storage_path = data_dir.as_ref().join("raft");
-        std::fs::create_dir_all(&storage_path)?;
-        let (log_store, sm_store) = create_storage(&storage_path, state.clone())?;
-        let network = HiveNetworkFactory::new();
+        let mut store = HiveStorage::new(storage.open(data_dir.as_ref())?, state.clone())?;
+        if storage.history_entries > 0 {
+            let backend = storage.open(&data_dir.as_ref().join("history"))?;
+            let history = HistoryStore::new(backend, storage.history_entries)?;
+            store = store.with_history(Arc::new(history))?;
+        }
+        let (log_store, sm_store) = Adaptor::new(store.clone());
+        let network = match &tls {
+            Some(tls) => HiveNetworkFactory::with_tls(tls.clone()),
+            None => HiveNetworkFactory::new(),
+        };
 
         network.register_node(node_id, addr.clone());
 
         let raft = Raft::new(node_id, config, network.clone(), log_store, sm_store).await?;
 
-        info!("Raft node {} initialized at {} with storage at {:?}", node_id, addr, storage_path);
+        info!(
+            "Raft node {} initialized at {} with {:?} storage{}",
+            node_id,
+            addr,
+            storage.backend,
+            if storage.encryption_key.is_some() { ", encrypted" } else { "" }
+        );
 
         Ok(Self {
             node_id,
+            addr,
+            hostname,
             raft,
             state,
+            store,
             network,
         })
     }
 
-    pub async fn initialize_single(&self) -> Result<()> {
-        let mut members = BTreeMap::new();
-        members.insert(
-            self.node_id,
-            HiveNode {
-                addr: "127.0.0.1:9000".to_string(),
-                hostname: "localhost".to_string(),
-            },
-        );
-        self.raft.initialize(members).await?;
+    /// Forms a new cluster whose voters are this node plus `voters`. Does
+    /// nothing once this node holds Raft state, so founding members can
+    /// call it on every start.
+    pub async fn bootstrap(&self, mut voters: BTreeMap<NodeIdType, HiveNode>) -> Result<()> {
+        if self.raft.is_initialized().await? {
+            debug!("Raft state already initialized, skipping bootstrap");
+            return Ok(());
+        }
+
+        voters.insert(self.node_id, self.self_node());
+        for (id, node) in &voters {
+            self.network.register_node(*id, node.addr.clone());
+        }
+
+        info!("Bootstrapping cluster with voters {:?}", voters.keys().collect::<Vec<_>>());
+        self.raft.initialize(voters).await?;
         Ok(())
     }
 
+    pub fn self_node(&self) -> HiveNode {
+        HiveNode {
+            addr: self.addr.clone(),
+            hostname: self.hostname.clone(),
+        }
+    }
+
+    /// Whether this node appears in the membership it has replicated,
+    /// as either voter or learner.
+    pub fn is_member(&self) -> bool {
+        let metrics = self.raft.metrics().borrow().clone();
+        metrics
+            .membership_config
+            .membership()
+            .get_node(&self.node_id)
+            .is_some()
+    }
+
+    /// Cluster state including every write committed before the call.
+    pub async fn backup(&self) -> Result<StateBackup> {
+        self.ensure_linearizable().await?;
+        Ok(self.store.backup())
+    }
+
+    /// Applied commands between `from` and `to`, as recorded by this node.
+    pub fn history(
+        &self,
+        from: Option<HistoryPoint>,
+        to: Option<HistoryPoint>,
+        entity: Option<&str>,
+    ) -> Result<Vec<HistoryEntry>, HistoryError> {
+        let history = self.store.history().ok_or(HistoryError::Disabled)?;
+        history.entries(from, to, entity)
+    }
+
+    /// The cluster as it stood once the entry at `at` was applied, with
+    /// that entry's leader and term.
+    pub fn view_at(&self, at: HistoryPoint) -> Result<ClusterView, HistoryError> {
+        let (state, log_id) = self.state_at(at)?;
+        let leader = log_id.leader_id.node_id;
+        let leader = state
+            .node_id_of(leader)
+            .cloned()
+            .unwrap_or_else(|| self.node_id_for(leader));
+        Ok(state.to_cluster_view(Some(leader), log_id.leader_id.term))
+    }
+
+    /// Changes from the state at `from` to the state at `to`, or to the
+    /// current state when `to` is `None`.
+    pub fn diff(
+        &self,
+        from: HistoryPoint,
+        to: Option<HistoryPoint>,
+    ) -> Result<StateDiff, HistoryError> {
+        let (old, _) = self.state_at(from)?;
+        let new = match to {
+            Some(to) => self.state_at(to)?.0,
+            None => self.state.snapshot(),
+        };
+        Ok(StateDiff::between(&old, &new))
+    }
+
+    /// Commands applied from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<AppliedCommand> {
+        self.state.subscribe()
+    }
+
+    /// Commands applied from log index `index` on, then a receiver for
+    /// those applied later. See `HiveStorage::watch_from`.
+    pub fn watch_from(
+        &self,
+        index: u64,
+    ) -> Result<(Vec<AppliedCommand>, broadcast::Receiver<AppliedCommand>), HistoryError> {
+        self.store.watch_from(index)
+    }
+
+    fn state_at(&self, at: HistoryPoint) -> Result<(HiveState, LogId<NodeIdType>), HistoryError> {
+        let history = self.store.history().ok_or(HistoryError::Disabled)?;
+        history.state_at(at)
+    }
+
     pub fn raft(&self) -> &HiveRaft {
         &self.raft
     }
@@ -80,41 +248,292 @@ impl RaftReplicator {
         &self.state
     }
 
+    pub fn leader_addr(&self) -> Option<String> {
+        let metrics = self.raft.metrics().borrow().clone();
+        let leader = metrics.current_leader?;
+        metrics
+            .membership_config
+            .membership()
+            .get_node(&leader)
+            .map(|node| node.addr.clone())
+    }
+
     pub fn network(&self) -> &HiveNetworkFactory {
         &self.network
     }
+
+    /// Human node ID registered for `raft_id`, or the number itself for a
+    /// node missing from the registry.
+    pub fn node_id_for(&self, raft_id: NodeIdType) -> NodeId {
+        self.state
+            .read(|state| state.node_id_of(raft_id).cloned())
+            .unwrap_or_else(|| raft_id.to_string())
+    }
+
+    pub fn members(&self) -> Vec<MemberStatus> {
+        let metrics = self.raft.metrics().borrow().clone();
+        let membership = metrics.membership_config.membership();
+        let last_index = metrics.last_log_index.unwrap_or(0);
+
+        membership
+            .nodes()
+            .map(|(id, node)| {
+                let matched_index = if *id == self.node_id && metrics.replication.is_some() {
+                    metrics.last_log_index
+                } else {
+                    metrics
+                        .replication
+                        .as_ref()
+                        .and_then(|replication| replication.get(id).copied().flatten())
+                        .map(|log_id| log_id.index)
+                };
+                let lag = metrics
+                    .replication
+                    .as_ref()
+                    .map(|_| last_index.saturating_sub(matched_index.unwrap_or(0)));
+
+                MemberStatus {
+                    node_id: self.node_id_for(*id),
+                    raft_id: *id,
+                    addr: node.addr.clone(),
+                    is_voter: membership.voter_ids().any(|voter| voter == *id),
+                    is_leader: metrics.current_leader == Some(*id),
+                    matched_index,
+                    lag,
+                }
+            })
+            .collect()
+    }
+
+    /// Turns a learner into a voter.
+    pub async fn promote_peer(&self, node_id: &str) -> Result<()> {
+        let raft_id = self.registered_raft_id(node_id)?;
+        self.raft
+            .change_membership(ChangeMembers::AddVoterIds(BTreeSet::from([raft_id])), false)
+            .await?;
+        info!("Promoted {} to voter", node_id);
+        Ok(())
+    }
+
+    /// Hands leadership to the voter `node_id` by having it start an
+    /// election, which it wins once its log is as current as ours. Only
+    /// the leader can do this, and only for a voter that has caught up.
+    ///
+    /// Voters refuse to elect anyone while their leader lease is valid, so
+    /// this node stops heartbeating and keeps asking the target to campaign
+    /// until the leases have lapsed and it wins. Elections are disabled
+    /// here meanwhile so this node cannot take leadership back.
+    pub async fn transfer_leadership(&self, node_id: &str) -> Result<()> {
+        let target = self.registered_raft_id(node_id)?;
+        if target == self.node_id {
+            return Ok(());
+        }
+        if !self.is_leader() {
+            return Err(anyhow!("Only the leader can transfer leadership"));
+        }
+
+        let member = self
+            .members()
+            .into_iter()
+            .find(|m| m.raft_id == target)
+            .ok_or_else(|| anyhow!("Node {} is not a member", node_id))?;
+        if !member.is_voter {
+            return Err(anyhow!("Node {} is a learner; promote it first", node_id));
+        }
+        if member.lag != Some(0) {
+            return Err(anyhow!(
+                "Node {} is still catching up (lag {:?})",
+                node_id,
+                member.lag
+            ));
+        }
+
+        let runtime = self.raft.runtime_config();
+        runtime.heartbeat(false);
+        runtime.elect(false);
+        let result = self.hand_over(node_id, target, &member.addr).await;
+        runtime.heartbeat(true);
+        runtime.elect(true);
+        result?;
+
+        info!("Transferred leadership to {}", node_id);
+        Ok(())
+    }
+
+    async fn hand_over(&self, node_id: &str, target: NodeIdType, addr: &str) -> Result<()> {
+        let url = format!("{}://{}/raft/campaign", self.network.scheme(), addr);
+        let deadline = tokio::time::Instant::now() + LEADER_TRANSFER_TIMEOUT;
+        loop {
+            let response = self
+                .network
+                .http_client()
+                .post(&url)
+                .json(&())
+                .send()
+                .await
+                .map_err(|e| anyhow!("Failed to reach {} at {}: {}", node_id, addr, e))?;
+            if !response.status().is_success() {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                return Err(anyhow!("{} refused to campaign ({}): {}", node_id, status, body));
+            }
+
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            let waited = self
+                .raft
+                .wait(Some(remaining.min(CAMPAIGN_INTERVAL)))
+                .metrics(|m| m.current_leader == Some(target), "leadership transfer")
+                .await;
+            match waited {
+                Ok(_) => return Ok(()),
+                Err(e) if remaining <= CAMPAIGN_INTERVAL => {
+                    return Err(anyhow!("Leadership did not move to {}: {}", node_id, e));
+                }
+                Err(_) => debug!("{} has not won its election yet; asking again", node_id),
+            }
+        }
+    }
+
+    fn registered_raft_id(&self, node_id: &str) -> Result<NodeIdType> {
+        self.state
+            .read(|state| state.raft_id_of(node_id))
+            .ok_or_else(|| anyhow!("Node {} has no Raft ID; it must be enrolled first", node_id))
+    }
+
+    /// Confirms leadership with a quorum and returns the index local state
+    /// must reach before a read is linearizable. Followers ask the leader.
+    pub async fn read_index(&self) -> Result<Option<u64>> {
+        for attempt in 0..LEADER_ATTEMPTS {
+            if attempt > 0 {
+                tokio::time::sleep(LEADER_RETRY_DELAY).await;
+            }
+
+            let leader = match self.raft.ensure_linearizable().await {
+                Ok(log_id) => return Ok(log_id.map(|id| id.index)),
+                Err(e) => match e.forward_to_leader() {
+                    Some(forward) => forward.leader_node.clone(),
+                    None => return Err(anyhow!("Linearizable read failed: {}", e)),
+                },
+            };
+
+            let Some(leader) = leader else {
+                debug!("No leader known, retrying read index");
+                continue;
+            };
+
+            let response: Option<ReadIndexResponse> =
+                self.post_to_leader(&leader.addr, "read_index", &()).await?;
+            match response {
+                Some(response) => return Ok(response.index),
+                None => debug!("{} is no longer leader, retrying read index", leader.addr),
+            }
+        }
+
+        Err(anyhow!("Linearizable read failed: no leader available"))
+    }
+
+    /// POSTs to a leader-only `/raft/` endpoint. Returns `Ok(None)` if the
+    /// target turned out not to be leader any more.
+    async fn post_to_leader<Req, Resp>(
+        &self,
+        leader_addr: &str,
+        path: &str,
+        req: &Req,
+    ) -> Result<Option<Resp>>
+    where
+        Req: Serialize + ?Sized,
+        Resp: DeserializeOwned,
+    {
+        let url = format!("{}://{}/raft/{}", self.network.scheme(), leader_addr, path);
+        let response = self
+            .network
+            .http_client()
+            .post(&url)
+            .json(req)
+            .send()
+            .await
+            .map_err(|e| anyhow!("Failed to reach leader at {}: {}", leader_addr, e))?;
+
+        let status = response.status();
+        if status == reqwest::StatusCode::MISDIRECTED_REQUEST {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow!("Leader at {} returned {}: {}", leader_addr, status, body));
+        }
+
+        Ok(Some(response.json().await?))
+    }
 }
 
 #[async_trait]
 impl Replicator for RaftReplicator {
-    async fn apply(&self, command: ClusterCommand) -> Result<()> {
-        let _resp = self
-            .raft
-            .client_write(command)
+    /// Writes through Raft, forwarding to the leader when this node is a
+    /// follower or learner.
+    async fn apply(&self, command: ClusterCommand) -> Result<CommandResult> {
+        for attempt in 0..LEADER_ATTEMPTS {
+            if attempt > 0 {
+                tokio::time::sleep(LEADER_RETRY_DELAY).await;
+            }
+
+            let leader = match self.raft.client_write(command.clone()).await {
+                Ok(response) => return Ok(response.data),
+                Err(e) => match e.forward_to_leader() {
+                    Some(forward) => forward.leader_node.clone(),
+                    None => return Err(anyhow!("Raft write failed: {}", e)),
+                },
+            };
+
+            let Some(leader) = leader else {
+                debug!("No leader known, retrying write");
+                continue;
+            };
+
+            let response: Option<CommandResult> =
+                self.post_to_leader(&leader.addr, "forward", &command).await?;
+            if let Some(result) = response {
+                return Ok(result);
+            }
+            debug!("{} is no longer leader, retrying write", leader.addr);
+        }
+
+        Err(anyhow!("Raft write failed: no leader available"))
+    }
+
+    async fn ensure_linearizable(&self) -> Result<()> {
+        let index = self.read_index().await?;
+        self.raft
+            .wait(Some(READ_APPLY_TIMEOUT))
+            .applied_index_at_least(index, "linearizable read")
             .await
-            .map_err(|e| anyhow!("Raft write failed: {}", e))?;
+            .map_err(|e| anyhow!("Timed out catching up for linearizable read: {}", e))?;
         Ok(())
     }
 
     fn snapshot(&self) -> ClusterView {
         let metrics = self.raft.metrics().borrow().clone();
-        let leader_id = metrics.current_leader.map(|id| id.to_string());
+        let leader_id = metrics.current_leader.map(|id| self.node_id_for(id));
         let term = metrics.current_term;
         self.state.to_cluster_view(leader_id, term)
     }
 
+    fn hive_state(&self) -> HiveState {
+        self.state.snapshot()
+    }
+
     fn is_leader(&self) -> bool {
         let metrics = self.raft.metrics().borrow().clone();
         metrics.current_leader == Some(self.node_id)
     }
 
     fn leader_id(&self) -> Option<NodeId> {
-        let metrics = self.raft.metrics().borrow().clone();
-        metrics.current_leader.map(|id| id.to_string())
+        let leader = self.raft.metrics().borrow().current_leader;
+        leader.map(|id| self.node_id_for(id))
     }
 
     async fn add_peer(&self, peer: PeerInfo) -> Result<()> {
-        let node_id: NodeIdType = peer.node_id.parse()?;
+        let node_id = self.registered_raft_id(&peer.node_id)?;
         let node = HiveNode {
             addr: peer.addr.clone(),
             hostname: peer.node_id.clone(),
@@ -122,16 +541,39 @@ impl Replicator for RaftReplicator {
 
         self.network.register_node(node_id, peer.addr.clone());
 
+        // Voters start as learners so they have caught up on the log before
+        // they count towards the quorum.
+        self.raft.add_learner(node_id, node, true).await?;
         if peer.is_voter {
-            let mut members = BTreeMap::new();
-            members.insert(node_id, node);
             self.raft
-                .change_membership(ChangeMembers::AddNodes(members), false)
+                .change_membership(ChangeMembers::AddVoterIds(BTreeSet::from([node_id])), false)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn remove_peer(&self, node_id: &str) -> Result<()> {
+        let raft_id = self.registered_raft_id(node_id)?;
+        if raft_id == self.node_id {
+            return Err(anyhow!("Refusing to remove this node from its own cluster"));
+        }
+
+        let membership = self.raft.metrics().borrow().membership_config.clone();
+        let membership = membership.membership();
+        let ids = BTreeSet::from([raft_id]);
+
+        if membership.voter_ids().any(|id| id == raft_id) {
+            self.raft
+                .change_membership(ChangeMembers::RemoveVoters(ids), false)
+                .await?;
+        } else if membership.learner_ids().any(|id| id == raft_id) {
+            self.raft
+                .change_membership(ChangeMembers::RemoveNodes(ids), false)
                 .await?;
-        } else {
-            self.raft.add_learner(node_id, node, true).await?;
         }
 
+        info!("Removed {} from Raft membership", node_id);
         Ok(())
     }
 }
@@ -1,8 +1,10 @@
+use crate::replicator::watch::{AppliedCommand, WATCH_CAPACITY};
 use crate::types::*;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::{Arc, RwLock};
+use tokio::sync::{broadcast, watch};
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct HiveState {
@@ -10,6 +12,30 @@ pub struct HiveState {
     pub tasks: HashMap<TaskId, Task>,
     pub attachments: HashMap<AttachmentId, Attachment>,
     pub goals: HashMap<GoalId, Goal>,
+    #[serde(default)]
+    pub tokens: HashMap<String, IssuedToken>,
+    #[serde(default)]
+    pub enrollments: HashMap<NodeId, EnrolledNode>,
+    #[serde(default)]
+    pub revoked_certificates: HashMap<String, RevokedCertificate>,
+    #[serde(default)]
+    pub crl: Option<SignedCrl>,
+    #[serde(default)]
+    pub trust_bundle: Option<TrustBundle>,
+    /// Raft IDs allocated to nodes, keyed by human node ID.
+    #[serde(default)]
+    pub raft_ids: HashMap<NodeId, u64>,
+    /// Replicated key-value namespace. Expired entries stay until the next
+    /// `Kv*` command removes them, so reads must skip them.
+    #[serde(default)]
+    pub kv: BTreeMap<String, KvEntry>,
+    /// Leases by lock key. Like `kv`, expired leases linger until the next
+    /// lock command.
+    #[serde(default)]
+    pub locks: BTreeMap<String, Lease>,
+    /// Incremented by every command that changes state.
+    #[serde(default)]
+    pub revision: u64,
     pub last_applied_index: u64,
 }
 
@@ -18,55 +44,361 @@ impl HiveState {
         Self::default()
     }
 
-    pub fn apply(&mut self, command: &ClusterCommand) {
+    pub fn apply(&mut self, command: &ClusterCommand) -> CommandResult {
+        self.apply_as_of(command, Utc::now())
+    }
+
+    /// Applies `command` as if at `now`, which replaying history uses to
+    /// reproduce the timestamps the command was first applied with.
+    pub fn apply_as_of(&mut self, command: &ClusterCommand, now: DateTime<Utc>) -> CommandResult {
+        let revision = self.revision + 1;
+        let result = self.apply_at(command, revision, now);
+        if result.is_applied() {
+            self.revision = revision;
+        }
+        result
+    }
+
+    /// Applies `command` from log entry `index` as of `now`. Leases taken by
+    /// the command get `index` as their fencing token.
+    pub fn apply_entry(
+        &mut self,
+        index: u64,
+        command: &ClusterCommand,
+        now: DateTime<Utc>,
+    ) -> CommandResult {
+        self.last_applied_index = index;
+        self.apply_as_of(command, now)
+    }
+
+    fn apply_at(
+        &mut self,
+        command: &ClusterCommand,
+        revision: u64,
+        now: DateTime<Utc>,
+    ) -> CommandResult {
         match command {
             ClusterCommand::RegisterNode(status) => {
-                self.nodes.insert(status.node_id.clone(), status.clone());
+                let mut status = status.clone();
+                status.revision = revision;
+                self.nodes.insert(status.node_id.clone(), status);
             }
             ClusterCommand::UpdateNodeHealth {
                 node_id,
                 health,
                 metrics,
+                expected_revision,
             } => {
-                if let Some(node) = self.nodes.get_mut(node_id) {
-                    node.health = health.clone();
-                    node.cpu_usage = metrics.cpu_usage;
-                    node.memory_usage = metrics.memory_usage;
-                    node.disk_usage = metrics.disk_usage;
-                    node.last_heartbeat = Utc::now();
+                let Some(node) = self.nodes.get_mut(node_id) else {
+                    return CommandResult::NotFound;
+                };
+                if let Err(result) = check_revision(Some(node.revision), *expected_revision) {
+                    return result;
                 }
+                node.revision = revision;
+                node.health = health.clone();
+                node.cpu_usage = metrics.cpu_usage;
+                node.memory_usage = metrics.memory_usage;
+                node.disk_usage = metrics.disk_usage;
+                node.last_heartbeat = now;
             }
             ClusterCommand::RemoveNode { node_id } => {
-                self.nodes.remove(node_id);
+                if self.nodes.remove(node_id).is_none() {
+                    return CommandResult::NotFound;
+                }
             }
-            ClusterCommand::PutTask(task) => {
-                self.tasks.insert(task.id.clone(), task.clone());
+            ClusterCommand::PutTask(task) | ClusterCommand::PutTaskIf { task, .. } => {
+                let current = self.tasks.get(&task.id).map(|t| t.revision);
+                if let Err(result) = check_revision(current, command.expected_revision()) {
+                    return result;
+                }
+                let mut task = task.clone();
+                task.revision = revision;
+                self.tasks.insert(task.id.clone(), task);
             }
             ClusterCommand::UpdateTaskStatus {
                 task_id,
                 status,
                 result,
+                expected_revision,
             } => {
-                if let Some(task) = self.tasks.get_mut(task_id) {
-                    task.status = status.clone();
-                    task.result = result.clone();
-                    task.updated_at = Utc::now();
+                let Some(task) = self.tasks.get_mut(task_id) else {
+                    return CommandResult::NotFound;
+                };
+                if let Err(result) = check_revision(Some(task.revision), *expected_revision) {
+                    return result;
                 }
+                task.revision = revision;
+                task.status = status.clone();
+                task.result = result.clone();
+                task.updated_at = now;
             }
-            ClusterCommand::PutAttachment(attachment) => {
-                self.attachments
-                    .insert(attachment.id.clone(), attachment.clone());
+            ClusterCommand::PutAttachment(attachment) | ClusterCommand::PutAttachmentIf { attachment, .. } => {
+                let current = self.attachments.get(&attachment.id).map(|a| a.revision);
+                if let Err(result) = check_revision(current, command.expected_revision()) {
+                    return result;
+                }
+                let mut attachment = attachment.clone();
+                attachment.revision = revision;
+                self.attachments.insert(attachment.id.clone(), attachment);
             }
             ClusterCommand::RemoveAttachment { attachment_id } => {
-                self.attachments.remove(attachment_id);
+                if self.attachments.remove(attachment_id).is_none() {
+                    return CommandResult::NotFound;
+                }
             }
-            ClusterCommand::PutGoal(goal) => {
-                self.goals.insert(goal.id.clone(), goal.clone());
+            ClusterCommand::PutGoal(goal) | ClusterCommand::PutGoalIf { goal, .. } => {
+                let current = self.goals.get(&goal.id).map(|g| g.revision);
+                if let Err(result) = check_revision(current, command.expected_revision()) {
+                    return result;
+                }
+                let mut goal = goal.clone();
+                goal.revision = revision;
+                self.goals.insert(goal.id.clone(), goal);
             }
             ClusterCommand::RemoveGoal { goal_id } => {
-                self.goals.remove(goal_id);
+                if self.goals.remove(goal_id).is_none() {
+                    return CommandResult::NotFound;
+                }
+            }
+            ClusterCommand::IssueToken(token) => {
+                self.tokens.retain(|_, t| t.expires_at > token.issued_at);
+                self.tokens.insert(token.token_hash.clone(), token.clone());
+            }
+            ClusterCommand::ConsumeToken {
+                token_hash,
+                node_id,
+                request_id,
+                consumed_at,
+            } => {
+                let Some(token) = self.tokens.get_mut(token_hash) else {
+                    return CommandResult::NotFound;
+                };
+                if token.consumed.is_some() || *consumed_at > token.expires_at {
+                    return CommandResult::Conflict;
+                }
+                token.consumed = Some(TokenConsumption {
+                    node_id: node_id.clone(),
+                    request_id: request_id.clone(),
+                    consumed_at: *consumed_at,
+                });
+            }
+            ClusterCommand::RecordEnrollment(node) => {
+                self.enrollments.insert(node.node_id.clone(), node.clone());
+            }
+            ClusterCommand::RevokeNode {
+                node_id,
+                revoked,
+                crl,
+            } => {
+                // The CRL was signed over the revocations the sender saw, so
+                // it only covers this one if nothing was revoked since.
+                if crl.number != self.crl.as_ref().map_or(0, |c| c.number) + 1 {
+                    return CommandResult::Conflict;
+                }
+                self.nodes.remove(node_id);
+                self.enrollments.remove(node_id);
+                for cert in revoked {
+                    self.revoked_certificates
+                        .insert(cert.serial.clone(), cert.clone());
+                }
+                self.crl = Some(crl.clone());
+            }
+            ClusterCommand::SetTrustBundle(bundle) => {
+                if self
+                    .trust_bundle
+                    .as_ref()
+                    .is_some_and(|current| bundle.version <= current.version)
+                {
+                    return CommandResult::Conflict;
+                }
+                self.trust_bundle = Some(bundle.clone());
+            }
+            ClusterCommand::AssignRaftId { node_id, raft_id } => {
+                let current = self.raft_ids.get(node_id);
+                let owner = self.node_id_of(*raft_id);
+                if current.is_some_and(|id| id != raft_id)
+                    || owner.is_some_and(|owner| owner != node_id)
+                {
+                    return CommandResult::Conflict;
+                }
+                self.raft_ids.insert(node_id.clone(), *raft_id);
+            }
+            ClusterCommand::KvPut {
+                key,
+                value,
+                ttl_secs,
+                written_at,
+            } => {
+                let expires_at = match kv_expiry(*written_at, *ttl_secs) {
+                    Ok(expires_at) => expires_at,
+                    Err(result) => return result,
+                };
+                self.kv.retain(|_, e| !e.is_expired(*written_at));
+                self.put_kv(key, value, expires_at, revision, *written_at);
+            }
+            ClusterCommand::KvDelete {
+                key,
+                expected_revision,
+                written_at,
+            } => {
+                self.kv.retain(|_, e| !e.is_expired(*written_at));
+                let Some(current) = self.kv.get(key).map(|e| e.revision) else {
+                    return CommandResult::NotFound;
+                };
+                if let Err(result) = check_revision(Some(current), *expected_revision) {
+                    return result;
+                }
+                self.kv.remove(key);
+            }
+            ClusterCommand::KvCas {
+                key,
+                value,
+                ttl_secs,
+                expected_revision,
+                written_at,
+            } => {
+                let expires_at = match kv_expiry(*written_at, *ttl_secs) {
+                    Ok(expires_at) => expires_at,
+                    Err(result) => return result,
+                };
+                self.kv.retain(|_, e| !e.is_expired(*written_at));
+                let current = self.kv.get(key).map(|e| e.revision);
+                if let Err(result) = check_revision(current, Some(*expected_revision)) {
+                    return result;
+                }
+                self.put_kv(key, value, expires_at, revision, *written_at);
+            }
+            ClusterCommand::AcquireLock {
+                key,
+                holder,
+                ttl_secs,
+                requested_at,
+            } => {
+                let Some(expires_at) = expiry_after(*requested_at, *ttl_secs) else {
+                    return CommandResult::Conflict;
+                };
+                self.locks.retain(|_, l| !l.is_expired(*requested_at));
+                let lease = match self.locks.get_mut(key) {
+                    Some(lease) if lease.holder != *holder => return CommandResult::Conflict,
+                    Some(lease) => {
+                        lease.expires_at = expires_at;
+                        lease.revision = revision;
+                        lease.clone()
+                    }
+                    None => {
+                        let lease = Lease {
+                            key: key.clone(),
+                            holder: holder.clone(),
+                            fencing_token: self.last_applied_index,
+                            acquired_at: *requested_at,
+                            expires_at,
+                            revision,
+                        };
+                        self.locks.insert(key.clone(), lease.clone());
+                        lease
+                    }
+                };
+                return CommandResult::Leased(lease);
+            }
+            ClusterCommand::RenewLock {
+                key,
+                holder,
+                ttl_secs,
+                requested_at,
+            } => {
+                let Some(expires_at) = expiry_after(*requested_at, *ttl_secs) else {
+                    return CommandResult::Conflict;
+                };
+                self.locks.retain(|_, l| !l.is_expired(*requested_at));
+                let Some(lease) = self.locks.get_mut(key) else {
+                    return CommandResult::NotFound;
+                };
+                if lease.holder != *holder {
+                    return CommandResult::Conflict;
+                }
+                lease.expires_at = expires_at;
+                lease.revision = revision;
+                return CommandResult::Leased(lease.clone());
+            }
+            ClusterCommand::ReleaseLock { key, holder } => {
+                let Some(lease) = self.locks.get(key) else {
+                    return CommandResult::NotFound;
+                };
+                if lease.holder != *holder {
+                    return CommandResult::Conflict;
+                }
+                self.locks.remove(key);
             }
         }
+
+        CommandResult::Applied { revision }
+    }
+
+    fn put_kv(
+        &mut self,
+        key: &str,
+        value: &str,
+        expires_at: Option<DateTime<Utc>>,
+        revision: u64,
+        written_at: DateTime<Utc>,
+    ) {
+        self.kv.insert(
+            key.to_string(),
+            KvEntry {
+                key: key.to_string(),
+                value: value.to_string(),
+                revision,
+                updated_at: written_at,
+                expires_at,
+            },
+        );
+    }
+
+    /// The entry for `key` unless it has expired by `now`.
+    pub fn kv_get(&self, key: &str, now: DateTime<Utc>) -> Option<&KvEntry> {
+        self.kv.get(key).filter(|e| !e.is_expired(now))
+    }
+
+    /// Unexpired entries whose key starts with `prefix`, in key order.
+    pub fn kv_list(&self, prefix: &str, now: DateTime<Utc>) -> Vec<KvEntry> {
+        self.kv
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(_, e)| e)
+            .filter(|e| !e.is_expired(now))
+            .cloned()
+            .collect()
+    }
+
+    /// The lease on `key` unless it has expired by `now`.
+    pub fn lease(&self, key: &str, now: DateTime<Utc>) -> Option<&Lease> {
+        self.locks.get(key).filter(|l| !l.is_expired(now))
+    }
+
+    /// Unexpired leases, in key order.
+    pub fn leases(&self, now: DateTime<Utc>) -> Vec<Lease> {
+        self.locks
+            .values()
+            .filter(|l| !l.is_expired(now))
+            .cloned()
+            .collect()
+    }
+
+    pub fn raft_id_of(&self, node_id: &str) -> Option<u64> {
+        self.raft_ids.get(node_id).copied()
+    }
+
+    pub fn node_id_of(&self, raft_id: u64) -> Option<&NodeId> {
+        self.raft_ids
+            .iter()
+            .find(|(_, id)| **id == raft_id)
+            .map(|(node_id, _)| node_id)
+    }
+
+    /// Lowest Raft ID above every one allocated so far. IDs start at 1.
+    pub fn next_raft_id(&self) -> u64 {
+        self.raft_ids.values().max().map_or(1, |max| max + 1)
     }
 
     pub fn to_cluster_view(&self, leader_id: Option<NodeId>, term: u64) -> ClusterView {
@@ -75,27 +407,109 @@ impl HiveState {
             tasks: self.tasks.values().cloned().collect(),
             attachments: self.attachments.values().cloned().collect(),
             goals: self.goals.values().cloned().collect(),
+            kv: self.kv_list("", Utc::now()),
             leader_id,
             term,
         }
     }
 }
 
+/// Compares an entry's current revision (`None` if it does not exist) with
+/// the revision a command expects.
+fn check_revision(current: Option<u64>, expected: Option<u64>) -> Result<(), CommandResult> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+    match current {
+        Some(current) if current == expected => Ok(()),
+        None if expected == 0 => Ok(()),
+        None => Err(CommandResult::NotFound),
+        Some(_) => Err(CommandResult::Conflict),
+    }
+}
+
+/// When a key written at `written_at` with `ttl_secs` expires. A TTL too
+/// large to represent rejects the command rather than panicking every node
+/// that applies it.
+fn kv_expiry(
+    written_at: DateTime<Utc>,
+    ttl_secs: Option<u64>,
+) -> Result<Option<DateTime<Utc>>, CommandResult> {
+    ttl_secs
+        .map(|ttl| expiry_after(written_at, ttl).ok_or(CommandResult::Conflict))
+        .transpose()
+}
+
+/// `HiveState` shared between the Raft state machine and readers. Every
+/// applied command is also sent to subscribers.
 #[derive(Clone)]
 pub struct SharedState {
     inner: Arc<RwLock<HiveState>>,
+    applied: broadcast::Sender<AppliedCommand>,
+    restored: Arc<watch::Sender<()>>,
 }
 
 impl SharedState {
     pub fn new() -> Self {
         Self {
             inner: Arc::new(RwLock::new(HiveState::new())),
+            applied: broadcast::channel(WATCH_CAPACITY).0,
+            restored: Arc::new(watch::channel(()).0),
         }
     }
 
-    pub fn apply(&self, command: &ClusterCommand) {
+    /// Applies `command` as the entry after the last one applied.
+    pub fn apply(&self, command: &ClusterCommand) -> CommandResult {
+        let mut state = self.inner.write().unwrap();
+        let index = state.last_applied_index + 1;
+        self.apply_locked(&mut state, index, command, Utc::now())
+    }
+
+    /// Applies `command` from log entry `index` as of `now`.
+    pub fn apply_entry(
+        &self,
+        index: u64,
+        command: &ClusterCommand,
+        now: DateTime<Utc>,
+    ) -> CommandResult {
         let mut state = self.inner.write().unwrap();
-        state.apply(command);
+        self.apply_locked(&mut state, index, command, now)
+    }
+
+    /// Sends the command to subscribers while `state` is still locked, so
+    /// they see commands in log order.
+    fn apply_locked(
+        &self,
+        state: &mut HiveState,
+        index: u64,
+        command: &ClusterCommand,
+        now: DateTime<Utc>,
+    ) -> CommandResult {
+        let result = state.apply_entry(index, command, now);
+        // Nobody may be subscribed, which is fine.
+        let _ = self.applied.send(AppliedCommand {
+            index,
+            applied_at: now,
+            command: command.clone(),
+            result: result.clone(),
+        });
+        result
+    }
+
+    /// Commands applied from now on. A receiver that falls more than
+    /// `WATCH_CAPACITY` commands behind gets `RecvError::Lagged`.
+    pub fn subscribe(&self) -> broadcast::Receiver<AppliedCommand> {
+        self.applied.subscribe()
+    }
+
+    /// Changes whenever the whole state is replaced from a snapshot, which
+    /// sends no applied commands.
+    pub fn watch_restores(&self) -> watch::Receiver<()> {
+        self.restored.subscribe()
+    }
+
+    pub fn read<T>(&self, f: impl FnOnce(&HiveState) -> T) -> T {
+        f(&self.inner.read().unwrap())
     }
 
     pub fn snapshot(&self) -> HiveState {
@@ -110,12 +524,17 @@ impl SharedState {
         self.inner.write().unwrap().last_applied_index = index;
     }
 
+    pub fn revision(&self) -> u64 {
+        self.inner.read().unwrap().revision
+    }
+
     pub fn last_applied(&self) -> u64 {
         self.inner.read().unwrap().last_applied_index
     }
 
     pub fn restore(&self, state: HiveState) {
         *self.inner.write().unwrap() = state;
+        self.restored.send_replace(());
     }
 }
 
@@ -1,24 +1,32 @@
+use crate::replicator::backend::{is_encrypted, StorageBackend};
+use crate::replicator::codec::{self, RecordError, FORMAT_VERSION};
+use crate::replicator::history::{HistoryError, HistoryStore};
+use crate::replicator::watch::AppliedCommand;
 use crate::replicator::state_machine::{HiveState, SharedState};
-use crate::types::ClusterCommand;
-use anyhow::Result;
+use crate::replicator::v0;
+use crate::types::{ClusterCommand, CommandResult};
+use anyhow::{Context, Result};
+use chrono::Utc;
 use openraft::storage::{Adaptor, LogState, RaftStorage};
 use openraft::{
-    Entry, EntryPayload, LogId, OptionalSend, RaftLogReader, RaftSnapshotBuilder, Snapshot,
-    SnapshotMeta, StorageError, StoredMembership, Vote,
+    Entry, EntryPayload, ErrorSubject, ErrorVerb, LogId, Membership, OptionalSend, RaftLogReader,
+    RaftSnapshotBuilder, Snapshot, SnapshotMeta, StorageError, StoredMembership, Vote,
 };
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Debug;
 use std::io::Cursor;
 use std::ops::RangeBounds;
-use std::path::Path;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
 
 pub type NodeIdType = u64;
 
 openraft::declare_raft_types!(
     pub TypeConfig:
         D = ClusterCommand,
-        R = (),
+        R = CommandResult,
         Node = HiveNode,
 );
 
@@ -34,124 +42,403 @@ impl std::fmt::Display for HiveNode {
     }
 }
 
+
+const KEY_FORMAT_VERSION: &[u8] = b"format_version";
 const KEY_VOTE: &[u8] = b"vote";
 const KEY_LAST_PURGED: &[u8] = b"last_purged";
-const KEY_LAST_APPLIED: &[u8] = b"last_applied";
-const KEY_MEMBERSHIP: &[u8] = b"membership";
+const KEY_COMMITTED: &[u8] = b"committed";
 const KEY_SNAPSHOT_IDX: &[u8] = b"snapshot_idx";
-const KEY_STATE_SNAPSHOT: &[u8] = b"state_snapshot";
+const KEY_CURRENT_SNAPSHOT_META: &[u8] = b"current_snapshot_meta";
+const KEY_CURRENT_SNAPSHOT_DATA: &[u8] = b"current_snapshot_data";
+
+// Written by versions that saved the whole state after every apply.
+const KEY_LEGACY_LAST_APPLIED: &[u8] = b"last_applied";
+const KEY_LEGACY_MEMBERSHIP: &[u8] = b"membership";
+const KEY_LEGACY_STATE: &[u8] = b"state_snapshot";
+
+/// Where the in-memory state machine stands in the log. Not persisted: on
+/// start it is reset to the current snapshot and openraft re-applies the
+/// committed entries that follow it.
+#[derive(Default)]
+struct AppliedLog {
+    last_applied: Option<LogId<NodeIdType>>,
+    membership: StoredMembership<NodeIdType, HiveNode>,
+}
 
-pub struct SledStorage {
-    db: sled::Db,
-    log_tree: sled::Tree,
-    meta_tree: sled::Tree,
+/// openraft storage over a `StorageBackend`. Handles returned by
+/// `get_log_reader` and `get_snapshot_builder` are clones sharing the same
+/// backend and locks.
+#[derive(Clone)]
+pub struct HiveStorage {
+    backend: Arc<dyn StorageBackend>,
     state: SharedState,
-    snapshot_idx: Mutex<u64>,
+    snapshot_idx: Arc<Mutex<u64>>,
+    /// Held while applying a batch, so a snapshot built concurrently sees
+    /// state and last-applied log id from the same point.
+    applied: Arc<Mutex<AppliedLog>>,
+    history: Option<Arc<HistoryStore>>,
 }
 
-impl SledStorage {
-    pub fn new<P: AsRef<Path>>(path: P, state: SharedState) -> Result<Self> {
-        let db = sled::open(path)?;
-        let log_tree = db.open_tree("raft_log")?;
-        let meta_tree = db.open_tree("raft_meta")?;
-
-        let snapshot_idx = meta_tree
-            .get(KEY_SNAPSHOT_IDX)?
-            .map(|v| bincode::deserialize(&v).unwrap_or(0))
-            .unwrap_or(0);
-
-        if let Some(state_data) = meta_tree.get(KEY_STATE_SNAPSHOT)? {
-            if let Ok(hive_state) = serde_json::from_slice::<HiveState>(&state_data) {
-                state.restore(hive_state);
-                tracing::info!("Restored state from snapshot");
-            }
-        }
+fn storage_error(
+    subject: ErrorSubject<NodeIdType>,
+    verb: ErrorVerb,
+    e: impl Into<std::io::Error>,
+) -> StorageError<NodeIdType> {
+    StorageError::from_io_error(subject, verb, e.into())
+}
+
+/// Cluster state as of one applied log entry, as served by the leader's
+/// backup endpoint. `state` is what `SharedState::restore` takes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateBackup {
+    pub state: HiveState,
+    pub membership: StoredMembership<NodeIdType, HiveNode>,
+    pub last_log_id: Option<LogId<NodeIdType>>,
+}
+
+impl StateBackup {
+    pub fn encode(&self) -> Result<Vec<u8>, RecordError> {
+        codec::encode(self)
+    }
 
-        Ok(Self {
-            db,
-            log_tree,
-            meta_tree,
+    pub fn decode(data: &[u8]) -> Result<Self, RecordError> {
+        codec::decode(data)
+    }
+}
+
+/// Snapshot data as built by `build_snapshot`. Leaders that predate the
+/// format header send plain JSON.
+fn decode_snapshot_data(data: &[u8]) -> Result<HiveState, RecordError> {
+    if codec::has_header(data) {
+        codec::decode(data)
+    } else {
+        Ok(serde_json::from_slice(data)?)
+    }
+}
+
+impl HiveStorage {
+    /// Opens Raft storage on `backend`, migrating its records and restoring
+    /// `state` from the current snapshot.
+    pub fn new(backend: Arc<dyn StorageBackend>, state: SharedState) -> Result<Self> {
+        let storage = Self {
+            backend,
             state,
-            snapshot_idx: Mutex::new(snapshot_idx),
-        })
+            snapshot_idx: Arc::new(Mutex::new(0)),
+            applied: Arc::new(Mutex::new(AppliedLog::default())),
+            history: None,
+        };
+        storage.migrate_format()?;
+        storage.migrate_legacy_state()?;
+
+        *storage.snapshot_idx.lock().unwrap() = storage.read_meta(KEY_SNAPSHOT_IDX)?.unwrap_or(0);
+        storage.restore_current_snapshot()?;
+        Ok(storage)
     }
 
-    fn log_key(index: u64) -> [u8; 8] {
-        index.to_be_bytes()
+    /// Records every applied entry in `history`. History that has no
+    /// checkpoint yet starts from the current snapshot.
+    pub fn with_history(mut self, history: Arc<HistoryStore>) -> Result<Self> {
+        if !history.has_checkpoints() {
+            let backup = self.backup();
+            if let Some(log_id) = backup.last_log_id {
+                history.checkpoint(log_id, &backup.state)?;
+            }
+        }
+        self.history = Some(history);
+        Ok(self)
     }
 
-    fn get_vote(&self) -> Option<Vote<NodeIdType>> {
-        self.meta_tree
-            .get(KEY_VOTE)
-            .ok()
-            .flatten()
-            .and_then(|v| bincode::deserialize(&v).ok())
+    pub fn history(&self) -> Option<&Arc<HistoryStore>> {
+        self.history.as_ref()
+    }
+
+    /// Commands applied from log index `index` on, read from history, and
+    /// a receiver for the commands applied after them. Both are taken under
+    /// the apply lock, so no command is missed or sent twice.
+    pub fn watch_from(
+        &self,
+        index: u64,
+    ) -> Result<(Vec<AppliedCommand>, broadcast::Receiver<AppliedCommand>), HistoryError> {
+        let _applied = self.applied.lock().unwrap();
+        let history = self.history.as_ref().ok_or(HistoryError::Disabled)?;
+        let receiver = self.state.subscribe();
+        let backlog = history
+            .since(index)?
+            .into_iter()
+            .filter_map(AppliedCommand::from_history)
+            .collect();
+        Ok((backlog, receiver))
     }
 
-    fn set_vote(&self, vote: &Vote<NodeIdType>) -> Result<(), sled::Error> {
-        let data = bincode::serialize(vote).unwrap();
-        self.meta_tree.insert(KEY_VOTE, data)?;
-        self.meta_tree.flush()?;
+    /// Brings records written by an older format version up to
+    /// `FORMAT_VERSION`, refusing storage written by a newer one.
+    fn migrate_format(&self) -> Result<()> {
+        let record = self.backend.get_meta(KEY_FORMAT_VERSION)?;
+        if record.as_deref().is_some_and(is_encrypted) {
+            anyhow::bail!("Raft storage is encrypted, but no key is set in [storage.encryption]");
+        }
+        let version = record.and_then(|v| v.first().copied()).unwrap_or(0);
+        if version > FORMAT_VERSION {
+            anyhow::bail!(
+                "Raft storage has format version {}, this build supports up to {}",
+                version,
+                FORMAT_VERSION
+            );
+        }
+
+        if version == 0 {
+            self.migrate_from_v0()?;
+        }
+
+        if version != FORMAT_VERSION {
+            self.backend
+                .write_meta(vec![(KEY_FORMAT_VERSION.to_vec(), Some(vec![FORMAT_VERSION]))], true)?;
+        }
         Ok(())
     }
 
-    fn get_last_purged(&self) -> Option<LogId<NodeIdType>> {
-        self.meta_tree
-            .get(KEY_LAST_PURGED)
-            .ok()
-            .flatten()
-            .and_then(|v| bincode::deserialize(&v).ok())
+    /// Version 0 stored records without a header: log entries, snapshot
+    /// metadata and snapshot data as JSON, everything else as bincode.
+    /// Records that already carry a header are left alone, so an
+    /// interrupted migration can simply run again.
+    fn migrate_from_v0(&self) -> Result<()> {
+        let mut migrated = Vec::new();
+        for (index, record) in self.backend.read_log(0, None)? {
+            if codec::has_header(&record) {
+                continue;
+            }
+            let entry = v0::decode_entry(&record)
+                .with_context(|| format!("Cannot migrate log entry {}", index))?;
+            migrated.push((index, codec::encode(&entry)?));
+        }
+        let entries = migrated.len();
+        if entries > 0 {
+            self.backend.append_log(migrated)?;
+        }
+
+        self.migrate_meta_from_v0(KEY_VOTE, |r| {
+            Ok(bincode::deserialize::<Vote<NodeIdType>>(r)?)
+        })?;
+        self.migrate_meta_from_v0(KEY_LAST_PURGED, |r| {
+            Ok(bincode::deserialize::<LogId<NodeIdType>>(r)?)
+        })?;
+        self.migrate_meta_from_v0(KEY_COMMITTED, |r| {
+            Ok(bincode::deserialize::<Option<LogId<NodeIdType>>>(r)?)
+        })?;
+        self.migrate_meta_from_v0(KEY_SNAPSHOT_IDX, |r| Ok(bincode::deserialize::<u64>(r)?))?;
+        self.migrate_meta_from_v0(KEY_CURRENT_SNAPSHOT_META, |r| {
+            Ok(serde_json::from_slice::<SnapshotMeta<NodeIdType, HiveNode>>(r)?)
+        })?;
+        self.migrate_meta_from_v0(KEY_CURRENT_SNAPSHOT_DATA, |r| {
+            Ok(serde_json::from_slice::<HiveState>(r)?)
+        })?;
+
+        if entries > 0 {
+            tracing::info!(
+                "Migrated {} log entries to storage format version {}",
+                entries,
+                FORMAT_VERSION
+            );
+        }
+        Ok(())
     }
 
-    fn set_last_purged(&self, log_id: &LogId<NodeIdType>) -> Result<(), sled::Error> {
-        let data = bincode::serialize(log_id).unwrap();
-        self.meta_tree.insert(KEY_LAST_PURGED, data)?;
+    fn migrate_meta_from_v0<T: Serialize>(
+        &self,
+        key: &[u8],
+        decode: impl FnOnce(&[u8]) -> Result<T>,
+    ) -> Result<()> {
+        let Some(record) = self.backend.get_meta(key)? else {
+            return Ok(());
+        };
+        if codec::has_header(&record) {
+            return Ok(());
+        }
+
+        let value = decode(&record)
+            .with_context(|| format!("Cannot migrate {}", String::from_utf8_lossy(key)))?;
+        self.backend
+            .write_meta(vec![(key.to_vec(), Some(codec::encode(&value)?))], true)?;
         Ok(())
     }
 
-    fn get_last_applied(&self) -> Option<LogId<NodeIdType>> {
-        self.meta_tree
-            .get(KEY_LAST_APPLIED)
-            .ok()
-            .flatten()
-            .and_then(|v| bincode::deserialize(&v).ok())
+    /// Adopts the state copy written by earlier versions as the current
+    /// snapshot, since their log may already be purged past it.
+    fn migrate_legacy_state(&self) -> Result<()> {
+        let Some(data) = self.backend.get_meta(KEY_LEGACY_STATE)? else {
+            return Ok(());
+        };
+
+        if self.backend.get_meta(KEY_CURRENT_SNAPSHOT_META)?.is_none() {
+            let hive_state: HiveState =
+                serde_json::from_slice(&data).context("Cannot migrate saved state")?;
+            let last_log_id: Option<LogId<NodeIdType>> = self
+                .backend
+                .get_meta(KEY_LEGACY_LAST_APPLIED)?
+                .map(|v| bincode::deserialize(&v))
+                .transpose()
+                .context("Cannot migrate last applied log id")?;
+            let last_membership = self
+                .backend
+                .get_meta(KEY_LEGACY_MEMBERSHIP)?
+                .map(|v| serde_json::from_slice(&v))
+                .transpose()
+                .context("Cannot migrate membership")?
+                .unwrap_or_default();
+            let meta = SnapshotMeta {
+                last_log_id,
+                last_membership,
+                snapshot_id: format!("legacy-{}", last_log_id.map(|l| l.index).unwrap_or(0)),
+            };
+            self.save_current_snapshot(&meta, &codec::encode(&hive_state)?)?;
+            tracing::info!("Migrated saved state to snapshot {}", meta.snapshot_id);
+        }
+
+        self.backend.write_meta(
+            vec![
+                (KEY_LEGACY_STATE.to_vec(), None),
+                (KEY_LEGACY_LAST_APPLIED.to_vec(), None),
+                (KEY_LEGACY_MEMBERSHIP.to_vec(), None),
+            ],
+            true,
+        )?;
+        Ok(())
     }
 
-    fn set_last_applied(&self, log_id: &LogId<NodeIdType>) -> Result<(), sled::Error> {
-        let data = bincode::serialize(log_id).unwrap();
-        self.meta_tree.insert(KEY_LAST_APPLIED, data)?;
+    fn restore_current_snapshot(&self) -> Result<()> {
+        let Some(snapshot) = self.load_current_snapshot()? else {
+            return Ok(());
+        };
+
+        let mut hive_state = decode_snapshot_data(snapshot.snapshot.get_ref())
+            .with_context(|| format!("Cannot read snapshot {}", snapshot.meta.snapshot_id))?;
+        hive_state.last_applied_index = snapshot.meta.last_log_id.map_or(0, |l| l.index);
+        self.state.restore(hive_state);
+        *self.applied.lock().unwrap() = AppliedLog {
+            last_applied: snapshot.meta.last_log_id,
+            membership: snapshot.meta.last_membership.clone(),
+        };
+        tracing::info!("Restored state from snapshot {}", snapshot.meta.snapshot_id);
         Ok(())
     }
 
-    fn get_membership(&self) -> StoredMembership<NodeIdType, HiveNode> {
-        self.meta_tree
-            .get(KEY_MEMBERSHIP)
-            .ok()
-            .flatten()
-            .and_then(|v| serde_json::from_slice(&v).ok())
-            .unwrap_or_default()
+    fn decode_entry(index: u64, record: &[u8]) -> Result<Entry<TypeConfig>, std::io::Error> {
+        codec::decode(record).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("log entry {}: {}", index, e),
+            )
+        })
     }
 
-    fn set_membership(&self, membership: &StoredMembership<NodeIdType, HiveNode>) -> Result<(), sled::Error> {
-        let data = serde_json::to_vec(membership).unwrap();
-        self.meta_tree.insert(KEY_MEMBERSHIP, data)?;
-        Ok(())
+    fn read_meta<T: DeserializeOwned>(&self, key: &[u8]) -> Result<Option<T>, std::io::Error> {
+        match self.backend.get_meta(key)? {
+            Some(record) => Ok(Some(codec::decode(&record)?)),
+            None => Ok(None),
+        }
     }
 
-    fn save_state_snapshot(&self) -> Result<(), sled::Error> {
-        let hive_state = self.state.snapshot();
-        let data = serde_json::to_vec(&hive_state).unwrap();
-        self.meta_tree.insert(KEY_STATE_SNAPSHOT, data)?;
-        self.meta_tree.flush()?;
-        Ok(())
+    fn write_meta<T: Serialize>(
+        &self,
+        key: &[u8],
+        value: &T,
+        durable: bool,
+    ) -> Result<(), std::io::Error> {
+        self.backend
+            .write_meta(vec![(key.to_vec(), Some(codec::encode(value)?))], durable)
+    }
+
+    /// Makes `meta` and `data` the snapshot served to followers that have
+    /// fallen behind the purged log.
+    fn save_current_snapshot(
+        &self,
+        meta: &SnapshotMeta<NodeIdType, HiveNode>,
+        data: &[u8],
+    ) -> Result<(), std::io::Error> {
+        self.backend.write_meta(
+            vec![
+                (KEY_CURRENT_SNAPSHOT_META.to_vec(), Some(codec::encode(meta)?)),
+                (KEY_CURRENT_SNAPSHOT_DATA.to_vec(), Some(data.to_vec())),
+            ],
+            true,
+        )
+    }
+
+    fn load_current_snapshot(&self) -> Result<Option<Snapshot<TypeConfig>>, std::io::Error> {
+        let Some(meta) = self.read_meta(KEY_CURRENT_SNAPSHOT_META)? else {
+            return Ok(None);
+        };
+        let Some(data) = self.backend.get_meta(KEY_CURRENT_SNAPSHOT_DATA)? else {
+            return Ok(None);
+        };
+        Ok(Some(Snapshot {
+            meta,
+            snapshot: Box::new(Cursor::new(data)),
+        }))
+    }
+
+    /// History is a convenience for operators, so failing to write it is
+    /// logged rather than stopping Raft.
+    fn checkpoint_history(&self, log_id: Option<LogId<NodeIdType>>, state: &HiveState) {
+        let (Some(history), Some(log_id)) = (&self.history, log_id) else {
+            return;
+        };
+        if let Err(e) = history.checkpoint(log_id, state) {
+            tracing::warn!("Cannot save history checkpoint at {}: {}", log_id, e);
+        }
     }
 
     pub fn shared_state(&self) -> &SharedState {
         &self.state
     }
+
+    /// The applied state, taken under the same lock as snapshots so it
+    /// matches `last_log_id` exactly.
+    pub fn backup(&self) -> StateBackup {
+        let applied = self.applied.lock().unwrap();
+        StateBackup {
+            state: self.state.snapshot(),
+            membership: applied.membership.clone(),
+            last_log_id: applied.last_applied,
+        }
+    }
+
+    /// Seeds empty storage with `backup` as its current snapshot, in a
+    /// cluster whose only voter is `node_id`. On start, openraft loads the
+    /// snapshot and this node elects itself in a term past any in the
+    /// backup.
+    pub fn restore_backup(
+        &self,
+        backup: &StateBackup,
+        node_id: NodeIdType,
+        node: HiveNode,
+    ) -> Result<()> {
+        let is_empty = self.backend.last_log()?.is_none()
+            && self.backend.get_meta(KEY_VOTE)?.is_none()
+            && self.backend.get_meta(KEY_CURRENT_SNAPSHOT_META)?.is_none();
+        if !is_empty {
+            anyhow::bail!("Raft storage already holds state; restore into an empty data_dir");
+        }
+        let Some(last_log_id) = backup.last_log_id else {
+            anyhow::bail!("Backup holds no applied log entries");
+        };
+
+        let membership = Membership::new(
+            vec![BTreeSet::from([node_id])],
+            BTreeMap::from([(node_id, node)]),
+        );
+        let meta = SnapshotMeta {
+            last_log_id: Some(last_log_id),
+            last_membership: StoredMembership::new(Some(last_log_id), membership),
+            snapshot_id: format!("{}-{}-restore", last_log_id.leader_id, last_log_id.index),
+        };
+        self.save_current_snapshot(&meta, &codec::encode(&backup.state)?)?;
+        self.write_meta(KEY_VOTE, &Vote::new(last_log_id.leader_id.term, node_id), true)?;
+        self.restore_current_snapshot()
+    }
 }
 
-impl RaftLogReader<TypeConfig> for SledStorage {
+impl RaftLogReader<TypeConfig> for HiveStorage {
     async fn try_get_log_entries<RB: RangeBounds<u64> + Clone + Debug + OptionalSend>(
         &mut self,
         range: RB,
@@ -167,53 +454,38 @@ impl RaftLogReader<TypeConfig> for SledStorage {
             std::ops::Bound::Unbounded => None,
         };
 
-        let mut entries = Vec::new();
-        for item in self.log_tree.range(Self::log_key(start)..) {
-            let (key, value) = item.map_err(|e| {
-                StorageError::from_io_error(
-                    openraft::ErrorSubject::Logs,
-                    openraft::ErrorVerb::Read,
-                    std::io::Error::new(std::io::ErrorKind::Other, e),
-                )
-            })?;
-
-            let index = u64::from_be_bytes(key.as_ref().try_into().unwrap());
-            if let Some(e) = end {
-                if index >= e {
-                    break;
-                }
-            }
-
-            let entry: Entry<TypeConfig> = serde_json::from_slice(&value).map_err(|e| {
-                StorageError::from_io_error(
-                    openraft::ErrorSubject::Logs,
-                    openraft::ErrorVerb::Read,
-                    std::io::Error::new(std::io::ErrorKind::InvalidData, e),
-                )
-            })?;
-            entries.push(entry);
-        }
-
-        Ok(entries)
+        self.backend
+            .read_log(start, end)
+            .and_then(|records| {
+                records
+                    .iter()
+                    .map(|(index, record)| Self::decode_entry(*index, record))
+                    .collect()
+            })
+            .map_err(|e| storage_error(ErrorSubject::Logs, ErrorVerb::Read, e))
     }
 }
 
-impl RaftSnapshotBuilder<TypeConfig> for SledStorage {
+impl RaftSnapshotBuilder<TypeConfig> for HiveStorage {
     async fn build_snapshot(&mut self) -> Result<Snapshot<TypeConfig>, StorageError<NodeIdType>> {
-        let hive_state = self.state.snapshot();
-        let data = serde_json::to_vec(&hive_state).unwrap();
-
-        let last_applied = self.get_last_applied();
-        let last_membership = self.get_membership();
-
-        let mut idx = self.snapshot_idx.lock().unwrap();
-        *idx += 1;
-        let snapshot_idx = *idx;
+        let (hive_state, last_applied, last_membership) = {
+            let applied = self.applied.lock().unwrap();
+            (
+                self.state.snapshot(),
+                applied.last_applied,
+                applied.membership.clone(),
+            )
+        };
+        let data = codec::encode(&hive_state)
+            .map_err(|e| storage_error(ErrorSubject::Snapshot(None), ErrorVerb::Write, e))?;
 
-        let _ = self.meta_tree.insert(
-            KEY_SNAPSHOT_IDX,
-            bincode::serialize(&snapshot_idx).unwrap(),
-        );
+        let snapshot_idx = {
+            let mut idx = self.snapshot_idx.lock().unwrap();
+            *idx += 1;
+            *idx
+        };
+        self.write_meta(KEY_SNAPSHOT_IDX, &snapshot_idx, false)
+            .map_err(|e| storage_error(ErrorSubject::Snapshot(None), ErrorVerb::Write, e))?;
 
         let snapshot_id = format!(
             "{}-{}-{}",
@@ -230,6 +502,12 @@ impl RaftSnapshotBuilder<TypeConfig> for SledStorage {
             snapshot_id,
         };
 
+        self.save_current_snapshot(&meta, &data).map_err(|e| {
+            storage_error(ErrorSubject::Snapshot(Some(meta.signature())), ErrorVerb::Write, e)
+        })?;
+        self.checkpoint_history(meta.last_log_id, &hive_state);
+        tracing::info!("Built snapshot {}", meta.snapshot_id);
+
         Ok(Snapshot {
             meta,
             snapshot: Box::new(Cursor::new(data)),
@@ -237,25 +515,24 @@ impl RaftSnapshotBuilder<TypeConfig> for SledStorage {
     }
 }
 
-impl RaftStorage<TypeConfig> for SledStorage {
+impl RaftStorage<TypeConfig> for HiveStorage {
     type LogReader = Self;
     type SnapshotBuilder = Self;
 
     async fn get_log_state(&mut self) -> Result<LogState<TypeConfig>, StorageError<NodeIdType>> {
-        let last_purged = self.get_last_purged();
+        let last_purged = self
+            .read_meta(KEY_LAST_PURGED)
+            .map_err(|e| storage_error(ErrorSubject::Logs, ErrorVerb::Read, e))?;
 
         let last_log_id = self
-            .log_tree
-            .last()
-            .map_err(|e| {
-                StorageError::from_io_error(
-                    openraft::ErrorSubject::Logs,
-                    openraft::ErrorVerb::Read,
-                    std::io::Error::new(std::io::ErrorKind::Other, e),
-                )
-            })?
-            .and_then(|(_, v)| serde_json::from_slice::<Entry<TypeConfig>>(&v).ok())
-            .map(|e| e.log_id);
+            .backend
+            .last_log()
+            .and_then(|last| {
+                last.map(|(index, record)| Self::decode_entry(index, &record))
+                    .transpose()
+            })
+            .map_err(|e| storage_error(ErrorSubject::Logs, ErrorVerb::Read, e))?
+            .map(|entry| entry.log_id);
 
         Ok(LogState {
             last_purged_log_id: last_purged,
@@ -264,104 +541,66 @@ impl RaftStorage<TypeConfig> for SledStorage {
     }
 
     async fn save_vote(&mut self, vote: &Vote<NodeIdType>) -> Result<(), StorageError<NodeIdType>> {
-        self.set_vote(vote).map_err(|e| {
-            StorageError::from_io_error(
-                openraft::ErrorSubject::Vote,
-                openraft::ErrorVerb::Write,
-                std::io::Error::new(std::io::ErrorKind::Other, e),
-            )
-        })
+        self.write_meta(KEY_VOTE, vote, true)
+            .map_err(|e| storage_error(ErrorSubject::Vote, ErrorVerb::Write, e))
     }
 
     async fn read_vote(&mut self) -> Result<Option<Vote<NodeIdType>>, StorageError<NodeIdType>> {
-        Ok(self.get_vote())
+        self.read_meta(KEY_VOTE)
+            .map_err(|e| storage_error(ErrorSubject::Vote, ErrorVerb::Read, e))
+    }
+
+    async fn save_committed(
+        &mut self,
+        committed: Option<LogId<NodeIdType>>,
+    ) -> Result<(), StorageError<NodeIdType>> {
+        self.write_meta(KEY_COMMITTED, &committed, false)
+            .map_err(|e| storage_error(ErrorSubject::Store, ErrorVerb::Write, e))
+    }
+
+    async fn read_committed(
+        &mut self,
+    ) -> Result<Option<LogId<NodeIdType>>, StorageError<NodeIdType>> {
+        let committed: Option<Option<LogId<NodeIdType>>> = self
+            .read_meta(KEY_COMMITTED)
+            .map_err(|e| storage_error(ErrorSubject::Store, ErrorVerb::Read, e))?;
+        Ok(committed.flatten())
     }
 
     async fn get_log_reader(&mut self) -> Self::LogReader {
-        SledStorage {
-            db: self.db.clone(),
-            log_tree: self.log_tree.clone(),
-            meta_tree: self.meta_tree.clone(),
-            state: self.state.clone(),
-            snapshot_idx: Mutex::new(*self.snapshot_idx.lock().unwrap()),
-        }
+        self.clone()
     }
 
     async fn append_to_log<I>(&mut self, entries: I) -> Result<(), StorageError<NodeIdType>>
     where
         I: IntoIterator<Item = Entry<TypeConfig>> + OptionalSend,
     {
-        for entry in entries {
-            let key = Self::log_key(entry.log_id.index);
-            let value = serde_json::to_vec(&entry).unwrap();
-            self.log_tree.insert(key, value).map_err(|e| {
-                StorageError::from_io_error(
-                    openraft::ErrorSubject::Logs,
-                    openraft::ErrorVerb::Write,
-                    std::io::Error::new(std::io::ErrorKind::Other, e),
-                )
-            })?;
-        }
-        self.log_tree.flush().map_err(|e| {
-            StorageError::from_io_error(
-                openraft::ErrorSubject::Logs,
-                openraft::ErrorVerb::Write,
-                std::io::Error::new(std::io::ErrorKind::Other, e),
-            )
-        })?;
-        Ok(())
+        let records = entries
+            .into_iter()
+            .map(|entry| Ok((entry.log_id.index, codec::encode(&entry)?)))
+            .collect::<Result<Vec<_>, RecordError>>()
+            .map_err(|e| storage_error(ErrorSubject::Logs, ErrorVerb::Write, e))?;
+        self.backend
+            .append_log(records)
+            .map_err(|e| storage_error(ErrorSubject::Logs, ErrorVerb::Write, e))
     }
 
     async fn delete_conflict_logs_since(
         &mut self,
         log_id: LogId<NodeIdType>,
     ) -> Result<(), StorageError<NodeIdType>> {
-        let keys_to_remove: Vec<_> = self
-            .log_tree
-            .range(Self::log_key(log_id.index)..)
-            .filter_map(|r| r.ok().map(|(k, _)| k))
-            .collect();
-
-        for key in keys_to_remove {
-            self.log_tree.remove(key).map_err(|e| {
-                StorageError::from_io_error(
-                    openraft::ErrorSubject::Logs,
-                    openraft::ErrorVerb::Write,
-                    std::io::Error::new(std::io::ErrorKind::Other, e),
-                )
-            })?;
-        }
-        Ok(())
+        self.backend
+            .truncate_log(log_id.index)
+            .map_err(|e| storage_error(ErrorSubject::Logs, ErrorVerb::Write, e))
     }
 
     async fn purge_logs_upto(
         &mut self,
         log_id: LogId<NodeIdType>,
     ) -> Result<(), StorageError<NodeIdType>> {
-        self.set_last_purged(&log_id).map_err(|e| {
-            StorageError::from_io_error(
-                openraft::ErrorSubject::Logs,
-                openraft::ErrorVerb::Write,
-                std::io::Error::new(std::io::ErrorKind::Other, e),
-            )
-        })?;
-
-        let keys_to_remove: Vec<_> = self
-            .log_tree
-            .range(..=Self::log_key(log_id.index))
-            .filter_map(|r| r.ok().map(|(k, _)| k))
-            .collect();
-
-        for key in keys_to_remove {
-            self.log_tree.remove(key).map_err(|e| {
-                StorageError::from_io_error(
-                    openraft::ErrorSubject::Logs,
-                    openraft::ErrorVerb::Write,
-                    std::io::Error::new(std::io::ErrorKind::Other, e),
-                )
-            })?;
-        }
-        Ok(())
+        self.write_meta(KEY_LAST_PURGED, &log_id, false)
+            .and_then(|()| self.backend.purge_log(log_id.index))
+            .map_err(|e| storage_error(ErrorSubject::Logs, ErrorVerb::Write, e))
     }
 
     async fn last_applied_state(
@@ -373,62 +612,55 @@ impl RaftStorage<TypeConfig> for SledStorage {
         ),
         StorageError<NodeIdType>,
     > {
-        Ok((self.get_last_applied(), self.get_membership()))
+        let applied = self.applied.lock().unwrap();
+        Ok((applied.last_applied, applied.membership.clone()))
     }
 
     async fn apply_to_state_machine(
         &mut self,
         entries: &[Entry<TypeConfig>],
-    ) -> Result<Vec<()>, StorageError<NodeIdType>> {
+    ) -> Result<Vec<CommandResult>, StorageError<NodeIdType>> {
+        let mut applied = self.applied.lock().unwrap();
         let mut results = Vec::new();
+        let applied_at = Utc::now();
 
+        // The state itself is only written out by snapshots; entries applied
+        // since the last one are replayed from the log after a restart.
         for entry in entries {
-            self.set_last_applied(&entry.log_id).map_err(|e| {
-                StorageError::from_io_error(
-                    openraft::ErrorSubject::StateMachine,
-                    openraft::ErrorVerb::Write,
-                    std::io::Error::new(std::io::ErrorKind::Other, e),
-                )
-            })?;
-
-            match &entry.payload {
-                EntryPayload::Blank => {}
+            applied.last_applied = Some(entry.log_id);
+
+            let result = match &entry.payload {
+                EntryPayload::Blank => {
+                    self.state.set_last_applied(entry.log_id.index);
+                    CommandResult::Applied {
+                        revision: self.state.revision(),
+                    }
+                }
                 EntryPayload::Normal(cmd) => {
-                    self.state.apply(cmd);
+                    self.state.apply_entry(entry.log_id.index, cmd, applied_at)
                 }
                 EntryPayload::Membership(mem) => {
-                    let membership = StoredMembership::new(Some(entry.log_id), mem.clone());
-                    self.set_membership(&membership).map_err(|e| {
-                        StorageError::from_io_error(
-                            openraft::ErrorSubject::StateMachine,
-                            openraft::ErrorVerb::Write,
-                            std::io::Error::new(std::io::ErrorKind::Other, e),
-                        )
-                    })?;
+                    self.state.set_last_applied(entry.log_id.index);
+                    applied.membership = StoredMembership::new(Some(entry.log_id), mem.clone());
+                    CommandResult::Applied {
+                        revision: self.state.revision(),
+                    }
                 }
-            }
-            results.push(());
+            };
+            results.push(result);
         }
 
-        self.save_state_snapshot().map_err(|e| {
-            StorageError::from_io_error(
-                openraft::ErrorSubject::StateMachine,
-                openraft::ErrorVerb::Write,
-                std::io::Error::new(std::io::ErrorKind::Other, e),
-            )
-        })?;
-
+        if let Some(history) = &self.history {
+            // A gap left here is reported by queries that span it.
+            if let Err(e) = history.record(entries, &results, applied_at) {
+                tracing::warn!("Cannot record applied entries in history: {}", e);
+            }
+        }
         Ok(results)
     }
 
     async fn get_snapshot_builder(&mut self) -> Self::SnapshotBuilder {
-        SledStorage {
-            db: self.db.clone(),
-            log_tree: self.log_tree.clone(),
-            meta_tree: self.meta_tree.clone(),
-            state: self.state.clone(),
-            snapshot_idx: Mutex::new(*self.snapshot_idx.lock().unwrap()),
-        }
+        self.clone()
     }
 
     async fn begin_receiving_snapshot(
@@ -443,59 +675,36 @@ impl RaftStorage<TypeConfig> for SledStorage {
         snapshot: Box<Cursor<Vec<u8>>>,
     ) -> Result<(), StorageError<NodeIdType>> {
         let data = snapshot.into_inner();
-        let hive_state: HiveState = serde_json::from_slice(&data).map_err(|e| {
-            StorageError::from_io_error(
-                openraft::ErrorSubject::Snapshot(Some(meta.signature())),
-                openraft::ErrorVerb::Read,
-                std::io::Error::new(std::io::ErrorKind::InvalidData, e),
-            )
+        let mut hive_state = decode_snapshot_data(&data).map_err(|e| {
+            storage_error(ErrorSubject::Snapshot(Some(meta.signature())), ErrorVerb::Read, e)
         })?;
 
-        self.state.restore(hive_state);
-
-        if let Some(log_id) = meta.last_log_id {
-            self.set_last_applied(&log_id).map_err(|e| {
-                StorageError::from_io_error(
-                    openraft::ErrorSubject::StateMachine,
-                    openraft::ErrorVerb::Write,
-                    std::io::Error::new(std::io::ErrorKind::Other, e),
-                )
-            })?;
-        }
-
-        self.set_membership(&meta.last_membership).map_err(|e| {
-            StorageError::from_io_error(
-                openraft::ErrorSubject::StateMachine,
-                openraft::ErrorVerb::Write,
-                std::io::Error::new(std::io::ErrorKind::Other, e),
-            )
+        // Stored re-encoded, so data received from an older leader is kept
+        // in the current format.
+        let data = codec::encode(&hive_state).map_err(|e| {
+            storage_error(ErrorSubject::Snapshot(Some(meta.signature())), ErrorVerb::Write, e)
         })?;
-
-        self.save_state_snapshot().map_err(|e| {
-            StorageError::from_io_error(
-                openraft::ErrorSubject::StateMachine,
-                openraft::ErrorVerb::Write,
-                std::io::Error::new(std::io::ErrorKind::Other, e),
-            )
+        self.save_current_snapshot(meta, &data).map_err(|e| {
+            storage_error(ErrorSubject::Snapshot(Some(meta.signature())), ErrorVerb::Write, e)
         })?;
 
+        self.checkpoint_history(meta.last_log_id, &hive_state);
+        let mut applied = self.applied.lock().unwrap();
+        hive_state.last_applied_index = meta.last_log_id.map_or(0, |l| l.index);
+        self.state.restore(hive_state);
+        applied.last_applied = meta.last_log_id;
+        applied.membership = meta.last_membership.clone();
+
         Ok(())
     }
 
     async fn get_current_snapshot(
         &mut self,
     ) -> Result<Option<Snapshot<TypeConfig>>, StorageError<NodeIdType>> {
-        Ok(None)
+        self.load_current_snapshot()
+            .map_err(|e| storage_error(ErrorSubject::Snapshot(None), ErrorVerb::Read, e))
     }
 }
 
-pub type SledAdaptorLogStore = Adaptor<TypeConfig, SledStorage>;
-pub type SledAdaptorStateMachine = Adaptor<TypeConfig, SledStorage>;
-
-pub fn create_storage<P: AsRef<Path>>(
-    path: P,
-    state: SharedState,
-) -> Result<(SledAdaptorLogStore, SledAdaptorStateMachine)> {
-    let storage = SledStorage::new(path, state)?;
-    Ok(Adaptor::new(storage))
-}
+pub type HiveLogStore = Adaptor<TypeConfig, HiveStorage>;
+pub type HiveStateMachineStore = Adaptor<TypeConfig, HiveStorage>;
@@ -0,0 +1,217 @@
+//! Log entries as storage format version 0 wrote them: JSON, with commands
+//! shaped as they were before entities carried revisions. These types are
+//! frozen so later changes to `crate::types` cannot stop old logs from
+//! migrating. Leaf enums whose JSON form never changed are shared.
+
+use crate::replicator::storage::{self, HiveNode};
+use crate::types::{
+    self, AttachmentId, AttachmentKind, GoalId, NodeHealth, NodeId, NodeMetrics, TaskId,
+    TaskPayload, TaskStatus,
+};
+use chrono::{DateTime, Utc};
+use openraft::{Entry, EntryPayload};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Cursor;
+
+openraft::declare_raft_types!(
+    pub TypeConfig:
+        D = ClusterCommand,
+        R = (),
+        Node = HiveNode,
+);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ClusterCommand {
+    RegisterNode(NodeStatus),
+    UpdateNodeHealth {
+        node_id: NodeId,
+        health: NodeHealth,
+        metrics: NodeMetrics,
+    },
+    RemoveNode {
+        node_id: NodeId,
+    },
+    PutTask(Task),
+    UpdateTaskStatus {
+        task_id: TaskId,
+        status: TaskStatus,
+        result: Option<serde_json::Value>,
+    },
+    PutAttachment(Attachment),
+    RemoveAttachment {
+        attachment_id: AttachmentId,
+    },
+    PutGoal(Goal),
+    RemoveGoal {
+        goal_id: GoalId,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeStatus {
+    pub node_id: NodeId,
+    pub hostname: String,
+    pub tags: Vec<String>,
+    pub health: NodeHealth,
+    pub last_heartbeat: DateTime<Utc>,
+    pub cpu_usage: f32,
+    pub memory_usage: f32,
+    pub disk_usage: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    pub id: AttachmentId,
+    pub node_id: NodeId,
+    pub kind: AttachmentKind,
+    pub capabilities: Vec<String>,
+    pub metadata: HashMap<String, String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Task {
+    pub id: TaskId,
+    pub target_node: NodeId,
+    pub payload: TaskPayload,
+    pub status: TaskStatus,
+    pub priority: u8,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub result: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Goal {
+    pub id: GoalId,
+    pub description: String,
+    pub constraints: Vec<String>,
+    pub priority: u8,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Decodes an unheadered log record. Builds between the baseline and the
+/// versioned envelope also wrote headerless JSON, including commands the
+/// baseline did not have, so a record that is not a baseline entry is read
+/// as a current one.
+pub fn decode_entry(record: &[u8]) -> serde_json::Result<Entry<storage::TypeConfig>> {
+    match serde_json::from_slice::<Entry<TypeConfig>>(record) {
+        Ok(entry) => Ok(upgrade_entry(entry)),
+        Err(e) => serde_json::from_slice(record).map_err(|_| e),
+    }
+}
+
+fn upgrade_entry(entry: Entry<TypeConfig>) -> Entry<storage::TypeConfig> {
+    let payload = match entry.payload {
+        EntryPayload::Blank => EntryPayload::Blank,
+        EntryPayload::Normal(command) => EntryPayload::Normal(command.into()),
+        EntryPayload::Membership(membership) => EntryPayload::Membership(membership),
+    };
+    Entry {
+        log_id: entry.log_id,
+        payload,
+    }
+}
+
+impl From<ClusterCommand> for types::ClusterCommand {
+    fn from(command: ClusterCommand) -> Self {
+        match command {
+            ClusterCommand::RegisterNode(status) => {
+                types::ClusterCommand::RegisterNode(status.into())
+            }
+            ClusterCommand::UpdateNodeHealth {
+                node_id,
+                health,
+                metrics,
+            } => types::ClusterCommand::UpdateNodeHealth {
+                node_id,
+                health,
+                metrics,
+                expected_revision: None,
+            },
+            ClusterCommand::RemoveNode { node_id } => types::ClusterCommand::RemoveNode { node_id },
+            ClusterCommand::PutTask(task) => types::ClusterCommand::PutTask(task.into()),
+            ClusterCommand::UpdateTaskStatus {
+                task_id,
+                status,
+                result,
+            } => types::ClusterCommand::UpdateTaskStatus {
+                task_id,
+                status,
+                result,
+                expected_revision: None,
+            },
+            ClusterCommand::PutAttachment(attachment) => {
+                types::ClusterCommand::PutAttachment(attachment.into())
+            }
+            ClusterCommand::RemoveAttachment { attachment_id } => {
+                types::ClusterCommand::RemoveAttachment { attachment_id }
+            }
+            ClusterCommand::PutGoal(goal) => types::ClusterCommand::PutGoal(goal.into()),
+            ClusterCommand::RemoveGoal { goal_id } => types::ClusterCommand::RemoveGoal { goal_id },
+        }
+    }
+}
+
+impl From<NodeStatus> for types::NodeStatus {
+    fn from(status: NodeStatus) -> Self {
+        types::NodeStatus {
+            node_id: status.node_id,
+            hostname: status.hostname,
+            tags: status.tags,
+            health: status.health,
+            last_heartbeat: status.last_heartbeat,
+            cpu_usage: status.cpu_usage,
+            memory_usage: status.memory_usage,
+            disk_usage: status.disk_usage,
+            revision: 0,
+        }
+    }
+}
+
+impl From<Attachment> for types::Attachment {
+    fn from(attachment: Attachment) -> Self {
+        types::Attachment {
+            id: attachment.id,
+            node_id: attachment.node_id,
+            kind: attachment.kind,
+            capabilities: attachment.capabilities,
+            metadata: attachment.metadata,
+            created_at: attachment.created_at,
+            revision: 0,
+        }
+    }
+}
+
+impl From<Task> for types::Task {
+    fn from(task: Task) -> Self {
+        types::Task {
+            id: task.id,
+            target_node: task.target_node,
+            payload: task.payload,
+            status: task.status,
+            priority: task.priority,
+            created_at: task.created_at,
+            updated_at: task.updated_at,
+            result: task.result,
+            revision: 0,
+            lock: None,
+        }
+    }
+}
+
+impl From<Goal> for types::Goal {
+    fn from(goal: Goal) -> Self {
+        types::Goal {
+            id: goal.id,
+            description: goal.description,
+            constraints: goal.constraints,
+            priority: goal.priority,
+            active: goal.active,
+            created_at: goal.created_at,
+            revision: 0,
+        }
+    }
+}
@@ -0,0 +1,57 @@
+//! Commands as they are applied, for subscribers that react to changes
+//! instead of polling the cluster view.
+
+use crate::replicator::history::HistoryEntry;
+use crate::types::{ClusterCommand, CommandResult, EntityType, NodeId, TaskId};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Commands a slow subscriber may fall behind by before it misses some.
+pub const WATCH_CAPACITY: usize = 1024;
+
+/// A command applied to the state machine at log index `index`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppliedCommand {
+    pub index: u64,
+    pub applied_at: DateTime<Utc>,
+    pub command: ClusterCommand,
+    pub result: CommandResult,
+}
+
+impl AppliedCommand {
+    /// The applied command recorded in `entry`, if it holds one.
+    pub fn from_history(entry: HistoryEntry) -> Option<Self> {
+        Some(Self {
+            index: entry.index,
+            applied_at: entry.applied_at,
+            command: entry.command?,
+            result: entry.result?,
+        })
+    }
+}
+
+/// Which applied commands a subscriber wants. Empty fields match anything.
+#[derive(Debug, Clone, Default)]
+pub struct WatchFilter {
+    pub types: Vec<EntityType>,
+    pub node: Option<NodeId>,
+    pub task: Option<TaskId>,
+    /// Key written by `Kv*` commands; with `key_prefix`, any key starting
+    /// with it.
+    pub key: Option<String>,
+    pub key_prefix: bool,
+}
+
+impl WatchFilter {
+    pub fn matches(&self, command: &ClusterCommand) -> bool {
+        let ids = command.entity_ids();
+        (self.types.is_empty() || self.types.contains(&command.entity_type()))
+            && self.node.as_deref().is_none_or(|node| ids.contains(&node))
+            && self.task.as_deref().is_none_or(|task| ids.contains(&task))
+            && self.key.as_deref().is_none_or(|key| {
+                command.kv_key().is_some_and(|written| {
+                    written == key || (self.key_prefix && written.starts_with(key))
+                })
+            })
+    }
+}
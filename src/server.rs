@@ -0,0 +1,115 @@
+use crate::auth::{extract_cn_from_der, extract_serial_from_der, ClusterTls};
+use anyhow::Result;
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Json, Router,
+};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tower::Service;
+use tracing::{debug, warn};
+
+/// Routes reachable without a client certificate. Everything else requires
+/// one issued by the cluster CA.
+const UNAUTHENTICATED_PATHS: &[&str] = &["/health", "/enroll"];
+
+#[derive(Debug, Clone)]
+pub struct ClientIdentity {
+    pub node_id: String,
+    pub serial: String,
+}
+
+pub async fn serve(listener: TcpListener, router: Router, tls: Option<Arc<ClusterTls>>) -> Result<()> {
+    let Some(tls) = tls else {
+        axum::serve(listener, router).await?;
+        return Ok(());
+    };
+
+    let router = router.layer(middleware::from_fn_with_state(
+        tls.clone(),
+        require_client_identity,
+    ));
+
+    loop {
+        let (stream, remote_addr) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+
+        let acceptor = TlsAcceptor::from(tls.server_config());
+        let router = router.clone();
+
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("TLS handshake with {} failed: {}", remote_addr, e);
+                    return;
+                }
+            };
+
+            let identity = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(|cert| {
+                    Some(ClientIdentity {
+                        node_id: extract_cn_from_der(cert).ok()?,
+                        serial: extract_serial_from_der(cert).ok()?,
+                    })
+                });
+
+            let service = hyper::service::service_fn(move |mut req: Request<hyper::body::Incoming>| {
+                if let Some(identity) = &identity {
+                    req.extensions_mut().insert(identity.clone());
+                }
+                router.clone().call(req)
+            });
+
+            if let Err(e) = Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await
+            {
+                debug!("Connection from {} closed with error: {}", remote_addr, e);
+            }
+        });
+    }
+}
+
+/// Connections outlive CRL updates, so revocation is checked again on every
+/// request rather than only during the handshake.
+async fn require_client_identity(
+    State(tls): State<Arc<ClusterTls>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let identity = req.extensions().get::<ClientIdentity>();
+
+    if identity.is_some_and(|id| tls.is_revoked(&id.serial)) {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({ "error": "client certificate revoked" })),
+        )
+            .into_response();
+    }
+
+    if identity.is_none() && !UNAUTHENTICATED_PATHS.contains(&req.uri().path()) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({ "error": "client certificate required" })),
+        )
+            .into_response();
+    }
+
+    next.run(req).await
+}
@@ -33,6 +33,10 @@ pub struct NodeStatus {
     pub cpu_usage: f32,
     pub memory_usage: f32,
     pub disk_usage: f32,
+    /// Cluster revision of the last write to this entry. Set by the state
+    /// machine; the value a client sends is ignored.
+    #[serde(default)]
+    pub revision: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -42,7 +46,11 @@ pub enum AttachmentKind {
     DockerContainer { container_id: String },
     Service { name: String, unit: Option<String> },
     Webhook { url: String },
-    Custom { type_name: String, config: serde_json::Value },
+    Custom {
+        type_name: String,
+        #[serde(with = "embedded_json")]
+        config: serde_json::Value,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -53,6 +61,8 @@ pub struct Attachment {
     pub capabilities: Vec<String>,
     pub metadata: HashMap<String, String>,
     pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub revision: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -73,7 +83,11 @@ pub enum TaskPayload {
     CheckService { service_name: String },
     RestartService { service_name: String },
     DockerRun { image: String, args: Vec<String> },
-    Custom { tool_id: String, args: serde_json::Value },
+    Custom {
+        tool_id: String,
+        #[serde(with = "embedded_json")]
+        args: serde_json::Value,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -85,7 +99,14 @@ pub struct Task {
     pub priority: u8,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    #[serde(default, with = "embedded_json::option")]
     pub result: Option<serde_json::Value>,
+    #[serde(default)]
+    pub revision: u64,
+    /// Lock held while the task runs, so tasks sharing it never run at
+    /// the same time anywhere in the cluster.
+    #[serde(default)]
+    pub lock: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -96,6 +117,8 @@ pub struct Goal {
     pub priority: u8,
     pub active: bool,
     pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub revision: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -104,6 +127,9 @@ pub struct ClusterView {
     pub tasks: Vec<Task>,
     pub attachments: Vec<Attachment>,
     pub goals: Vec<Goal>,
+    /// Unexpired key-value entries, in key order.
+    #[serde(default)]
+    pub kv: Vec<KvEntry>,
     pub leader_id: Option<NodeId>,
     pub term: u64,
 }
@@ -115,6 +141,7 @@ impl ClusterView {
             tasks: Vec::new(),
             attachments: Vec::new(),
             goals: Vec::new(),
+            kv: Vec::new(),
             leader_id: None,
             term: 0,
         }
@@ -151,6 +178,10 @@ impl ClusterView {
             .filter(|t| t.target_node == node_id)
             .collect()
     }
+
+    pub fn kv_get(&self, key: &str) -> Option<&KvEntry> {
+        self.kv.iter().find(|e| e.key == key)
+    }
 }
 
 impl Default for ClusterView {
@@ -199,6 +230,13 @@ pub enum BrainAction {
     },
 }
 
+/// Writes replicated through Raft. Commands carrying an `expected_revision`
+/// only apply if the target's current revision matches; 0 means the target
+/// must not exist yet and `None` skips the check.
+///
+/// Logs and peers from before revisions existed send the plain `Put*`
+/// variants, so those keep their original shape and the revision-checked
+/// writes are separate `Put*If` variants.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ClusterCommand {
     RegisterNode(NodeStatus),
@@ -206,24 +244,335 @@ pub enum ClusterCommand {
         node_id: NodeId,
         health: NodeHealth,
         metrics: NodeMetrics,
+        #[serde(default)]
+        expected_revision: Option<u64>,
     },
     RemoveNode {
         node_id: NodeId,
     },
     PutTask(Task),
+    PutTaskIf {
+        task: Task,
+        expected_revision: u64,
+    },
     UpdateTaskStatus {
         task_id: TaskId,
         status: TaskStatus,
+        #[serde(default, with = "embedded_json::option")]
         result: Option<serde_json::Value>,
+        #[serde(default)]
+        expected_revision: Option<u64>,
     },
     PutAttachment(Attachment),
+    PutAttachmentIf {
+        attachment: Attachment,
+        expected_revision: u64,
+    },
     RemoveAttachment {
         attachment_id: AttachmentId,
     },
     PutGoal(Goal),
+    PutGoalIf {
+        goal: Goal,
+        expected_revision: u64,
+    },
     RemoveGoal {
         goal_id: GoalId,
     },
+    IssueToken(IssuedToken),
+    ConsumeToken {
+        token_hash: String,
+        node_id: NodeId,
+        request_id: String,
+        consumed_at: DateTime<Utc>,
+    },
+    RecordEnrollment(EnrolledNode),
+    RevokeNode {
+        node_id: NodeId,
+        revoked: Vec<RevokedCertificate>,
+        crl: SignedCrl,
+    },
+    SetTrustBundle(TrustBundle),
+    /// Records the Raft ID allocated to a node. IDs are never reused, so a
+    /// node already mapped elsewhere or an ID already taken conflicts.
+    AssignRaftId {
+        node_id: NodeId,
+        raft_id: u64,
+    },
+    /// Sets `key`, expiring it `ttl_secs` after `written_at` if given.
+    /// Expiry is judged against the time a command was written, so every
+    /// node applies it the same way.
+    KvPut {
+        key: String,
+        value: String,
+        ttl_secs: Option<u64>,
+        written_at: DateTime<Utc>,
+    },
+    KvDelete {
+        key: String,
+        #[serde(default)]
+        expected_revision: Option<u64>,
+        written_at: DateTime<Utc>,
+    },
+    /// `KvPut` that only applies if the key's revision is
+    /// `expected_revision`; 0 means the key must not exist.
+    KvCas {
+        key: String,
+        value: String,
+        ttl_secs: Option<u64>,
+        expected_revision: u64,
+        written_at: DateTime<Utc>,
+    },
+    /// Gives `holder` the lease on `key` until `ttl_secs` after
+    /// `requested_at`, unless someone else holds an unexpired lease. The
+    /// fencing token is the log index of the command that took the lease;
+    /// a holder acquiring its own lease again just extends it.
+    AcquireLock {
+        key: String,
+        holder: String,
+        ttl_secs: u64,
+        requested_at: DateTime<Utc>,
+    },
+    /// Extends `holder`'s unexpired lease on `key` to `ttl_secs` after
+    /// `requested_at`.
+    RenewLock {
+        key: String,
+        holder: String,
+        ttl_secs: u64,
+        requested_at: DateTime<Utc>,
+    },
+    ReleaseLock {
+        key: String,
+        holder: String,
+    },
+}
+
+/// The kind of entity a [`ClusterCommand`] writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntityType {
+    Node,
+    Task,
+    Attachment,
+    Goal,
+    Token,
+    Enrollment,
+    TrustBundle,
+    Kv,
+    Lock,
+}
+
+impl std::str::FromStr for EntityType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "node" => Ok(EntityType::Node),
+            "task" => Ok(EntityType::Task),
+            "attachment" => Ok(EntityType::Attachment),
+            "goal" => Ok(EntityType::Goal),
+            "token" => Ok(EntityType::Token),
+            "enrollment" => Ok(EntityType::Enrollment),
+            "trust_bundle" => Ok(EntityType::TrustBundle),
+            "kv" => Ok(EntityType::Kv),
+            "lock" => Ok(EntityType::Lock),
+            _ => Err(format!("Unknown entity type: {}", s)),
+        }
+    }
+}
+
+impl ClusterCommand {
+    pub fn entity_type(&self) -> EntityType {
+        match self {
+            ClusterCommand::RegisterNode(_)
+            | ClusterCommand::UpdateNodeHealth { .. }
+            | ClusterCommand::RemoveNode { .. }
+            | ClusterCommand::RevokeNode { .. }
+            | ClusterCommand::AssignRaftId { .. } => EntityType::Node,
+            ClusterCommand::PutTask(_)
+            | ClusterCommand::PutTaskIf { .. }
+            | ClusterCommand::UpdateTaskStatus { .. } => EntityType::Task,
+            ClusterCommand::PutAttachment(_)
+            | ClusterCommand::PutAttachmentIf { .. }
+            | ClusterCommand::RemoveAttachment { .. } => EntityType::Attachment,
+            ClusterCommand::PutGoal(_)
+            | ClusterCommand::PutGoalIf { .. }
+            | ClusterCommand::RemoveGoal { .. } => EntityType::Goal,
+            ClusterCommand::IssueToken(_) | ClusterCommand::ConsumeToken { .. } => {
+                EntityType::Token
+            }
+            ClusterCommand::RecordEnrollment(_) => EntityType::Enrollment,
+            ClusterCommand::SetTrustBundle(_) => EntityType::TrustBundle,
+            ClusterCommand::KvPut { .. }
+            | ClusterCommand::KvDelete { .. }
+            | ClusterCommand::KvCas { .. } => EntityType::Kv,
+            ClusterCommand::AcquireLock { .. }
+            | ClusterCommand::RenewLock { .. }
+            | ClusterCommand::ReleaseLock { .. } => EntityType::Lock,
+        }
+    }
+
+    /// IDs of the nodes, tasks, attachments, goals and tokens the command
+    /// touches.
+    pub fn entity_ids(&self) -> Vec<&str> {
+        match self {
+            ClusterCommand::RegisterNode(status) => vec![status.node_id.as_str()],
+            ClusterCommand::UpdateNodeHealth { node_id, .. }
+            | ClusterCommand::RemoveNode { node_id }
+            | ClusterCommand::RevokeNode { node_id, .. }
+            | ClusterCommand::AssignRaftId { node_id, .. } => vec![node_id.as_str()],
+            ClusterCommand::PutTask(task) | ClusterCommand::PutTaskIf { task, .. } => {
+                vec![task.id.as_str(), task.target_node.as_str()]
+            }
+            ClusterCommand::UpdateTaskStatus { task_id, .. } => vec![task_id.as_str()],
+            ClusterCommand::PutAttachment(attachment)
+            | ClusterCommand::PutAttachmentIf { attachment, .. } => {
+                vec![attachment.id.as_str(), attachment.node_id.as_str()]
+            }
+            ClusterCommand::RemoveAttachment { attachment_id } => vec![attachment_id.as_str()],
+            ClusterCommand::PutGoal(goal) | ClusterCommand::PutGoalIf { goal, .. } => {
+                vec![goal.id.as_str()]
+            }
+            ClusterCommand::RemoveGoal { goal_id } => vec![goal_id.as_str()],
+            ClusterCommand::IssueToken(token) => vec![token.token_hash.as_str()],
+            ClusterCommand::ConsumeToken {
+                token_hash,
+                node_id,
+                ..
+            } => vec![token_hash.as_str(), node_id.as_str()],
+            ClusterCommand::RecordEnrollment(node) => vec![node.node_id.as_str()],
+            ClusterCommand::SetTrustBundle(_) => Vec::new(),
+            ClusterCommand::KvPut { key, .. }
+            | ClusterCommand::KvDelete { key, .. }
+            | ClusterCommand::KvCas { key, .. } => vec![key.as_str()],
+            ClusterCommand::AcquireLock { key, holder, .. }
+            | ClusterCommand::RenewLock { key, holder, .. }
+            | ClusterCommand::ReleaseLock { key, holder } => {
+                vec![key.as_str(), holder.as_str()]
+            }
+        }
+    }
+
+    /// The key a `Kv*` command writes.
+    pub fn kv_key(&self) -> Option<&str> {
+        match self {
+            ClusterCommand::KvPut { key, .. }
+            | ClusterCommand::KvDelete { key, .. }
+            | ClusterCommand::KvCas { key, .. } => Some(key),
+            _ => None,
+        }
+    }
+
+    /// The revision a revision-checked write expects its target to be at.
+    pub fn expected_revision(&self) -> Option<u64> {
+        match self {
+            ClusterCommand::PutTaskIf {
+                expected_revision, ..
+            }
+            | ClusterCommand::PutAttachmentIf {
+                expected_revision, ..
+            }
+            | ClusterCommand::PutGoalIf {
+                expected_revision, ..
+            }
+            | ClusterCommand::KvCas {
+                expected_revision, ..
+            } => Some(*expected_revision),
+            ClusterCommand::UpdateNodeHealth {
+                expected_revision, ..
+            }
+            | ClusterCommand::UpdateTaskStatus {
+                expected_revision, ..
+            }
+            | ClusterCommand::KvDelete {
+                expected_revision, ..
+            } => *expected_revision,
+            _ => None,
+        }
+    }
+}
+
+/// Outcome of applying a [`ClusterCommand`] to the state machine. Anything
+/// other than `Applied` or `Leased` means the command left state unchanged.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CommandResult {
+    /// The command changed state and was assigned cluster revision
+    /// `revision`.
+    Applied { revision: u64 },
+    /// An `AcquireLock` or `RenewLock` applied, leaving this lease. Its
+    /// `revision` is the one the command was assigned.
+    Leased(Lease),
+    /// The command referred to a node, task, attachment, goal, token, key or
+    /// lease that does not exist.
+    NotFound,
+    /// The target exists but the command lost to a newer or conflicting
+    /// write, e.g. a token that was already consumed or a revision mismatch.
+    /// Also returned for a TTL too large to represent.
+    Conflict,
+}
+
+impl CommandResult {
+    pub fn is_applied(&self) -> bool {
+        matches!(self, CommandResult::Applied { .. } | CommandResult::Leased(_))
+    }
+
+    pub fn revision(&self) -> Option<u64> {
+        match self {
+            CommandResult::Applied { revision } => Some(*revision),
+            CommandResult::Leased(lease) => Some(lease.revision),
+            _ => None,
+        }
+    }
+}
+
+/// A value in the replicated key-value namespace.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KvEntry {
+    pub key: String,
+    pub value: String,
+    pub revision: u64,
+    pub updated_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl KvEntry {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// Longest TTL a key may be written with.
+pub const MAX_KV_TTL_SECS: u64 = 365 * 24 * 60 * 60;
+
+/// `ttl_secs` after `from`, or `None` if that is beyond what a timestamp
+/// can hold. TTLs come from clients, so they are never added unchecked.
+pub fn expiry_after(from: DateTime<Utc>, ttl_secs: u64) -> Option<DateTime<Utc>> {
+    let ttl = i64::try_from(ttl_secs)
+        .ok()
+        .and_then(chrono::TimeDelta::try_seconds)?;
+    from.checked_add_signed(ttl)
+}
+
+/// A time-limited lock on `key`. Clients pass `fencing_token` along with
+/// writes made under the lock so that resources can refuse writes from a
+/// holder whose lease has since passed to someone else.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lease {
+    pub key: String,
+    pub holder: String,
+    pub fencing_token: u64,
+    pub acquired_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub revision: u64,
+}
+
+/// Longest TTL a lease may be taken or renewed for.
+pub const MAX_LOCK_TTL_SECS: u64 = 24 * 60 * 60;
+
+impl Lease {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at <= now
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -241,6 +590,114 @@ pub struct EnrollmentToken {
     pub allowed_tags: Vec<String>,
 }
 
+/// Replicated form of an [`EnrollmentToken`]. Only the SHA-256 of the token
+/// is stored so the Raft log and snapshots never hold a usable secret.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IssuedToken {
+    pub token_hash: String,
+    pub cluster_id: String,
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub allowed_tags: Vec<String>,
+    pub consumed: Option<TokenConsumption>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TokenConsumption {
+    pub node_id: NodeId,
+    pub request_id: String,
+    pub consumed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnrolledNode {
+    pub node_id: NodeId,
+    pub hostname: String,
+    pub addr: String,
+    pub tags: Vec<String>,
+    pub enrolled_at: DateTime<Utc>,
+    #[serde(default)]
+    pub cert_serial: Option<String>,
+    /// Serials of earlier certificates replaced by renewal. They stay valid
+    /// until they expire, so revoking the node revokes them too. Ones whose
+    /// expiry is recorded in `cert_expiry` are dropped once past it.
+    #[serde(default)]
+    pub superseded_serials: Vec<String>,
+    /// Key identifier of the CA that signed `cert_serial`.
+    #[serde(default)]
+    pub issuer_key_id: Option<String>,
+    /// Expiry of the certificates in `cert_serial` and `superseded_serials`,
+    /// where known.
+    #[serde(default)]
+    pub cert_expiry: HashMap<String, DateTime<Utc>>,
+}
+
+impl EnrolledNode {
+    /// Makes `serial` the current certificate, keeping the previous one as
+    /// superseded, and drops superseded serials that expired before `now`.
+    pub fn replace_cert(
+        &mut self,
+        serial: Option<String>,
+        not_after: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) {
+        self.superseded_serials.extend(self.cert_serial.take());
+        if let (Some(serial), Some(not_after)) = (&serial, not_after) {
+            self.cert_expiry.insert(serial.clone(), not_after);
+        }
+        self.cert_serial = serial;
+        self.prune_expired(now);
+    }
+
+    /// Drops superseded serials of certificates that expired before `now`.
+    /// No peer accepts those any more, so they need not be revoked. Serials
+    /// recorded without an expiry are kept.
+    pub fn prune_expired(&mut self, now: DateTime<Utc>) {
+        let expiry = &self.cert_expiry;
+        self.superseded_serials
+            .retain(|serial| expiry.get(serial).is_none_or(|not_after| *not_after > now));
+
+        let current = &self.cert_serial;
+        let superseded = &self.superseded_serials;
+        self.cert_expiry
+            .retain(|serial, _| current.as_ref() == Some(serial) || superseded.contains(serial));
+    }
+
+    /// The current serial and the superseded ones still valid at `now`.
+    pub fn live_serials(&self, now: DateTime<Utc>) -> Vec<String> {
+        let mut node = self.clone();
+        node.prune_expired(now);
+        node.cert_serial.into_iter().chain(node.superseded_serials).collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevokedCertificate {
+    pub serial: String,
+    pub node_id: NodeId,
+    pub revoked_at: DateTime<Utc>,
+    pub reason: Option<String>,
+}
+
+/// Root certificates every node trusts, published by the node holding the
+/// signing CA. During a CA rollover `roots_pem` lists both the outgoing and
+/// incoming roots; nodes whose certificate was not issued by
+/// `signing_key_id` renew it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TrustBundle {
+    pub version: u64,
+    pub roots_pem: String,
+    pub signing_key_id: String,
+}
+
+/// PEM-encoded CRL signed by the cluster CA. `number` increases with every
+/// revocation so nodes can tell whether the copy they loaded is current.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SignedCrl {
+    pub number: u64,
+    pub pem: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EnrollmentResponse {
     pub node_id: NodeId,
@@ -255,3 +712,56 @@ pub struct PeerInfo {
     pub addr: String,
     pub is_voter: bool,
 }
+
+/// Free-form JSON inside replicated types. Binary formats cannot carry a
+/// schemaless value, so there it is stored as a JSON string; JSON APIs see
+/// it unchanged.
+pub mod embedded_json {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use serde_json::Value;
+
+    pub fn serialize<S: Serializer>(value: &Value, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            value.serialize(serializer)
+        } else {
+            serializer.serialize_str(&value.to_string())
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Value, D::Error> {
+        if deserializer.is_human_readable() {
+            Value::deserialize(deserializer)
+        } else {
+            let text = String::deserialize(deserializer)?;
+            serde_json::from_str(&text).map_err(D::Error::custom)
+        }
+    }
+
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(
+            value: &Option<Value>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            if serializer.is_human_readable() {
+                value.serialize(serializer)
+            } else {
+                value.as_ref().map(Value::to_string).serialize(serializer)
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Value>, D::Error> {
+            if deserializer.is_human_readable() {
+                Option::<Value>::deserialize(deserializer)
+            } else {
+                Option::<String>::deserialize(deserializer)?
+                    .map(|text| serde_json::from_str(&text).map_err(D::Error::custom))
+                    .transpose()
+            }
+        }
+    }
+}
@@ -169,6 +169,7 @@ fn test_sync_from_cluster() {
             capabilities: vec![],
             metadata: HashMap::new(),
             created_at: Utc::now(),
+            revision: 0,
         },
         Attachment {
             id: "attach-2".to_string(),
@@ -179,6 +180,7 @@ fn test_sync_from_cluster() {
             capabilities: vec![],
             metadata: HashMap::new(),
             created_at: Utc::now(),
+            revision: 0,
         },
     ];
 
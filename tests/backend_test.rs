@@ -0,0 +1,281 @@
+use flockmind::replicator::backend::{
+    open_backend, rotate_key, BackendKind, EncryptedBackend, EncryptionKey, FileBackend,
+    MemoryBackend, SledBackend, StorageBackend,
+};
+use flockmind::replicator::{HiveStorage, SharedState};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+fn record(index: u64) -> (u64, Vec<u8>) {
+    (index, format!("entry-{}", index).into_bytes())
+}
+
+fn indexes(backend: &dyn StorageBackend, start: u64, end: Option<u64>) -> Vec<u64> {
+    backend
+        .read_log(start, end)
+        .unwrap()
+        .into_iter()
+        .map(|(index, _)| index)
+        .collect()
+}
+
+fn meta(key: &str, value: Option<&str>) -> (Vec<u8>, Option<Vec<u8>>) {
+    (key.as_bytes().to_vec(), value.map(|v| v.as_bytes().to_vec()))
+}
+
+fn new_key() -> Arc<EncryptionKey> {
+    Arc::new(EncryptionKey::from_hex(&EncryptionKey::generate_hex().unwrap()).unwrap())
+}
+
+/// Behaviour every backend must share.
+fn check_backend(backend: &dyn StorageBackend) {
+    assert!(backend.last_log().unwrap().is_none());
+    assert!(backend.read_log(0, None).unwrap().is_empty());
+
+    backend.append_log((1..=10).map(record).collect()).unwrap();
+    assert_eq!(backend.last_log().unwrap(), Some(record(10)));
+    assert_eq!(indexes(backend, 3, Some(6)), vec![3, 4, 5]);
+    assert_eq!(indexes(backend, 8, None), vec![8, 9, 10]);
+    assert_eq!(backend.read_log(2, Some(3)).unwrap(), vec![record(2)]);
+
+    // Appending over an existing index replaces the record.
+    backend
+        .append_log(vec![(10, b"replaced".to_vec())])
+        .unwrap();
+    assert_eq!(
+        backend.last_log().unwrap(),
+        Some((10, b"replaced".to_vec()))
+    );
+
+    backend.truncate_log(8).unwrap();
+    assert_eq!(backend.last_log().unwrap(), Some(record(7)));
+
+    backend.purge_log(3).unwrap();
+    assert_eq!(indexes(backend, 0, None), vec![4, 5, 6, 7]);
+
+    backend.purge_log(7).unwrap();
+    assert!(backend.last_log().unwrap().is_none());
+
+    assert!(backend.get_meta(b"vote").unwrap().is_none());
+    backend
+        .write_meta(vec![meta("vote", Some("v1")), meta("committed", Some("c1"))], true)
+        .unwrap();
+    assert_eq!(backend.get_meta(b"vote").unwrap(), Some(b"v1".to_vec()));
+    assert_eq!(
+        backend.get_meta(b"committed").unwrap(),
+        Some(b"c1".to_vec())
+    );
+
+    let mut keys = backend.meta_keys().unwrap();
+    keys.sort();
+    assert_eq!(keys, vec![b"committed".to_vec(), b"vote".to_vec()]);
+
+    backend
+        .write_meta(vec![meta("vote", Some("v2")), meta("committed", None)], false)
+        .unwrap();
+    assert_eq!(backend.get_meta(b"vote").unwrap(), Some(b"v2".to_vec()));
+    assert!(backend.get_meta(b"committed").unwrap().is_none());
+    assert_eq!(backend.meta_keys().unwrap(), vec![b"vote".to_vec()]);
+}
+
+#[test]
+fn test_memory_backend() {
+    check_backend(&MemoryBackend::default());
+}
+
+#[test]
+fn test_sled_backend() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    check_backend(&SledBackend::with_db(db).unwrap());
+}
+
+#[test]
+fn test_file_backend() {
+    let dir = TempDir::new().unwrap();
+    check_backend(&FileBackend::open(dir.path()).unwrap());
+}
+
+#[test]
+fn test_file_backend_survives_reopen() {
+    let dir = TempDir::new().unwrap();
+
+    {
+        let backend = FileBackend::open(dir.path()).unwrap();
+        backend.append_log((1..=5).map(record).collect()).unwrap();
+        backend.truncate_log(5).unwrap();
+        backend.purge_log(1).unwrap();
+        backend
+            .write_meta(vec![meta("vote", Some("v1"))], true)
+            .unwrap();
+    }
+
+    let backend = FileBackend::open(dir.path()).unwrap();
+    assert_eq!(indexes(&backend, 0, None), vec![2, 3, 4]);
+    assert_eq!(backend.get_meta(b"vote").unwrap(), Some(b"v1".to_vec()));
+}
+
+#[test]
+fn test_file_backend_discards_torn_write() {
+    let dir = TempDir::new().unwrap();
+
+    {
+        let backend = FileBackend::open(dir.path()).unwrap();
+        backend.append_log((1..=3).map(record).collect()).unwrap();
+    }
+
+    // A frame header promising more bytes than were written.
+    let log_path = dir.path().join("log.journal");
+    let mut file = OpenOptions::new().append(true).open(&log_path).unwrap();
+    file.write_all(&[64, 0, 0, 0, 1, 2, 3, 4, 5]).unwrap();
+    drop(file);
+
+    let backend = FileBackend::open(dir.path()).unwrap();
+    assert_eq!(indexes(&backend, 0, None), vec![1, 2, 3]);
+
+    // Later writes land after the last whole frame and survive a reopen.
+    backend.append_log(vec![record(4)]).unwrap();
+    drop(backend);
+    let backend = FileBackend::open(dir.path()).unwrap();
+    assert_eq!(indexes(&backend, 0, None), vec![1, 2, 3, 4]);
+}
+
+#[test]
+fn test_file_backend_refuses_corrupt_frame() {
+    let dir = TempDir::new().unwrap();
+
+    {
+        let backend = FileBackend::open(dir.path()).unwrap();
+        for i in 1..=3 {
+            backend.append_log(vec![record(i)]).unwrap();
+        }
+    }
+
+    // Damage the body of the first of three frames.
+    let log_path = dir.path().join("log.journal");
+    let mut contents = std::fs::read(&log_path).unwrap();
+    let len = contents.len();
+    contents[12] ^= 0xff;
+    std::fs::write(&log_path, &contents).unwrap();
+
+    let err = FileBackend::open(dir.path()).err().unwrap();
+    assert!(err.to_string().contains("corrupt at byte 0"), "{}", err);
+    assert_eq!(std::fs::metadata(&log_path).unwrap().len(), len as u64);
+}
+
+#[test]
+fn test_open_backend_refuses_other_backends_state() {
+    let dir = TempDir::new().unwrap();
+
+    {
+        let backend = open_backend(BackendKind::File, dir.path()).unwrap();
+        backend.append_log(vec![record(1)]).unwrap();
+    }
+
+    let err = open_backend(BackendKind::Sled, dir.path()).err().unwrap();
+    assert!(err.to_string().contains("File"), "{}", err);
+
+    let backend = open_backend(BackendKind::File, dir.path()).unwrap();
+    assert_eq!(backend.last_log().unwrap(), Some(record(1)));
+}
+
+#[test]
+fn test_encrypted_backend() {
+    check_backend(&EncryptedBackend::new(
+        Arc::new(MemoryBackend::default()),
+        new_key(),
+    ));
+}
+
+#[test]
+fn test_encrypted_backend_hides_records() {
+    let inner = Arc::new(MemoryBackend::default());
+    let backend = EncryptedBackend::new(inner.clone(), new_key());
+    backend.append_log(vec![record(1)]).unwrap();
+    backend
+        .write_meta(vec![meta("vote", Some("secret-vote"))], true)
+        .unwrap();
+
+    let (_, stored) = inner.last_log().unwrap().unwrap();
+    assert!(!stored.windows(7).any(|w| w == b"entry-1"));
+    let stored = inner.get_meta(b"vote").unwrap().unwrap();
+    assert!(!stored.windows(6).any(|w| w == b"secret"));
+}
+
+#[test]
+fn test_encrypted_backend_rejects_wrong_key_and_moved_records() {
+    let inner = Arc::new(MemoryBackend::default());
+    let key = new_key();
+    EncryptedBackend::new(inner.clone(), key.clone())
+        .append_log(vec![record(1)])
+        .unwrap();
+
+    let other = EncryptedBackend::new(inner.clone(), new_key());
+    let err = other.read_log(0, None).unwrap_err();
+    assert!(err.to_string().contains(&key.id()), "{}", err);
+
+    // A record copied to another index no longer authenticates.
+    let (_, stored) = inner.last_log().unwrap().unwrap();
+    inner.append_log(vec![(2, stored)]).unwrap();
+    let backend = EncryptedBackend::new(inner.clone(), key);
+    assert_eq!(backend.read_log(1, Some(2)).unwrap(), vec![record(1)]);
+    let err = backend.read_log(2, None).unwrap_err();
+    assert!(err.to_string().contains("log entry 2"), "{}", err);
+}
+
+#[test]
+fn test_rotate_key_encrypts_then_rekeys() {
+    let inner = Arc::new(MemoryBackend::default());
+    inner.append_log((1..=3).map(record).collect()).unwrap();
+    inner
+        .write_meta(vec![meta("vote", Some("v1"))], true)
+        .unwrap();
+
+    // Plaintext storage is refused until it has been encrypted.
+    let first = new_key();
+    assert!(EncryptedBackend::new(inner.clone(), first.clone())
+        .read_log(0, None)
+        .is_err());
+
+    let summary = rotate_key(inner.as_ref(), None, &first).unwrap();
+    assert_eq!((summary.log_entries, summary.meta_records), (3, 1));
+    let backend = EncryptedBackend::new(inner.clone(), first.clone());
+    assert_eq!(indexes(&backend, 0, None), vec![1, 2, 3]);
+
+    let second = new_key();
+    assert!(rotate_key(inner.as_ref(), None, &second).is_err());
+    rotate_key(inner.as_ref(), Some(&first), &second).unwrap();
+
+    // Running it again finds nothing left under the old key.
+    let summary = rotate_key(inner.as_ref(), Some(&first), &second).unwrap();
+    assert_eq!((summary.log_entries, summary.meta_records), (0, 0));
+
+    let backend = EncryptedBackend::new(inner.clone(), second);
+    assert_eq!(backend.last_log().unwrap(), Some(record(3)));
+    assert_eq!(backend.get_meta(b"vote").unwrap(), Some(b"v1".to_vec()));
+    assert!(EncryptedBackend::new(inner.clone(), first)
+        .read_log(0, None)
+        .is_err());
+}
+
+#[test]
+fn test_encrypted_storage_needs_its_key() {
+    let inner: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::default());
+    let encrypted = Arc::new(EncryptedBackend::new(inner.clone(), new_key()));
+    HiveStorage::new(encrypted, SharedState::new()).unwrap();
+
+    let err = HiveStorage::new(inner, SharedState::new()).err().unwrap();
+    assert!(err.to_string().contains("encrypted"), "{}", err);
+}
+
+#[test]
+fn test_encryption_key_parsing() {
+    let hex = EncryptionKey::generate_hex().unwrap();
+    assert_eq!(hex.len(), 64);
+    let key = EncryptionKey::from_hex(&format!("{}\n", hex)).unwrap();
+    assert_eq!(key.id(), EncryptionKey::from_hex(&hex).unwrap().id());
+
+    assert!(EncryptionKey::from_hex(&hex[..62]).is_err());
+    assert!(EncryptionKey::from_hex(&"zz".repeat(32)).is_err());
+}
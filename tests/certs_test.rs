@@ -129,3 +129,160 @@ fn test_create_client_tls_config() {
 
     let _config = create_client_tls_config(&node_cert, &ca.cert_pem).unwrap();
 }
+
+#[test]
+fn test_node_certificate_serial() {
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let cert1 = ca.sign_node("node-1", vec![], vec![]).unwrap();
+    let cert2 = ca.sign_node("node-1", vec![], vec![]).unwrap();
+
+    let serial = cert1.serial().unwrap();
+    assert!(!serial.is_empty());
+    assert!(serial.chars().all(|c| c.is_ascii_hexdigit()));
+    assert_ne!(serial, cert2.serial().unwrap());
+}
+
+#[test]
+fn test_sign_crl_lists_revoked_serials() {
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let revoked_cert = ca.sign_node("node-1", vec![], vec![]).unwrap();
+    let serial = revoked_cert.serial().unwrap();
+
+    let crl_pem = ca
+        .sign_crl(
+            &[flockmind::RevokedCertificate {
+                serial: serial.clone(),
+                node_id: "node-1".to_string(),
+                revoked_at: chrono::Utc::now(),
+                reason: Some("compromised".to_string()),
+            }],
+            1,
+        )
+        .unwrap();
+
+    assert!(crl_pem.contains("BEGIN X509 CRL"));
+    assert_eq!(revoked_serials_from_crl(&crl_pem).unwrap(), vec![serial]);
+}
+
+#[test]
+fn test_create_tls_config_with_crls() {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let node_cert = ca.sign_node("node-1", vec![], vec![]).unwrap();
+    let crls = vec![ca.sign_crl(&[], 1).unwrap()];
+
+    create_tls_config_with_crls(&node_cert, &ca.cert_pem, &crls).unwrap();
+    create_client_tls_config_with_crls(&node_cert, &ca.cert_pem, &crls).unwrap();
+}
+
+#[test]
+fn test_sign_node_sets_validity() {
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let node_cert = ca.sign_node("node-1", vec![], vec![]).unwrap();
+
+    let remaining = node_cert.not_after().unwrap() - chrono::Utc::now();
+    assert!(remaining <= chrono::Duration::days(NODE_CERT_VALIDITY_DAYS));
+    assert!(remaining > chrono::Duration::days(NODE_CERT_VALIDITY_DAYS - 1));
+}
+
+#[test]
+fn test_sign_node_with_validity() {
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let node_cert = ca
+        .sign_node_with_validity("node-1", vec![], vec![], chrono::Duration::hours(1))
+        .unwrap();
+
+    let remaining = node_cert.not_after().unwrap() - chrono::Utc::now();
+    assert!(remaining <= chrono::Duration::hours(1));
+    assert!(remaining > chrono::Duration::minutes(59));
+}
+
+#[test]
+fn test_subject_alt_names_round_trip() {
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let node_cert = ca
+        .sign_node(
+            "node-1",
+            vec!["host1.local".to_string(), "localhost".to_string()],
+            vec!["127.0.0.1".to_string(), "::1".to_string()],
+        )
+        .unwrap();
+
+    let (hostnames, ips) = node_cert.subject_alt_names().unwrap();
+    assert_eq!(hostnames, vec!["host1.local", "localhost"]);
+    assert_eq!(ips, vec!["127.0.0.1", "::1"]);
+}
+
+#[test]
+fn test_ca_load_rejects_mismatched_key() {
+    let temp_dir = TempDir::new().unwrap();
+    let cert_path = temp_dir.path().join("ca.crt");
+    let key_path = temp_dir.path().join("ca.key");
+
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    ca.save(&cert_path, &key_path).unwrap();
+    let other = CaCertificate::generate("test-cluster").unwrap();
+    other
+        .save(temp_dir.path().join("unused.crt"), key_path.clone())
+        .unwrap();
+
+    assert!(CaCertificate::load(&cert_path, &key_path).is_err());
+}
+
+#[test]
+fn test_issued_certificate_names_saved_ca_as_issuer() {
+    let temp_dir = TempDir::new().unwrap();
+    let cert_path = temp_dir.path().join("ca.crt");
+    let key_path = temp_dir.path().join("ca.key");
+
+    CaCertificate::generate("test-cluster")
+        .unwrap()
+        .save(&cert_path, &key_path)
+        .unwrap();
+    let ca = CaCertificate::load(&cert_path, &key_path).unwrap();
+    let node_cert = ca.sign_node("node-1", vec![], vec![]).unwrap();
+
+    let ca_pem = pem::parse(&ca.cert_pem).unwrap();
+    let (_, ca_x509) = x509_parser::parse_x509_certificate(ca_pem.contents()).unwrap();
+    let node_pem = pem::parse(&node_cert.cert_pem).unwrap();
+    let (_, node_x509) = x509_parser::parse_x509_certificate(node_pem.contents()).unwrap();
+
+    assert_eq!(node_x509.issuer().as_raw(), ca_x509.subject().as_raw());
+}
+
+#[test]
+fn test_intermediate_signs_node_with_chain() {
+    let root = CaCertificate::generate_root("test-cluster").unwrap();
+    let intermediate = root.issue_intermediate("test-cluster").unwrap();
+
+    assert!(root.is_root());
+    assert!(!intermediate.is_root());
+    assert!(root.chain_pem().is_empty());
+
+    let node_cert = intermediate.sign_node("node-1", vec![], vec![]).unwrap();
+    assert_eq!(node_cert.cert_chain_der().unwrap().len(), 2);
+    assert_eq!(
+        node_cert.issuer_key_id().unwrap(),
+        Some(intermediate.key_id().unwrap())
+    );
+    assert_ne!(root.key_id().unwrap(), intermediate.key_id().unwrap());
+}
+
+#[test]
+fn test_intermediate_save_load_keeps_chain() {
+    let temp_dir = TempDir::new().unwrap();
+    let cert_path = temp_dir.path().join("intermediate.crt");
+    let key_path = temp_dir.path().join("intermediate.key");
+
+    let root = CaCertificate::generate_root("test-cluster").unwrap();
+    let intermediate = root.issue_intermediate("test-cluster").unwrap();
+    intermediate.save(&cert_path, &key_path).unwrap();
+
+    let loaded = CaCertificate::load(&cert_path, &key_path).unwrap();
+    assert!(!loaded.is_root());
+    assert_eq!(loaded.key_id().unwrap(), intermediate.key_id().unwrap());
+
+    let node_cert = loaded.sign_node("node-1", vec![], vec![]).unwrap();
+    assert_eq!(node_cert.cert_chain_der().unwrap().len(), 2);
+}
@@ -0,0 +1,293 @@
+mod common;
+
+use common::goal;
+use flockmind::replicator::{BackendKind, HiveNode, SnapshotConfig, StorageConfig};
+use flockmind::{create_raft_router, server, ClusterCommand, Goal, PeerInfo, RaftReplicator, Replicator};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::net::TcpListener;
+
+struct TestNode {
+    node_id: String,
+    raft_id: u64,
+    addr: String,
+    replicator: Arc<RaftReplicator>,
+    _data_dir: TempDir,
+}
+
+async fn start_node(node_id: &str, raft_id: u64) -> TestNode {
+    start_node_with(node_id, raft_id, SnapshotConfig::default()).await
+}
+
+async fn start_node_with(node_id: &str, raft_id: u64, snapshot: SnapshotConfig) -> TestNode {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let data_dir = TempDir::new().unwrap();
+
+    let replicator = Arc::new(
+        RaftReplicator::new(
+            raft_id,
+            addr.clone(),
+            node_id.to_string(),
+            data_dir.path(),
+            None,
+            snapshot,
+            StorageConfig {
+                backend: BackendKind::Memory,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap(),
+    );
+
+    let router = create_raft_router(replicator.clone());
+    tokio::spawn(async move {
+        let _ = server::serve(listener, router, None).await;
+    });
+
+    TestNode {
+        node_id: node_id.to_string(),
+        raft_id,
+        addr,
+        replicator,
+        _data_dir: data_dir,
+    }
+}
+
+async fn wait_for(what: &str, mut condition: impl FnMut() -> bool) {
+    for _ in 0..100 {
+        if condition() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("timed out waiting for {}", what);
+}
+
+/// Bootstraps `leader` as a single-voter cluster and adds `learner`.
+async fn form_cluster(leader: &TestNode, learner: &TestNode) {
+    let mut members = BTreeMap::new();
+    members.insert(
+        leader.raft_id,
+        HiveNode {
+            addr: leader.addr.clone(),
+            hostname: leader.node_id.clone(),
+        },
+    );
+    leader.replicator.raft().initialize(members).await.unwrap();
+    wait_for("leader election", || leader.replicator.is_leader()).await;
+
+    for node in [leader, learner] {
+        leader
+            .replicator
+            .apply(ClusterCommand::AssignRaftId {
+                node_id: node.node_id.clone(),
+                raft_id: node.raft_id,
+            })
+            .await
+            .unwrap();
+    }
+
+    leader
+        .replicator
+        .add_peer(PeerInfo {
+            node_id: learner.node_id.clone(),
+            addr: learner.addr.clone(),
+            is_voter: false,
+        })
+        .await
+        .unwrap();
+    wait_for("learner to see the leader", || {
+        learner.replicator.leader_id().is_some()
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_write_on_follower_is_forwarded_to_leader() {
+    let node1 = start_node("node-1", 1).await;
+    let node2 = start_node("node-2", 2).await;
+    form_cluster(&node1, &node2).await;
+
+    assert!(!node2.replicator.is_leader());
+    let goal = goal("forwarded");
+    let result = node2
+        .replicator
+        .apply(ClusterCommand::PutGoalIf {
+            goal: goal.clone(),
+            expected_revision: 0,
+        })
+        .await
+        .unwrap();
+    assert!(result.is_applied());
+
+    assert!(node1.replicator.snapshot().goals.iter().any(|g| g.id == goal.id));
+    wait_for("write to replicate back to the follower", || {
+        node2.replicator.snapshot().goals.iter().any(|g| g.id == goal.id)
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_linearizable_read_on_follower_sees_committed_write() {
+    let node1 = start_node("node-1", 1).await;
+    let node2 = start_node("node-2", 2).await;
+    form_cluster(&node1, &node2).await;
+
+    for i in 0..5 {
+        let goal = goal(&format!("goal-{}", i));
+        node1
+            .replicator
+            .apply(ClusterCommand::PutGoal(goal.clone()))
+            .await
+            .unwrap();
+
+        node2.replicator.ensure_linearizable().await.unwrap();
+        assert!(node2.replicator.snapshot().goals.iter().any(|g| g.id == goal.id));
+    }
+}
+
+#[tokio::test]
+async fn test_leader_reported_by_node_id() {
+    let node1 = start_node("production-web-1", 1).await;
+    let node2 = start_node("production-web-2", 2).await;
+    form_cluster(&node1, &node2).await;
+
+    wait_for("follower to learn the registry", || {
+        node2.replicator.leader_id().as_deref() == Some("production-web-1")
+    })
+    .await;
+    assert_eq!(
+        node2.replicator.snapshot().leader_id.as_deref(),
+        Some("production-web-1")
+    );
+}
+
+#[tokio::test]
+async fn test_bootstrap_forms_multi_voter_cluster() {
+    let node1 = start_node("node-1", 1).await;
+    let node2 = start_node("node-2", 2).await;
+
+    for (node, other) in [(&node1, &node2), (&node2, &node1)] {
+        let voters = BTreeMap::from([(
+            other.raft_id,
+            HiveNode {
+                addr: other.addr.clone(),
+                hostname: other.node_id.clone(),
+            },
+        )]);
+        node.replicator.bootstrap(voters).await.unwrap();
+    }
+    wait_for("leader election", || {
+        node1.replicator.is_leader() || node2.replicator.is_leader()
+    })
+    .await;
+
+    let metrics = node1.replicator.raft().metrics().borrow().clone();
+    let voters: Vec<u64> = metrics.membership_config.membership().voter_ids().collect();
+    assert_eq!(voters, vec![1, 2]);
+    assert!(node1.replicator.is_member());
+
+    node1.replicator.bootstrap(BTreeMap::new()).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_promote_and_transfer_leadership() {
+    let node1 = start_node("node-1", 1).await;
+    let node2 = start_node("node-2", 2).await;
+    form_cluster(&node1, &node2).await;
+
+    let err = node1.replicator.transfer_leadership("node-2").await.unwrap_err();
+    assert!(err.to_string().contains("learner"));
+
+    node1.replicator.promote_peer("node-2").await.unwrap();
+    wait_for("node-2 to catch up", || {
+        node1
+            .replicator
+            .members()
+            .iter()
+            .any(|m| m.node_id == "node-2" && m.is_voter && m.lag == Some(0))
+    })
+    .await;
+
+    let members = node1.replicator.members();
+    assert_eq!(members.len(), 2);
+    assert!(members.iter().any(|m| m.node_id == "node-1" && m.is_leader));
+
+    node1.replicator.transfer_leadership("node-2").await.unwrap();
+    assert!(node2.replicator.is_leader());
+    wait_for("node-1 to follow node-2", || {
+        node1.replicator.leader_id().as_deref() == Some("node-2")
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_new_learner_catches_up_from_snapshot() {
+    let snapshot = SnapshotConfig {
+        logs_since_last: 10,
+        logs_to_keep: 0,
+    };
+    let node1 = start_node_with("node-1", 1, snapshot).await;
+    let node2 = start_node("node-2", 2).await;
+
+    let members = BTreeMap::from([(
+        node1.raft_id,
+        HiveNode {
+            addr: node1.addr.clone(),
+            hostname: node1.node_id.clone(),
+        },
+    )]);
+    node1.replicator.raft().initialize(members).await.unwrap();
+    wait_for("leader election", || node1.replicator.is_leader()).await;
+
+    for node in [&node1, &node2] {
+        node1
+            .replicator
+            .apply(ClusterCommand::AssignRaftId {
+                node_id: node.node_id.clone(),
+                raft_id: node.raft_id,
+            })
+            .await
+            .unwrap();
+    }
+    let goals: Vec<Goal> = (0..30).map(|i| goal(&format!("goal-{}", i))).collect();
+    for goal in &goals {
+        node1
+            .replicator
+            .apply(ClusterCommand::PutGoalIf {
+                goal: goal.clone(),
+                expected_revision: 0,
+            })
+            .await
+            .unwrap();
+    }
+
+    wait_for("leader to snapshot and purge its log", || {
+        let metrics = node1.replicator.raft().metrics().borrow().clone();
+        metrics.snapshot.is_some() && metrics.purged.is_some()
+    })
+    .await;
+    let purged = node1.replicator.raft().metrics().borrow().purged.unwrap();
+
+    node1
+        .replicator
+        .add_peer(PeerInfo {
+            node_id: node2.node_id.clone(),
+            addr: node2.addr.clone(),
+            is_voter: false,
+        })
+        .await
+        .unwrap();
+
+    wait_for("learner to receive every goal", || {
+        node2.replicator.snapshot().goals.len() == goals.len()
+    })
+    .await;
+    let installed = node2.replicator.raft().metrics().borrow().snapshot;
+    assert!(installed.is_some_and(|log_id| log_id.index >= purged.index));
+    assert_eq!(node2.replicator.leader_id().as_deref(), Some("node-1"));
+}
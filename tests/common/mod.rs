@@ -0,0 +1,123 @@
+#![allow(dead_code)]
+
+use async_trait::async_trait;
+use chrono::Utc;
+use flockmind::replicator::backend::{MemoryBackend, StorageBackend};
+use flockmind::replicator::state_machine::{HiveState, SharedState};
+use flockmind::replicator::TypeConfig;
+use flockmind::*;
+use openraft::{CommittedLeaderId, Entry, EntryPayload, LogId};
+use std::sync::Arc;
+
+/// Single-process stand-in for `RaftReplicator` that applies commands
+/// directly to a `SharedState`.
+#[derive(Clone, Default)]
+pub struct LocalReplicator {
+    state: SharedState,
+}
+
+impl LocalReplicator {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn shared_state(&self) -> &SharedState {
+        &self.state
+    }
+}
+
+#[async_trait]
+impl Replicator for LocalReplicator {
+    async fn apply(&self, command: ClusterCommand) -> anyhow::Result<CommandResult> {
+        tokio::task::yield_now().await;
+        Ok(self.state.apply(&command))
+    }
+
+    async fn ensure_linearizable(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn snapshot(&self) -> ClusterView {
+        self.state.to_cluster_view(Some("local".to_string()), 1)
+    }
+
+    fn hive_state(&self) -> HiveState {
+        self.state.snapshot()
+    }
+
+    fn is_leader(&self) -> bool {
+        true
+    }
+
+    fn leader_id(&self) -> Option<NodeId> {
+        Some("local".to_string())
+    }
+
+    async fn add_peer(&self, _peer: PeerInfo) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn remove_peer(&self, _node_id: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+pub fn register_node(node_id: &str) -> ClusterCommand {
+    ClusterCommand::RegisterNode(NodeStatus {
+        node_id: node_id.to_string(),
+        hostname: node_id.to_string(),
+        tags: vec![],
+        health: NodeHealth::Healthy,
+        last_heartbeat: Utc::now(),
+        cpu_usage: 0.0,
+        memory_usage: 0.0,
+        disk_usage: 0.0,
+        revision: 0,
+    })
+}
+
+pub fn task(id: &str, target_node: &str) -> Task {
+    Task {
+        id: id.to_string(),
+        target_node: target_node.to_string(),
+        payload: TaskPayload::Echo {
+            message: "hi".to_string(),
+        },
+        status: TaskStatus::Pending,
+        priority: 5,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        result: None,
+        revision: 0,
+        lock: None,
+    }
+}
+
+pub fn goal(description: &str) -> Goal {
+    Goal {
+        id: uuid::Uuid::new_v4().to_string(),
+        description: description.to_string(),
+        constraints: vec![],
+        priority: 5,
+        active: true,
+        created_at: Utc::now(),
+        revision: 0,
+    }
+}
+
+pub fn log_id(index: u64) -> LogId<u64> {
+    LogId::new(CommittedLeaderId::new(1, 1), index)
+}
+
+pub fn entry(index: u64, command: ClusterCommand) -> Entry<TypeConfig> {
+    Entry {
+        log_id: log_id(index),
+        payload: EntryPayload::Normal(command),
+    }
+}
+
+/// A backend that outlives the `HiveStorage` instances opened on it, so
+/// tests can restart storage over the same records.
+pub fn memory_backend() -> Arc<dyn StorageBackend> {
+    Arc::new(MemoryBackend::default())
+}
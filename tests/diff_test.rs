@@ -0,0 +1,170 @@
+mod common;
+
+use chrono::Utc;
+use common::{goal, register_node, task};
+use flockmind::replicator::diff::*;
+use flockmind::replicator::state_machine::*;
+use flockmind::*;
+use std::collections::HashMap;
+
+fn report_health(node_id: &str, health: NodeHealth) -> ClusterCommand {
+    ClusterCommand::UpdateNodeHealth {
+        node_id: node_id.to_string(),
+        health,
+        metrics: NodeMetrics {
+            cpu_usage: 0.9,
+            memory_usage: 0.5,
+            disk_usage: 0.5,
+        },
+        expected_revision: None,
+    }
+}
+
+fn attachment(id: &str, capabilities: &[&str]) -> Attachment {
+    Attachment {
+        id: id.to_string(),
+        node_id: "web-1".to_string(),
+        kind: AttachmentKind::Directory {
+            path: "/srv".to_string(),
+        },
+        capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+        metadata: HashMap::new(),
+        created_at: Utc::now(),
+        revision: 0,
+    }
+}
+
+fn apply_all(state: &mut HiveState, commands: Vec<ClusterCommand>) {
+    for command in commands {
+        assert!(state.apply(&command).is_applied());
+    }
+}
+
+#[test]
+fn test_identical_states_have_no_changes() {
+    let mut state = HiveState::new();
+    apply_all(&mut state, vec![register_node("web-1")]);
+
+    let mut later = state.clone();
+    // Heartbeats and metrics alone are not reported.
+    apply_all(&mut later, vec![report_health("web-1", NodeHealth::Healthy)]);
+
+    let diff = StateDiff::between(&state, &later);
+    assert!(diff.is_empty());
+    assert_eq!(diff.to_string(), "No changes\n");
+}
+
+#[test]
+fn test_diff_reports_structural_changes() {
+    let goal_1 = Goal {
+        id: "goal-1".to_string(),
+        ..goal("keep disks below 80%")
+    };
+    let mut old = HiveState::new();
+    apply_all(
+        &mut old,
+        vec![
+            register_node("web-1"),
+            register_node("web-2"),
+            ClusterCommand::PutTask(task("task-1", "web-1")),
+            ClusterCommand::PutGoal(goal_1.clone()),
+            ClusterCommand::PutAttachment(attachment("att-1", &["read"])),
+        ],
+    );
+
+    let mut new = old.clone();
+    apply_all(
+        &mut new,
+        vec![
+            ClusterCommand::RemoveNode {
+                node_id: "web-2".to_string(),
+            },
+            register_node("web-3"),
+            report_health(
+                "web-1",
+                NodeHealth::Degraded {
+                    reason: "high cpu".to_string(),
+                },
+            ),
+            ClusterCommand::UpdateTaskStatus {
+                task_id: "task-1".to_string(),
+                status: TaskStatus::Running,
+                result: None,
+                expected_revision: None,
+            },
+            ClusterCommand::PutTask(task("task-2", "web-3")),
+            ClusterCommand::PutGoal(Goal {
+                priority: 9,
+                active: false,
+                ..goal_1
+            }),
+            ClusterCommand::PutAttachment(attachment("att-1", &["read", "write"])),
+        ],
+    );
+
+    let diff = StateDiff::between(&old, &new);
+    assert_eq!(
+        diff.nodes,
+        vec![
+            NodeChange::HealthChanged {
+                node_id: "web-1".to_string(),
+                from: NodeHealth::Healthy,
+                to: NodeHealth::Degraded {
+                    reason: "high cpu".to_string()
+                },
+            },
+            NodeChange::Removed {
+                node_id: "web-2".to_string()
+            },
+            NodeChange::Added {
+                node_id: "web-3".to_string(),
+                health: NodeHealth::Healthy,
+            },
+        ]
+    );
+    assert_eq!(
+        diff.tasks,
+        vec![
+            TaskChange::StatusChanged {
+                task_id: "task-1".to_string(),
+                from: TaskStatus::Pending,
+                to: TaskStatus::Running,
+            },
+            TaskChange::Added {
+                task_id: "task-2".to_string(),
+                target_node: "web-3".to_string(),
+                status: TaskStatus::Pending,
+            },
+        ]
+    );
+    assert_eq!(
+        diff.goals,
+        vec![
+            GoalChange::Toggled {
+                goal_id: "goal-1".to_string(),
+                active: false,
+            },
+            GoalChange::Changed {
+                goal_id: "goal-1".to_string(),
+                fields: vec!["priority".to_string()],
+            },
+        ]
+    );
+    assert_eq!(
+        diff.attachments,
+        vec![AttachmentChange::Changed {
+            attachment_id: "att-1".to_string(),
+            fields: vec!["capabilities".to_string()],
+        }]
+    );
+
+    let text = diff.to_string();
+    assert!(text.contains("  ~ web-1: healthy -> degraded (high cpu)\n"));
+    assert!(text.contains("  ~ task-1: pending -> running\n"));
+    assert!(text.contains("  ~ goal-1: now inactive\n"));
+
+    let json = serde_json::to_string(&diff).unwrap();
+    assert!(json.contains(r#""change":"health_changed""#));
+    let decoded: StateDiff = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded, diff);
+}
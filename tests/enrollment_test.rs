@@ -1,10 +1,14 @@
-use flockmind::auth::certs::CaCertificate;
+mod common;
+
+use common::LocalReplicator;
+use flockmind::auth::certs::{revoked_serials_from_crl, CaCertificate};
 use flockmind::auth::enrollment::*;
+use std::sync::Arc;
 use tempfile::TempDir;
 
-fn create_test_manager() -> EnrollmentManager {
+fn create_test_manager() -> EnrollmentManager<LocalReplicator> {
     let ca = CaCertificate::generate("test-cluster").unwrap();
-    EnrollmentManager::new("test-cluster".to_string(), ca)
+    EnrollmentManager::new("test-cluster".to_string(), ca, LocalReplicator::new())
 }
 
 #[test]
@@ -17,7 +21,7 @@ fn test_enrollment_manager_new() {
 #[test]
 fn test_load_or_create_new() {
     let temp_dir = TempDir::new().unwrap();
-    let manager = EnrollmentManager::load_or_create(temp_dir.path(), "new-cluster").unwrap();
+    let manager = EnrollmentManager::load_or_create(temp_dir.path(), "new-cluster", LocalReplicator::new()).unwrap();
 
     assert_eq!(manager.cluster_id(), "new-cluster");
     assert!(temp_dir.path().join("ca.crt").exists());
@@ -28,38 +32,39 @@ fn test_load_or_create_new() {
 fn test_load_or_create_existing() {
     let temp_dir = TempDir::new().unwrap();
 
-    let manager1 = EnrollmentManager::load_or_create(temp_dir.path(), "existing-cluster").unwrap();
+    let manager1 = EnrollmentManager::load_or_create(temp_dir.path(), "existing-cluster", LocalReplicator::new()).unwrap();
     let cert_pem = manager1.ca_cert_pem().to_string();
 
-    let manager2 = EnrollmentManager::load_or_create(temp_dir.path(), "existing-cluster").unwrap();
+    let manager2 = EnrollmentManager::load_or_create(temp_dir.path(), "existing-cluster", LocalReplicator::new()).unwrap();
     assert_eq!(manager2.ca_cert_pem(), cert_pem);
 }
 
-#[test]
-fn test_generate_token() {
+#[tokio::test]
+async fn test_generate_token() {
     let manager = create_test_manager();
-    let token = manager.generate_token(24, vec!["gpu".to_string()]);
+    let token = manager.generate_token(24, vec!["gpu".to_string()]).await.unwrap();
 
     assert!(!token.token.is_empty());
     assert_eq!(token.cluster_id, "test-cluster");
     assert_eq!(token.allowed_tags, vec!["gpu".to_string()]);
 }
 
-#[test]
-fn test_enroll_success() {
+#[tokio::test]
+async fn test_enroll_success() {
     let manager = create_test_manager();
-    let token = manager.generate_token(24, vec![]);
+    let token = manager.generate_token(24, vec![]).await.unwrap();
 
     let req = EnrollmentRequest {
         token: token.token,
         node_id: "node-1".to_string(),
         hostname: "host1".to_string(),
+        addr: "127.0.0.1:9001".to_string(),
         hostnames: vec!["localhost".to_string()],
         ips: vec!["127.0.0.1".to_string()],
         tags: vec!["dev".to_string()],
     };
 
-    let resp = manager.enroll(req).unwrap();
+    let resp = manager.enroll(req).await.unwrap();
     assert_eq!(resp.node_id, "node-1");
     assert_eq!(resp.cluster_id, "test-cluster");
     assert!(!resp.node_cert_pem.is_empty());
@@ -67,20 +72,21 @@ fn test_enroll_success() {
     assert!(!resp.ca_cert_pem.is_empty());
 }
 
-#[test]
-fn test_enroll_invalid_token() {
+#[tokio::test]
+async fn test_enroll_invalid_token() {
     let manager = create_test_manager();
 
     let req = EnrollmentRequest {
         token: "invalid-token".to_string(),
         node_id: "node-1".to_string(),
         hostname: "host1".to_string(),
+        addr: "127.0.0.1:9001".to_string(),
         hostnames: vec![],
         ips: vec![],
         tags: vec![],
     };
 
-    let result = manager.enroll(req);
+    let result = manager.enroll(req).await;
     assert!(result.is_err());
     assert!(result
         .unwrap_err()
@@ -88,133 +94,153 @@ fn test_enroll_invalid_token() {
         .contains("Invalid enrollment token"));
 }
 
-#[test]
-fn test_enroll_token_consumed() {
+#[tokio::test]
+async fn test_enroll_token_consumed() {
     let manager = create_test_manager();
-    let token = manager.generate_token(24, vec![]);
+    let token = manager.generate_token(24, vec![]).await.unwrap();
     let token_str = token.token.clone();
 
     let req1 = EnrollmentRequest {
         token: token_str.clone(),
         node_id: "node-1".to_string(),
         hostname: "host1".to_string(),
+        addr: "127.0.0.1:9001".to_string(),
         hostnames: vec![],
         ips: vec![],
         tags: vec![],
     };
 
-    manager.enroll(req1).unwrap();
+    manager.enroll(req1).await.unwrap();
 
     let req2 = EnrollmentRequest {
         token: token_str,
         node_id: "node-2".to_string(),
         hostname: "host2".to_string(),
+        addr: "127.0.0.1:9002".to_string(),
         hostnames: vec![],
         ips: vec![],
         tags: vec![],
     };
 
-    let result = manager.enroll(req2);
+    let result = manager.enroll(req2).await;
     assert!(result.is_err());
 }
 
-#[test]
-fn test_enroll_tag_restriction() {
+#[tokio::test]
+async fn test_enroll_tag_restriction() {
     let manager = create_test_manager();
-    let token = manager.generate_token(24, vec!["gpu".to_string()]);
+    let token = manager.generate_token(24, vec!["gpu".to_string()]).await.unwrap();
 
     let req = EnrollmentRequest {
         token: token.token,
         node_id: "node-1".to_string(),
         hostname: "host1".to_string(),
+        addr: "127.0.0.1:9001".to_string(),
         hostnames: vec![],
         ips: vec![],
         tags: vec!["cpu".to_string()],
     };
 
-    let result = manager.enroll(req);
+    let result = manager.enroll(req).await;
     assert!(result.is_err());
     assert!(result.unwrap_err().to_string().contains("not in allowed tags"));
 }
 
-#[test]
-fn test_enroll_tag_restriction_success() {
+#[tokio::test]
+async fn test_enroll_tag_restriction_success() {
     let manager = create_test_manager();
-    let token = manager.generate_token(24, vec!["gpu".to_string(), "dev".to_string()]);
+    let token = manager
+        .generate_token(24, vec!["gpu".to_string(), "dev".to_string()])
+        .await
+        .unwrap();
 
     let req = EnrollmentRequest {
         token: token.token,
         node_id: "node-1".to_string(),
         hostname: "host1".to_string(),
+        addr: "127.0.0.1:9001".to_string(),
         hostnames: vec![],
         ips: vec![],
         tags: vec!["gpu".to_string()],
     };
 
-    let result = manager.enroll(req);
+    let result = manager.enroll(req).await;
     assert!(result.is_ok());
 }
 
-#[test]
-fn test_register_enrolled_node() {
+#[tokio::test]
+async fn test_register_enrolled_node() {
     let manager = create_test_manager();
 
     assert!(!manager.is_enrolled("node-1"));
 
-    manager.register_enrolled_node(
-        "node-1".to_string(),
-        "host1".to_string(),
-        "127.0.0.1:9000".to_string(),
-        vec!["gpu".to_string()],
-    );
+    manager
+        .register_enrolled_node(
+            "node-1".to_string(),
+            "host1".to_string(),
+            "127.0.0.1:9000".to_string(),
+            vec!["gpu".to_string()],
+        )
+        .await
+        .unwrap();
 
     assert!(manager.is_enrolled("node-1"));
     assert!(!manager.is_enrolled("node-2"));
 }
 
-#[test]
-fn test_get_enrolled_nodes() {
+#[tokio::test]
+async fn test_get_enrolled_nodes() {
     let manager = create_test_manager();
 
-    manager.register_enrolled_node(
-        "node-1".to_string(),
-        "host1".to_string(),
-        "127.0.0.1:9000".to_string(),
-        vec![],
-    );
-    manager.register_enrolled_node(
-        "node-2".to_string(),
-        "host2".to_string(),
-        "127.0.0.1:9001".to_string(),
-        vec![],
-    );
+    manager
+        .register_enrolled_node(
+            "node-1".to_string(),
+            "host1".to_string(),
+            "127.0.0.1:9000".to_string(),
+            vec![],
+        )
+        .await
+        .unwrap();
+    manager
+        .register_enrolled_node(
+            "node-2".to_string(),
+            "host2".to_string(),
+            "127.0.0.1:9001".to_string(),
+            vec![],
+        )
+        .await
+        .unwrap();
 
     let nodes = manager.get_enrolled_nodes();
     assert_eq!(nodes.len(), 2);
 }
 
-#[test]
-fn test_enroll_returns_peers() {
+#[tokio::test]
+async fn test_enroll_returns_peers() {
     let manager = create_test_manager();
 
-    manager.register_enrolled_node(
-        "node-1".to_string(),
-        "host1".to_string(),
-        "127.0.0.1:9000".to_string(),
-        vec![],
-    );
+    manager
+        .register_enrolled_node(
+            "node-1".to_string(),
+            "host1".to_string(),
+            "127.0.0.1:9000".to_string(),
+            vec![],
+        )
+        .await
+        .unwrap();
 
-    let token = manager.generate_token(24, vec![]);
+    let token = manager.generate_token(24, vec![]).await.unwrap();
     let req = EnrollmentRequest {
         token: token.token,
         node_id: "node-2".to_string(),
         hostname: "host2".to_string(),
+        addr: "127.0.0.1:9002".to_string(),
         hostnames: vec![],
         ips: vec![],
         tags: vec![],
     };
 
-    let resp = manager.enroll(req).unwrap();
+    let resp = manager.enroll(req).await.unwrap();
 
     assert_eq!(resp.peers.len(), 1);
     assert_eq!(resp.peers[0].node_id, "node-1");
@@ -232,3 +258,319 @@ fn test_sign_node_cert() {
     assert!(!cert.key_pem.is_empty());
     assert_eq!(cert.node_id, "node-1");
 }
+
+#[tokio::test]
+async fn test_token_issued_on_one_node_is_accepted_by_another() {
+    let replicator = LocalReplicator::new();
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let manager1 = EnrollmentManager::new("test-cluster".to_string(), ca.clone(), replicator.clone());
+    let manager2 = EnrollmentManager::new("test-cluster".to_string(), ca, replicator);
+
+    let token = manager1.generate_token(24, vec![]).await.unwrap();
+
+    let req = EnrollmentRequest {
+        token: token.token,
+        node_id: "node-1".to_string(),
+        hostname: "host1".to_string(),
+        addr: "127.0.0.1:9001".to_string(),
+        hostnames: vec![],
+        ips: vec![],
+        tags: vec![],
+    };
+
+    manager2.enroll(req).await.unwrap();
+    assert!(manager1.is_enrolled("node-1"));
+}
+
+#[tokio::test]
+async fn test_token_consumed_once_when_nodes_race() {
+    let replicator = LocalReplicator::new();
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let manager1 = Arc::new(EnrollmentManager::new(
+        "test-cluster".to_string(),
+        ca.clone(),
+        replicator.clone(),
+    ));
+    let manager2 = Arc::new(EnrollmentManager::new(
+        "test-cluster".to_string(),
+        ca,
+        replicator,
+    ));
+
+    let token = manager1.generate_token(24, vec![]).await.unwrap();
+    let make_req = |node_id: &str| EnrollmentRequest {
+        token: token.token.clone(),
+        node_id: node_id.to_string(),
+        hostname: node_id.to_string(),
+        addr: "127.0.0.1:9001".to_string(),
+        hostnames: vec![],
+        ips: vec![],
+        tags: vec![],
+    };
+
+    let (r1, r2) = tokio::join!(
+        manager1.enroll(make_req("node-1")),
+        manager2.enroll(make_req("node-2"))
+    );
+
+    assert_eq!(r1.is_ok() as u8 + r2.is_ok() as u8, 1);
+    assert_eq!(manager1.get_enrolled_nodes().len(), 1);
+}
+
+#[tokio::test]
+async fn test_token_secret_not_replicated() {
+    let replicator = LocalReplicator::new();
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let manager = EnrollmentManager::new("test-cluster".to_string(), ca, replicator.clone());
+
+    let token = manager.generate_token(24, vec![]).await.unwrap();
+
+    let state = replicator.shared_state().snapshot();
+    assert_eq!(state.tokens.len(), 1);
+    assert!(state.tokens.contains_key(&hash_token(&token.token)));
+    assert!(!state.tokens.contains_key(&token.token));
+}
+
+#[tokio::test]
+async fn test_enroll_records_certificate_serial() {
+    let manager = create_test_manager();
+    let token = manager.generate_token(24, vec![]).await.unwrap();
+
+    let req = EnrollmentRequest {
+        token: token.token,
+        node_id: "node-1".to_string(),
+        hostname: "host1".to_string(),
+        addr: "127.0.0.1:9001".to_string(),
+        hostnames: vec![],
+        ips: vec![],
+        tags: vec![],
+    };
+    let resp = manager.enroll(req).await.unwrap();
+
+    let cert = flockmind::NodeCertificate {
+        cert_pem: resp.node_cert_pem,
+        key_pem: resp.node_key_pem,
+        node_id: resp.node_id,
+    };
+    let recorded = manager.enrolled_node("node-1").unwrap();
+    assert_eq!(recorded.cert_serial, Some(cert.serial().unwrap()));
+}
+
+#[tokio::test]
+async fn test_revoke_node_publishes_crl() {
+    let replicator = LocalReplicator::new();
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let manager = EnrollmentManager::new("test-cluster".to_string(), ca, replicator.clone());
+
+    for node_id in ["node-1", "node-2"] {
+        let token = manager.generate_token(24, vec![]).await.unwrap();
+        manager
+            .enroll(EnrollmentRequest {
+                token: token.token,
+                node_id: node_id.to_string(),
+                hostname: node_id.to_string(),
+                addr: "127.0.0.1:9001".to_string(),
+                hostnames: vec![],
+                ips: vec![],
+                tags: vec![],
+            })
+            .await
+            .unwrap();
+    }
+    let serial1 = manager.enrolled_node("node-1").unwrap().cert_serial.unwrap();
+    let serial2 = manager.enrolled_node("node-2").unwrap().cert_serial.unwrap();
+
+    manager.revoke_node("node-1", None).await.unwrap();
+    manager
+        .revoke_node("node-2", Some("lost laptop".to_string()))
+        .await
+        .unwrap();
+
+    let state = replicator.shared_state().snapshot();
+    assert!(state.enrollments.is_empty());
+    assert_eq!(state.revoked_certificates.len(), 2);
+
+    let crl = state.crl.unwrap();
+    assert_eq!(crl.number, 2);
+    let mut listed = revoked_serials_from_crl(&crl.pem).unwrap();
+    listed.sort();
+    let mut expected = vec![serial1, serial2];
+    expected.sort();
+    assert_eq!(listed, expected);
+}
+
+#[tokio::test]
+async fn test_concurrent_revocations_are_both_in_crl() {
+    let replicator = LocalReplicator::new();
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let manager1 = EnrollmentManager::new("test-cluster".to_string(), ca.clone(), replicator.clone());
+    let manager2 = EnrollmentManager::new("test-cluster".to_string(), ca, replicator.clone());
+    enroll_node(&manager1, "node-1").await;
+    enroll_node(&manager1, "node-2").await;
+    let serial1 = manager1.enrolled_node("node-1").unwrap().cert_serial.unwrap();
+    let serial2 = manager1.enrolled_node("node-2").unwrap().cert_serial.unwrap();
+
+    let (r1, r2) = tokio::join!(
+        manager1.revoke_node("node-1", None),
+        manager2.revoke_node("node-2", None)
+    );
+    r1.unwrap();
+    r2.unwrap();
+
+    let crl = replicator.shared_state().snapshot().crl.unwrap();
+    assert_eq!(crl.number, 2);
+    let mut listed = revoked_serials_from_crl(&crl.pem).unwrap();
+    listed.sort();
+    let mut expected = vec![serial1, serial2];
+    expected.sort();
+    assert_eq!(listed, expected);
+}
+
+#[tokio::test]
+async fn test_revoke_unknown_node_fails() {
+    let manager = create_test_manager();
+    assert!(manager.revoke_node("node-9", None).await.is_err());
+}
+
+async fn enroll_node(manager: &EnrollmentManager<LocalReplicator>, node_id: &str) -> EnrollmentResponse {
+    let token = manager.generate_token(24, vec![]).await.unwrap();
+    manager
+        .enroll(EnrollmentRequest {
+            token: token.token,
+            node_id: node_id.to_string(),
+            hostname: node_id.to_string(),
+            addr: "127.0.0.1:9001".to_string(),
+            hostnames: vec![],
+            ips: vec![],
+            tags: vec![],
+        })
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_renew_replaces_recorded_serial() {
+    let manager = create_test_manager();
+    enroll_node(&manager, "node-1").await;
+    let old_serial = manager.enrolled_node("node-1").unwrap().cert_serial.unwrap();
+
+    let renewed = manager
+        .renew("node-1", &old_serial, vec!["host1".to_string()], vec![])
+        .await
+        .unwrap();
+
+    let recorded = manager.enrolled_node("node-1").unwrap();
+    assert_eq!(recorded.cert_serial, Some(renewed.serial().unwrap()));
+    assert_eq!(recorded.superseded_serials, vec![old_serial]);
+    assert_eq!(renewed.node_id, "node-1");
+}
+
+#[tokio::test]
+async fn test_renew_rejects_foreign_serial() {
+    let manager = create_test_manager();
+    enroll_node(&manager, "node-1").await;
+    enroll_node(&manager, "node-2").await;
+    let other_serial = manager.enrolled_node("node-2").unwrap().cert_serial.unwrap();
+
+    assert!(manager.renew("node-1", &other_serial, vec![], vec![]).await.is_err());
+    assert!(manager.renew("node-9", &other_serial, vec![], vec![]).await.is_err());
+}
+
+#[tokio::test]
+async fn test_revoke_covers_superseded_certificates() {
+    let replicator = LocalReplicator::new();
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let manager = EnrollmentManager::new("test-cluster".to_string(), ca, replicator.clone());
+
+    enroll_node(&manager, "node-1").await;
+    let old_serial = manager.enrolled_node("node-1").unwrap().cert_serial.unwrap();
+    let renewed = manager.renew("node-1", &old_serial, vec![], vec![]).await.unwrap();
+
+    manager.revoke_node("node-1", None).await.unwrap();
+
+    let state = replicator.shared_state().snapshot();
+    assert!(state.revoked_certificates.contains_key(&old_serial));
+    assert!(state.revoked_certificates.contains_key(&renewed.serial().unwrap()));
+    assert!(manager.renew("node-1", &old_serial, vec![], vec![]).await.is_err());
+}
+
+#[tokio::test]
+async fn test_expired_superseded_serials_are_dropped() {
+    let replicator = LocalReplicator::new();
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let manager = EnrollmentManager::new("test-cluster".to_string(), ca, replicator.clone());
+
+    enroll_node(&manager, "node-1").await;
+    let mut node = manager.enrolled_node("node-1").unwrap();
+    let expired = node.cert_serial.clone().unwrap();
+    let live = "0a1b".to_string();
+    let now = chrono::Utc::now();
+    node.superseded_serials.push(live.clone());
+    node.cert_expiry.insert(live.clone(), now + chrono::Duration::days(1));
+    node.cert_expiry.insert(expired.clone(), now - chrono::Duration::days(1));
+    manager.record_enrollment(node).await.unwrap();
+
+    let renewed = manager.renew("node-1", &expired, vec![], vec![]).await.unwrap();
+    let recorded = manager.enrolled_node("node-1").unwrap();
+    assert_eq!(recorded.superseded_serials, vec![live.clone()]);
+    assert!(!recorded.cert_expiry.contains_key(&expired));
+    assert!(recorded.cert_expiry.contains_key(&renewed.serial().unwrap()));
+
+    let mut node = recorded;
+    node.cert_expiry.insert(live.clone(), now - chrono::Duration::days(1));
+    manager.record_enrollment(node).await.unwrap();
+
+    let revoked = manager.revoke_node("node-1", None).await.unwrap();
+    let serials: Vec<String> = revoked.into_iter().map(|r| r.serial).collect();
+    assert_eq!(serials, vec![renewed.serial().unwrap()]);
+    let crl = replicator.shared_state().snapshot().crl.unwrap();
+    assert!(!revoked_serials_from_crl(&crl.pem).unwrap().contains(&live));
+}
+
+#[tokio::test]
+async fn test_enroll_with_intermediate_returns_trust_bundle() {
+    let root = CaCertificate::generate_root("test-cluster").unwrap();
+    let intermediate = root.issue_intermediate("test-cluster").unwrap();
+    let manager = EnrollmentManager::new("test-cluster".to_string(), intermediate, LocalReplicator::new())
+        .with_trust_bundle(root.cert_pem.clone());
+
+    let resp = enroll_node(&manager, "node-1").await;
+    assert_eq!(resp.ca_cert_pem, root.cert_pem);
+    assert!(manager.nodes_pending_reissue().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_nodes_pending_reissue_after_ca_switch() {
+    let replicator = LocalReplicator::new();
+    let old_ca = CaCertificate::generate("test-cluster").unwrap();
+    let old_manager = EnrollmentManager::new("test-cluster".to_string(), old_ca.clone(), replicator.clone());
+    enroll_node(&old_manager, "node-1").await;
+    enroll_node(&old_manager, "node-2").await;
+
+    let new_root = CaCertificate::generate_root("test-cluster").unwrap();
+    let new_manager = EnrollmentManager::new(
+        "test-cluster".to_string(),
+        new_root.issue_intermediate("test-cluster").unwrap(),
+        replicator,
+    )
+    .with_trust_bundle(format!("{}{}", old_ca.cert_pem, new_root.cert_pem));
+    assert_eq!(new_manager.nodes_pending_reissue().unwrap().len(), 2);
+
+    let serial = new_manager.enrolled_node("node-1").unwrap().cert_serial.unwrap();
+    new_manager.renew("node-1", &serial, vec![], vec![]).await.unwrap();
+
+    let pending = new_manager.nodes_pending_reissue().unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].node_id, "node-2");
+}
+
+#[tokio::test]
+async fn test_enroll_allocates_distinct_raft_ids() {
+    let manager = create_test_manager();
+
+    let web1 = enroll_node(&manager, "production-web-1").await;
+    let web2 = enroll_node(&manager, "production-web-2").await;
+
+    assert_ne!(web1.raft_id, web2.raft_id);
+    assert_eq!(manager.assign_raft_id("production-web-1").await.unwrap(), web1.raft_id);
+}
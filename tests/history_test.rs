@@ -0,0 +1,218 @@
+mod common;
+
+use common::{entry, goal, log_id, memory_backend, register_node};
+use chrono::Utc;
+use flockmind::replicator::backend::StorageBackend;
+use flockmind::replicator::state_machine::*;
+use flockmind::replicator::{HistoryError, HistoryPoint, HistoryStore, HiveNode, HiveStorage};
+use flockmind::*;
+use openraft::storage::RaftStorage;
+use openraft::{Entry, EntryPayload, Membership, RaftSnapshotBuilder, SnapshotMeta, StoredMembership};
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::sync::Arc;
+
+fn put_goal(id: &str) -> ClusterCommand {
+    ClusterCommand::PutGoal(Goal {
+        id: id.to_string(),
+        ..goal(&format!("goal {}", id))
+    })
+}
+
+fn heartbeat(node_id: &str) -> ClusterCommand {
+    ClusterCommand::UpdateNodeHealth {
+        node_id: node_id.to_string(),
+        health: NodeHealth::Healthy,
+        metrics: NodeMetrics {
+            cpu_usage: 0.5,
+            memory_usage: 0.5,
+            disk_usage: 0.5,
+        },
+        expected_revision: None,
+    }
+}
+
+/// Storage recording history on its own backend, both kept by the caller
+/// so tests can restart over them.
+fn storage_with_history(
+    backend: &Arc<dyn StorageBackend>,
+    history_backend: &Arc<dyn StorageBackend>,
+    keep_entries: u64,
+) -> (HiveStorage, Arc<HistoryStore>) {
+    let history = Arc::new(HistoryStore::new(history_backend.clone(), keep_entries).unwrap());
+    let storage = HiveStorage::new(backend.clone(), SharedState::new())
+        .unwrap()
+        .with_history(history.clone())
+        .unwrap();
+    (storage, history)
+}
+
+#[tokio::test]
+async fn test_history_lists_applied_commands() {
+    let (mut storage, history) = storage_with_history(&memory_backend(), &memory_backend(), 100);
+
+    storage
+        .apply_to_state_machine(&[
+            Entry {
+                log_id: log_id(0),
+                payload: EntryPayload::Blank,
+            },
+            entry(1, register_node("web-1")),
+            entry(2, put_goal("goal-a")),
+        ])
+        .await
+        .unwrap();
+    let between = Utc::now();
+    storage
+        .apply_to_state_machine(&[entry(3, heartbeat("web-1")), entry(4, put_goal("goal-b"))])
+        .await
+        .unwrap();
+
+    let all = history.entries(None, None, None).unwrap();
+    assert_eq!(all.iter().map(|e| e.index).collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+    assert!(all.iter().all(|e| e.term == 1));
+
+    let node = history.entries(None, None, Some("web-1")).unwrap();
+    assert_eq!(node.iter().map(|e| e.index).collect::<Vec<_>>(), vec![1, 3]);
+
+    let range = history
+        .entries(Some(HistoryPoint::Index(2)), Some(HistoryPoint::Index(3)), None)
+        .unwrap();
+    assert_eq!(range.iter().map(|e| e.index).collect::<Vec<_>>(), vec![2, 3]);
+
+    let after = history
+        .entries(Some(HistoryPoint::Time(between)), None, None)
+        .unwrap();
+    assert_eq!(after.iter().map(|e| e.index).collect::<Vec<_>>(), vec![3, 4]);
+
+    assert_eq!(
+        "1200".parse::<HistoryPoint>().unwrap(),
+        HistoryPoint::Index(1200)
+    );
+    assert!(matches!(
+        "2026-10-01T00:00:00Z".parse::<HistoryPoint>(),
+        Ok(HistoryPoint::Time(_))
+    ));
+    assert!(matches!(
+        "yesterday".parse::<HistoryPoint>(),
+        Err(HistoryError::InvalidPoint(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_state_at_replays_from_nearest_checkpoint() {
+    let backend = memory_backend();
+    let history_backend = memory_backend();
+
+    let heartbeat_at = {
+        let (mut storage, history) = storage_with_history(&backend, &history_backend, 100);
+        let mut entries = vec![Entry {
+            log_id: log_id(0),
+            payload: EntryPayload::Blank,
+        }];
+        entries.extend((1..=3).map(|i| entry(i, put_goal(&format!("goal-{}", i)))));
+        storage.apply_to_state_machine(&entries).await.unwrap();
+        storage.build_snapshot().await.unwrap();
+
+        storage
+            .apply_to_state_machine(&[entry(4, register_node("web-1"))])
+            .await
+            .unwrap();
+        storage
+            .apply_to_state_machine(&[entry(5, heartbeat("web-1"))])
+            .await
+            .unwrap();
+        history.entries(Some(HistoryPoint::Index(5)), None, None).unwrap()[0].applied_at
+    };
+
+    // openraft re-applies 4 and 5 after a restart; history keeps their
+    // original times.
+    let (mut storage, history) = storage_with_history(&backend, &history_backend, 100);
+    storage
+        .apply_to_state_machine(&[entry(4, register_node("web-1")), entry(5, heartbeat("web-1"))])
+        .await
+        .unwrap();
+    assert_eq!(history.entries(None, None, None).unwrap().len(), 5);
+
+    // Before the first snapshot, state is replayed from the start of the log.
+    let (state, at) = history.state_at(HistoryPoint::Index(2)).unwrap();
+    assert_eq!(at, log_id(2));
+    assert_eq!(state.goals.len(), 2);
+    assert!(state.nodes.is_empty());
+
+    let (state, at) = history.state_at(HistoryPoint::Index(5)).unwrap();
+    assert_eq!(at, log_id(5));
+    assert_eq!(state.goals.len(), 3);
+    assert_eq!(state.nodes["web-1"].last_heartbeat, heartbeat_at);
+
+    let (state, at) = history.state_at(HistoryPoint::Time(heartbeat_at)).unwrap();
+    assert_eq!(at, log_id(5));
+    assert_eq!(state.revision, 5);
+
+    assert!(matches!(
+        history.state_at(HistoryPoint::Index(6)),
+        Err(HistoryError::NotApplied(6))
+    ));
+}
+
+#[tokio::test]
+async fn test_history_is_trimmed_to_retention() {
+    let (mut storage, history) = storage_with_history(&memory_backend(), &memory_backend(), 3);
+
+    for batch in [1..=4, 5..=8, 9..=12] {
+        let entries: Vec<_> = batch
+            .map(|i| entry(i, put_goal(&format!("goal-{}", i))))
+            .collect();
+        storage.apply_to_state_machine(&entries).await.unwrap();
+        storage.build_snapshot().await.unwrap();
+    }
+
+    // The checkpoint at 8 is the newest at or before 12 - 3, so it and the
+    // entries after it are all that is needed.
+    let kept = history.entries(None, None, None).unwrap();
+    assert_eq!(kept.first().unwrap().index, 9);
+    let (state, _) = history.state_at(HistoryPoint::Index(8)).unwrap();
+    assert_eq!(state.goals.len(), 8);
+    assert!(matches!(
+        history.state_at(HistoryPoint::Index(7)),
+        Err(HistoryError::NotRetained(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_entries_skipped_by_installed_snapshot_are_reported_missing() {
+    let (mut storage, history) = storage_with_history(&memory_backend(), &memory_backend(), 100);
+    storage
+        .apply_to_state_machine(&[entry(1, put_goal("goal-1"))])
+        .await
+        .unwrap();
+
+    let mut leader_state = HiveState::new();
+    for i in 1..=10 {
+        leader_state.apply(&put_goal(&format!("goal-{}", i)));
+    }
+    let meta = SnapshotMeta {
+        last_log_id: Some(log_id(10)),
+        last_membership: StoredMembership::new(
+            Some(log_id(0)),
+            Membership::new(vec![[1].into()], BTreeMap::from([(1, HiveNode::default())])),
+        ),
+        snapshot_id: "leader-10".to_string(),
+    };
+    let data = flockmind::replicator::codec::encode(&leader_state).unwrap();
+    storage
+        .install_snapshot(&meta, Box::new(Cursor::new(data)))
+        .await
+        .unwrap();
+    storage
+        .apply_to_state_machine(&[entry(11, put_goal("goal-11"))])
+        .await
+        .unwrap();
+
+    let (state, _) = history.state_at(HistoryPoint::Index(11)).unwrap();
+    assert_eq!(state.goals.len(), 11);
+    assert!(matches!(
+        history.state_at(HistoryPoint::Index(5)),
+        Err(HistoryError::NotRetained(_))
+    ));
+}
@@ -0,0 +1,152 @@
+use chrono::{Duration, Utc};
+use flockmind::replicator::state_machine::*;
+use flockmind::replicator::WatchFilter;
+use flockmind::*;
+
+fn put(key: &str, value: &str, ttl_secs: Option<u64>) -> ClusterCommand {
+    ClusterCommand::KvPut {
+        key: key.to_string(),
+        value: value.to_string(),
+        ttl_secs,
+        written_at: Utc::now(),
+    }
+}
+
+fn cas(key: &str, value: &str, expected_revision: u64) -> ClusterCommand {
+    ClusterCommand::KvCas {
+        key: key.to_string(),
+        value: value.to_string(),
+        ttl_secs: None,
+        expected_revision,
+        written_at: Utc::now(),
+    }
+}
+
+fn delete(key: &str, expected_revision: Option<u64>) -> ClusterCommand {
+    ClusterCommand::KvDelete {
+        key: key.to_string(),
+        expected_revision,
+        written_at: Utc::now(),
+    }
+}
+
+#[test]
+fn test_put_get_and_list() {
+    let mut state = HiveState::new();
+    assert!(state.apply(&put("deploy/api/version", "1.4.2", None)).is_applied());
+    assert!(state.apply(&put("deploy/web/version", "2.0.0", None)).is_applied());
+    assert!(state.apply(&put("flags/maintenance", "false", None)).is_applied());
+
+    let now = Utc::now();
+    let entry = state.kv_get("deploy/api/version", now).unwrap();
+    assert_eq!(entry.value, "1.4.2");
+    assert_eq!(entry.revision, 1);
+    assert!(state.kv_get("deploy/api", now).is_none());
+
+    let keys: Vec<_> = state
+        .kv_list("deploy/", now)
+        .into_iter()
+        .map(|e| e.key)
+        .collect();
+    assert_eq!(keys, vec!["deploy/api/version", "deploy/web/version"]);
+    assert_eq!(state.kv_list("", now).len(), 3);
+
+    let view = state.to_cluster_view(None, 1);
+    assert_eq!(view.kv_get("flags/maintenance").unwrap().value, "false");
+}
+
+#[test]
+fn test_cas_and_delete_check_revision() {
+    let mut state = HiveState::new();
+    let created = state.apply(&cas("lock/owner", "web-1", 0));
+    let revision = created.revision().unwrap();
+    assert_eq!(state.apply(&cas("lock/owner", "web-2", 0)), CommandResult::Conflict);
+
+    assert!(state.apply(&cas("lock/owner", "web-2", revision)).is_applied());
+    assert_eq!(
+        state.apply(&cas("lock/owner", "web-3", revision)),
+        CommandResult::Conflict
+    );
+    assert_eq!(state.apply(&cas("missing", "x", 7)), CommandResult::NotFound);
+
+    assert_eq!(
+        state.apply(&delete("lock/owner", Some(revision))),
+        CommandResult::Conflict
+    );
+    assert!(state.apply(&delete("lock/owner", None)).is_applied());
+    assert_eq!(state.apply(&delete("lock/owner", None)), CommandResult::NotFound);
+}
+
+#[test]
+fn test_expired_keys_are_hidden_and_purged() {
+    let mut state = HiveState::new();
+    let written_at = Utc::now() - Duration::seconds(120);
+    let expiring = ClusterCommand::KvPut {
+        key: "flags/maintenance".to_string(),
+        value: "true".to_string(),
+        ttl_secs: Some(60),
+        written_at,
+    };
+    assert!(state.apply(&expiring).is_applied());
+
+    let entry = state.kv.get("flags/maintenance").unwrap();
+    assert_eq!(entry.expires_at, Some(written_at + Duration::seconds(60)));
+    assert!(state.kv_get("flags/maintenance", written_at).is_some());
+    assert!(state.kv_get("flags/maintenance", Utc::now()).is_none());
+    assert!(state.kv_list("flags/", Utc::now()).is_empty());
+
+    // An expired key counts as missing and is dropped by the next write.
+    assert!(state.apply(&cas("flags/maintenance", "false", 0)).is_applied());
+    assert_eq!(state.kv_get("flags/maintenance", Utc::now()).unwrap().value, "false");
+    assert!(state.apply(&put("flags/other", "1", Some(60))).is_applied());
+    assert_eq!(state.kv.len(), 2);
+}
+
+#[test]
+fn test_watch_filter_by_key() {
+    let exact = WatchFilter {
+        key: Some("deploy/api".to_string()),
+        ..Default::default()
+    };
+    assert!(exact.matches(&put("deploy/api", "1", None)));
+    assert!(!exact.matches(&put("deploy/api/version", "1", None)));
+    assert!(!exact.matches(&ClusterCommand::RemoveGoal {
+        goal_id: "deploy/api".to_string(),
+    }));
+
+    let prefix = WatchFilter {
+        types: vec!["kv".parse().unwrap()],
+        key: Some("deploy/".to_string()),
+        key_prefix: true,
+        ..Default::default()
+    };
+    assert!(prefix.matches(&delete("deploy/api/version", None)));
+    assert!(!prefix.matches(&put("flags/maintenance", "true", None)));
+}
+
+#[test]
+fn test_out_of_range_ttl_is_rejected() {
+    let mut state = HiveState::new();
+    for ttl_secs in [u64::MAX, i64::MAX as u64, MAX_KV_TTL_SECS * 1_000_000] {
+        assert_eq!(
+            state.apply(&put("flags/maintenance", "true", Some(ttl_secs))),
+            CommandResult::Conflict
+        );
+        assert_eq!(
+            state.apply(&ClusterCommand::KvCas {
+                key: "flags/maintenance".to_string(),
+                value: "true".to_string(),
+                ttl_secs: Some(ttl_secs),
+                expected_revision: 0,
+                written_at: Utc::now(),
+            }),
+            CommandResult::Conflict
+        );
+    }
+    assert!(state.kv.is_empty());
+
+    assert!(state
+        .apply(&put("flags/maintenance", "true", Some(MAX_KV_TTL_SECS)))
+        .is_applied());
+    assert!(state.kv_get("flags/maintenance", Utc::now()).is_some());
+}
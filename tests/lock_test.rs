@@ -0,0 +1,150 @@
+mod common;
+
+use chrono::{Duration, Utc};
+use common::LocalReplicator;
+use flockmind::replicator::state_machine::*;
+use flockmind::*;
+
+fn acquire(key: &str, holder: &str, ttl_secs: u64) -> ClusterCommand {
+    ClusterCommand::AcquireLock {
+        key: key.to_string(),
+        holder: holder.to_string(),
+        ttl_secs,
+        requested_at: Utc::now(),
+    }
+}
+
+fn renew(key: &str, holder: &str) -> ClusterCommand {
+    ClusterCommand::RenewLock {
+        key: key.to_string(),
+        holder: holder.to_string(),
+        ttl_secs: 30,
+        requested_at: Utc::now(),
+    }
+}
+
+fn release(key: &str, holder: &str) -> ClusterCommand {
+    ClusterCommand::ReleaseLock {
+        key: key.to_string(),
+        holder: holder.to_string(),
+    }
+}
+
+fn lease(state: &SharedState, key: &str) -> Option<Lease> {
+    state.read(|s| s.lease(key, Utc::now()).cloned())
+}
+
+#[test]
+fn test_lock_is_exclusive_and_fenced_by_log_index() {
+    let state = SharedState::new();
+    let CommandResult::Leased(first) = state.apply(&acquire("cron/backup", "worker-a", 30)) else {
+        panic!("lock was not acquired");
+    };
+    assert_eq!(lease(&state, "cron/backup").unwrap(), first);
+    assert_eq!(first.holder, "worker-a");
+    assert_eq!(first.fencing_token, 1);
+
+    assert_eq!(state.apply(&acquire("cron/backup", "worker-b", 30)), CommandResult::Conflict);
+    assert_eq!(state.apply(&renew("cron/backup", "worker-b")), CommandResult::Conflict);
+    assert_eq!(state.apply(&release("cron/backup", "worker-b")), CommandResult::Conflict);
+
+    // Acquiring again as the holder only extends the lease.
+    assert!(state.apply(&acquire("cron/backup", "worker-a", 60)).is_applied());
+    let extended = lease(&state, "cron/backup").unwrap();
+    assert_eq!(extended.fencing_token, first.fencing_token);
+    assert!(extended.expires_at > first.expires_at);
+    assert!(state.apply(&renew("cron/backup", "worker-a")).is_applied());
+
+    assert!(state.apply(&release("cron/backup", "worker-a")).is_applied());
+    assert!(lease(&state, "cron/backup").is_none());
+    assert_eq!(state.apply(&release("cron/backup", "worker-a")), CommandResult::NotFound);
+
+    assert!(state.apply(&acquire("cron/backup", "worker-b", 30)).is_applied());
+    let second = lease(&state, "cron/backup").unwrap();
+    assert_eq!(second.fencing_token, state.last_applied());
+    assert!(second.fencing_token > first.fencing_token);
+}
+
+#[test]
+fn test_expired_lease_can_be_taken_over() {
+    let state = SharedState::new();
+    state.apply(&ClusterCommand::AcquireLock {
+        key: "cron/backup".to_string(),
+        holder: "worker-a".to_string(),
+        ttl_secs: 10,
+        requested_at: Utc::now() - Duration::seconds(60),
+    });
+    assert!(lease(&state, "cron/backup").is_none());
+    assert!(state.read(|s| s.leases(Utc::now()).is_empty()));
+
+    assert_eq!(state.apply(&renew("cron/backup", "worker-a")), CommandResult::NotFound);
+    assert!(state.apply(&acquire("cron/backup", "worker-b", 30)).is_applied());
+    assert_eq!(lease(&state, "cron/backup").unwrap().holder, "worker-b");
+}
+
+#[test]
+fn test_out_of_range_ttl_is_rejected() {
+    let state = SharedState::new();
+    for ttl_secs in [u64::MAX, i64::MAX as u64, MAX_LOCK_TTL_SECS * 100_000_000] {
+        assert_eq!(
+            state.apply(&acquire("cron/backup", "worker-a", ttl_secs)),
+            CommandResult::Conflict
+        );
+    }
+    assert!(lease(&state, "cron/backup").is_none());
+
+    assert!(state.apply(&acquire("cron/backup", "worker-a", 30)).is_applied());
+    assert_eq!(
+        state.apply(&ClusterCommand::RenewLock {
+            key: "cron/backup".to_string(),
+            holder: "worker-a".to_string(),
+            ttl_secs: u64::MAX,
+            requested_at: Utc::now(),
+        }),
+        CommandResult::Conflict
+    );
+    assert!(state.apply(&renew("cron/backup", "worker-a")).is_applied());
+    assert!(state.apply(&release("cron/backup", "worker-a")).is_applied());
+}
+
+fn locked_task(id: &str, lock: &str) -> Task {
+    Task {
+        id: id.to_string(),
+        target_node: "web-1".to_string(),
+        payload: TaskPayload::Echo {
+            message: "hi".to_string(),
+        },
+        status: TaskStatus::Pending,
+        priority: 5,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        result: None,
+        revision: 0,
+        lock: Some(lock.to_string()),
+    }
+}
+
+#[tokio::test]
+async fn test_executor_waits_for_task_lock() {
+    let replicator = LocalReplicator::new();
+    let state = replicator.shared_state().clone();
+    let executor = HiveExecutor::new(
+        "web-1".to_string(),
+        replicator.clone(),
+        ExecutionPolicy::default(),
+    );
+
+    state.apply(&ClusterCommand::PutTask(locked_task("task-1", "deploy/api")));
+    state.apply(&acquire("deploy/api", "web-2/task-0", 30));
+
+    let task = state.read(|s| s.tasks["task-1"].clone());
+    assert!(executor.run_task(&task).await.is_err());
+    let task = state.read(|s| s.tasks["task-1"].clone());
+    assert_eq!(task.status, TaskStatus::Pending);
+
+    state.apply(&release("deploy/api", "web-2/task-0"));
+    executor.run_task(&task).await.unwrap();
+    let task = state.read(|s| s.tasks["task-1"].clone());
+    assert_eq!(task.status, TaskStatus::Completed);
+    assert!(lease(&state, "deploy/api").is_none());
+}
@@ -24,6 +24,7 @@ fn test_apply_register_node() {
         cpu_usage: 0.5,
         memory_usage: 0.3,
         disk_usage: 0.2,
+        revision: 0,
     });
 
     state.apply(&cmd);
@@ -46,6 +47,7 @@ fn test_apply_update_node_health() {
         cpu_usage: 0.0,
         memory_usage: 0.0,
         disk_usage: 0.0,
+        revision: 0,
     }));
 
     state.apply(&ClusterCommand::UpdateNodeHealth {
@@ -58,6 +60,7 @@ fn test_apply_update_node_health() {
             memory_usage: 0.5,
             disk_usage: 0.3,
         },
+        expected_revision: None,
     });
 
     let node = state.nodes.get("node-1").unwrap();
@@ -65,6 +68,33 @@ fn test_apply_update_node_health() {
     assert_eq!(node.cpu_usage, 0.95);
 }
 
+#[test]
+fn test_apply_updates_to_missing_targets_not_found() {
+    let mut state = HiveState::new();
+
+    let health = state.apply(&ClusterCommand::UpdateNodeHealth {
+        node_id: "ghost".to_string(),
+        health: NodeHealth::Healthy,
+        metrics: NodeMetrics::default(),
+        expected_revision: None,
+    });
+    let task = state.apply(&ClusterCommand::UpdateTaskStatus {
+        task_id: "ghost".to_string(),
+        status: TaskStatus::Running,
+        result: None,
+        expected_revision: None,
+    });
+    let goal = state.apply(&ClusterCommand::RemoveGoal {
+        goal_id: "ghost".to_string(),
+    });
+
+    assert_eq!(health, CommandResult::NotFound);
+    assert_eq!(task, CommandResult::NotFound);
+    assert_eq!(goal, CommandResult::NotFound);
+    assert!(state.nodes.is_empty());
+    assert!(state.tasks.is_empty());
+}
+
 #[test]
 fn test_apply_remove_node() {
     let mut state = HiveState::new();
@@ -78,6 +108,7 @@ fn test_apply_remove_node() {
         cpu_usage: 0.0,
         memory_usage: 0.0,
         disk_usage: 0.0,
+        revision: 0,
     }));
 
     assert_eq!(state.nodes.len(), 1);
@@ -103,6 +134,8 @@ fn test_apply_put_task() {
         created_at: Utc::now(),
         updated_at: Utc::now(),
         result: None,
+        revision: 0,
+        lock: None,
     };
 
     state.apply(&ClusterCommand::PutTask(task));
@@ -126,12 +159,15 @@ fn test_apply_update_task_status() {
         created_at: Utc::now(),
         updated_at: Utc::now(),
         result: None,
+        revision: 0,
+        lock: None,
     }));
 
     state.apply(&ClusterCommand::UpdateTaskStatus {
         task_id: "task-1".to_string(),
         status: TaskStatus::Completed,
         result: Some(serde_json::json!({"output": "done"})),
+        expected_revision: None,
     });
 
     let task = state.tasks.get("task-1").unwrap();
@@ -151,6 +187,7 @@ fn test_apply_put_attachment() {
         capabilities: vec!["read".to_string(), "write".to_string()],
         metadata: std::collections::HashMap::new(),
         created_at: Utc::now(),
+        revision: 0,
     };
 
     state.apply(&ClusterCommand::PutAttachment(attachment));
@@ -172,6 +209,7 @@ fn test_apply_remove_attachment() {
         capabilities: vec![],
         metadata: std::collections::HashMap::new(),
         created_at: Utc::now(),
+        revision: 0,
     }));
 
     assert_eq!(state.attachments.len(), 1);
@@ -193,6 +231,7 @@ fn test_apply_put_goal() {
         priority: 5,
         active: true,
         created_at: Utc::now(),
+        revision: 0,
     };
 
     state.apply(&ClusterCommand::PutGoal(goal));
@@ -212,6 +251,7 @@ fn test_apply_remove_goal() {
         priority: 5,
         active: true,
         created_at: Utc::now(),
+        revision: 0,
     }));
 
     state.apply(&ClusterCommand::RemoveGoal {
@@ -234,6 +274,7 @@ fn test_shared_state_apply() {
         cpu_usage: 0.0,
         memory_usage: 0.0,
         disk_usage: 0.0,
+        revision: 0,
     }));
 
     let snapshot = shared.snapshot();
@@ -253,6 +294,7 @@ fn test_shared_state_to_cluster_view() {
         cpu_usage: 0.0,
         memory_usage: 0.0,
         disk_usage: 0.0,
+        revision: 0,
     }));
 
     let view = shared.to_cluster_view(Some("node-1".to_string()), 5);
@@ -277,6 +319,7 @@ fn test_shared_state_restore() {
             cpu_usage: 0.0,
             memory_usage: 0.0,
             disk_usage: 0.0,
+            revision: 0,
         },
     );
     state.last_applied_index = 100;
@@ -309,6 +352,7 @@ fn test_shared_state_clone() {
         cpu_usage: 0.0,
         memory_usage: 0.0,
         disk_usage: 0.0,
+        revision: 0,
     }));
 
     let cloned = shared.clone();
@@ -325,7 +369,281 @@ fn test_shared_state_clone() {
         cpu_usage: 0.0,
         memory_usage: 0.0,
         disk_usage: 0.0,
+        revision: 0,
     }));
 
     assert_eq!(shared.snapshot().nodes.len(), 2);
 }
+
+fn issued_token(hash: &str, expires_in_hours: i64) -> IssuedToken {
+    IssuedToken {
+        token_hash: hash.to_string(),
+        cluster_id: "test-cluster".to_string(),
+        issued_at: Utc::now(),
+        expires_at: Utc::now() + chrono::Duration::hours(expires_in_hours),
+        allowed_tags: vec![],
+        consumed: None,
+    }
+}
+
+fn consume(hash: &str, node_id: &str, request_id: &str) -> ClusterCommand {
+    ClusterCommand::ConsumeToken {
+        token_hash: hash.to_string(),
+        node_id: node_id.to_string(),
+        request_id: request_id.to_string(),
+        consumed_at: Utc::now(),
+    }
+}
+
+#[test]
+fn test_apply_consume_token_first_wins() {
+    let mut state = HiveState::new();
+    state.apply(&ClusterCommand::IssueToken(issued_token("abc", 1)));
+
+    assert_eq!(state.apply(&consume("abc", "node-1", "req-1")), CommandResult::Applied { revision: 2 });
+    assert_eq!(state.apply(&consume("abc", "node-2", "req-2")), CommandResult::Conflict);
+
+    let consumed = state.tokens.get("abc").unwrap().consumed.as_ref().unwrap();
+    assert_eq!(consumed.node_id, "node-1");
+    assert_eq!(consumed.request_id, "req-1");
+}
+
+#[test]
+fn test_apply_consume_expired_token_ignored() {
+    let mut state = HiveState::new();
+    state.apply(&ClusterCommand::IssueToken(issued_token("abc", -1)));
+
+    assert_eq!(state.apply(&consume("abc", "node-1", "req-1")), CommandResult::Conflict);
+
+    assert!(state.tokens.get("abc").unwrap().consumed.is_none());
+}
+
+#[test]
+fn test_apply_consume_unknown_token_not_found() {
+    let mut state = HiveState::new();
+
+    assert_eq!(state.apply(&consume("abc", "node-1", "req-1")), CommandResult::NotFound);
+}
+
+#[test]
+fn test_apply_issue_token_prunes_expired() {
+    let mut state = HiveState::new();
+    state.apply(&ClusterCommand::IssueToken(issued_token("old", -1)));
+    state.apply(&ClusterCommand::IssueToken(issued_token("new", 1)));
+
+    assert!(!state.tokens.contains_key("old"));
+    assert!(state.tokens.contains_key("new"));
+}
+
+#[test]
+fn test_apply_record_enrollment() {
+    let mut state = HiveState::new();
+    state.apply(&ClusterCommand::RecordEnrollment(EnrolledNode {
+        node_id: "node-1".to_string(),
+        hostname: "host1".to_string(),
+        addr: "127.0.0.1:9001".to_string(),
+        tags: vec![],
+        enrolled_at: Utc::now(),
+        cert_serial: None,
+        superseded_serials: vec![],
+        issuer_key_id: None,
+        cert_expiry: Default::default(),
+    }));
+
+    assert_eq!(state.enrollments.get("node-1").unwrap().addr, "127.0.0.1:9001");
+}
+
+fn revoke_command(node_id: &str, serial: &str, crl_number: u64) -> ClusterCommand {
+    ClusterCommand::RevokeNode {
+        node_id: node_id.to_string(),
+        revoked: vec![RevokedCertificate {
+            serial: serial.to_string(),
+            node_id: node_id.to_string(),
+            revoked_at: Utc::now(),
+            reason: None,
+        }],
+        crl: SignedCrl {
+            number: crl_number,
+            pem: format!("crl-{}", crl_number),
+        },
+    }
+}
+
+#[test]
+fn test_apply_revoke_node() {
+    let mut state = HiveState::new();
+    state.apply(&ClusterCommand::RegisterNode(NodeStatus {
+        node_id: "node-1".to_string(),
+        hostname: "host1".to_string(),
+        tags: vec![],
+        health: NodeHealth::Healthy,
+        last_heartbeat: Utc::now(),
+        cpu_usage: 0.0,
+        memory_usage: 0.0,
+        disk_usage: 0.0,
+        revision: 0,
+    }));
+    state.apply(&ClusterCommand::RecordEnrollment(EnrolledNode {
+        node_id: "node-1".to_string(),
+        hostname: "host1".to_string(),
+        addr: "127.0.0.1:9001".to_string(),
+        tags: vec![],
+        enrolled_at: Utc::now(),
+        cert_serial: Some("0a1b".to_string()),
+        superseded_serials: vec![],
+        issuer_key_id: None,
+        cert_expiry: Default::default(),
+    }));
+
+    state.apply(&revoke_command("node-1", "0a1b", 1));
+
+    assert!(state.nodes.is_empty());
+    assert!(state.enrollments.is_empty());
+    assert_eq!(state.revoked_certificates.get("0a1b").unwrap().node_id, "node-1");
+    assert_eq!(state.crl.as_ref().unwrap().number, 1);
+}
+
+#[test]
+fn test_apply_revoke_node_requires_next_crl_number() {
+    let mut state = HiveState::new();
+
+    assert!(state.apply(&revoke_command("node-1", "01", 1)).is_applied());
+    // Signed over the same state as the first, so it would drop serial 01.
+    assert_eq!(
+        state.apply(&revoke_command("node-2", "02", 1)),
+        CommandResult::Conflict
+    );
+    assert_eq!(
+        state.apply(&revoke_command("node-2", "02", 3)),
+        CommandResult::Conflict
+    );
+    assert_eq!(state.revoked_certificates.len(), 1);
+
+    assert!(state.apply(&revoke_command("node-2", "02", 2)).is_applied());
+    assert_eq!(state.revoked_certificates.len(), 2);
+    assert_eq!(state.crl.unwrap().pem, "crl-2");
+}
+
+#[test]
+fn test_apply_trust_bundle_keeps_newest_version() {
+    let mut state = HiveState::new();
+    let bundle = |version: u64| TrustBundle {
+        version,
+        roots_pem: format!("roots-{}", version),
+        signing_key_id: "aa".to_string(),
+    };
+
+    state.apply(&ClusterCommand::SetTrustBundle(bundle(2)));
+    assert_eq!(
+        state.apply(&ClusterCommand::SetTrustBundle(bundle(1))),
+        CommandResult::Conflict
+    );
+    assert_eq!(state.trust_bundle.as_ref().unwrap().version, 2);
+
+    state.apply(&ClusterCommand::SetTrustBundle(bundle(3)));
+    assert_eq!(state.trust_bundle.unwrap().roots_pem, "roots-3");
+}
+
+fn echo_task(id: &str) -> Task {
+    Task {
+        id: id.to_string(),
+        target_node: "node-1".to_string(),
+        payload: TaskPayload::Echo {
+            message: "hello".to_string(),
+        },
+        status: TaskStatus::Pending,
+        priority: 5,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        result: None,
+        revision: 0,
+        lock: None,
+    }
+}
+
+#[test]
+fn test_apply_assigns_increasing_revisions() {
+    let mut state = HiveState::new();
+
+    let first = state.apply(&ClusterCommand::PutTask(echo_task("task-1")));
+    let second = state.apply(&ClusterCommand::UpdateTaskStatus {
+        task_id: "task-1".to_string(),
+        status: TaskStatus::Running,
+        result: None,
+        expected_revision: None,
+    });
+
+    assert_eq!(first.revision(), Some(1));
+    assert_eq!(second.revision(), Some(2));
+    assert_eq!(state.tasks.get("task-1").unwrap().revision, 2);
+    assert_eq!(state.revision, 2);
+}
+
+#[test]
+fn test_apply_put_with_stale_revision_conflicts() {
+    let mut state = HiveState::new();
+    state.apply(&ClusterCommand::PutTaskIf {
+        task: echo_task("task-1"),
+        expected_revision: 0,
+    });
+    state.apply(&ClusterCommand::UpdateTaskStatus {
+        task_id: "task-1".to_string(),
+        status: TaskStatus::Running,
+        result: None,
+        expected_revision: Some(1),
+    });
+
+    let mut rebalanced = echo_task("task-1");
+    rebalanced.target_node = "node-2".to_string();
+    let result = state.apply(&ClusterCommand::PutTaskIf {
+        task: rebalanced,
+        expected_revision: 1,
+    });
+
+    assert_eq!(result, CommandResult::Conflict);
+    let task = state.tasks.get("task-1").unwrap();
+    assert_eq!(task.status, TaskStatus::Running);
+    assert_eq!(task.target_node, "node-1");
+    assert_eq!(state.revision, 2);
+}
+
+#[test]
+fn test_apply_create_only_rejects_existing_entry() {
+    let mut state = HiveState::new();
+    let put = || ClusterCommand::PutTaskIf {
+        task: echo_task("task-1"),
+        expected_revision: 0,
+    };
+
+    assert!(state.apply(&put()).is_applied());
+    assert_eq!(state.apply(&put()), CommandResult::Conflict);
+    assert_eq!(
+        state.apply(&ClusterCommand::UpdateTaskStatus {
+            task_id: "task-2".to_string(),
+            status: TaskStatus::Running,
+            result: None,
+            expected_revision: Some(1),
+        }),
+        CommandResult::NotFound
+    );
+}
+
+#[test]
+fn test_apply_assign_raft_id() {
+    let mut state = HiveState::new();
+    let assign = |node_id: &str, raft_id| ClusterCommand::AssignRaftId {
+        node_id: node_id.to_string(),
+        raft_id,
+    };
+
+    assert_eq!(state.next_raft_id(), 1);
+    assert!(state.apply(&assign("production-web-1", 1)).is_applied());
+    assert!(state.apply(&assign("production-web-1", 1)).is_applied());
+    assert_eq!(state.apply(&assign("production-web-2", 1)), CommandResult::Conflict);
+    assert_eq!(state.apply(&assign("production-web-1", 2)), CommandResult::Conflict);
+
+    assert_eq!(state.next_raft_id(), 2);
+    assert!(state.apply(&assign("production-web-2", 2)).is_applied());
+    assert_eq!(state.raft_id_of("production-web-2"), Some(2));
+    assert_eq!(state.node_id_of(1).map(String::as_str), Some("production-web-1"));
+}
@@ -0,0 +1,463 @@
+mod common;
+
+use common::{goal, log_id, memory_backend};
+use flockmind::config::{PeerConfig, StorageSettings, TlsSettings};
+use flockmind::replicator::backend::{BackendKind, StorageConfig};
+use flockmind::replicator::codec;
+use flockmind::replicator::state_machine::*;
+use flockmind::replicator::{HiveNode, HiveStorage, SnapshotConfig, StateBackup, TypeConfig};
+use flockmind::{
+    AttachmentKind, ClusterCommand, Goal, HiveDaemon, NodeConfig, RaftReplicator, Replicator,
+    TaskStatus,
+};
+use openraft::storage::RaftStorage;
+use openraft::{Entry, EntryPayload, RaftLogReader, RaftSnapshotBuilder, Vote};
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn file_storage() -> StorageConfig {
+    StorageConfig {
+        backend: BackendKind::File,
+        ..Default::default()
+    }
+}
+
+fn put_goal_entry(index: u64, description: &str) -> Entry<TypeConfig> {
+    Entry {
+        log_id: log_id(index),
+        payload: EntryPayload::Normal(ClusterCommand::PutGoal(goal(description))),
+    }
+}
+
+#[tokio::test]
+async fn test_restart_restores_last_snapshot() {
+    let backend = memory_backend();
+
+    {
+        let mut storage = HiveStorage::new(backend.clone(), SharedState::new()).unwrap();
+        let entries: Vec<_> = (1..=3).map(|i| put_goal_entry(i, "snapshotted")).collect();
+        storage.apply_to_state_machine(&entries).await.unwrap();
+        storage.build_snapshot().await.unwrap();
+
+        let entries: Vec<_> = (4..=5).map(|i| put_goal_entry(i, "after")).collect();
+        storage.apply_to_state_machine(&entries).await.unwrap();
+        assert_eq!(storage.shared_state().read(|s| s.goals.len()), 5);
+    }
+
+    let state = SharedState::new();
+    let mut storage = HiveStorage::new(backend.clone(), state.clone()).unwrap();
+
+    // Entries after the snapshot are left for openraft to re-apply.
+    assert_eq!(state.read(|s| s.goals.len()), 3);
+    let (last_applied, _) = storage.last_applied_state().await.unwrap();
+    assert_eq!(last_applied, Some(log_id(3)));
+
+    let snapshot = storage.get_current_snapshot().await.unwrap().unwrap();
+    assert_eq!(snapshot.meta.last_log_id, Some(log_id(3)));
+}
+
+#[tokio::test]
+async fn test_restart_without_snapshot_starts_empty() {
+    let backend = memory_backend();
+
+    {
+        let mut storage = HiveStorage::new(backend.clone(), SharedState::new()).unwrap();
+        storage
+            .apply_to_state_machine(&[put_goal_entry(1, "unsnapshotted")])
+            .await
+            .unwrap();
+    }
+
+    let state = SharedState::new();
+    let mut storage = HiveStorage::new(backend.clone(), state.clone()).unwrap();
+    assert_eq!(state.read(|s| s.goals.len()), 0);
+    assert_eq!(storage.last_applied_state().await.unwrap().0, None);
+    assert!(storage.get_current_snapshot().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_legacy_state_is_migrated_to_snapshot() {
+    let backend = memory_backend();
+
+    {
+        let mut hive_state = HiveState::new();
+        hive_state.apply(&ClusterCommand::PutGoal(goal("legacy")));
+
+        let legacy_state = serde_json::to_vec(&hive_state).unwrap();
+        let legacy_last_applied = bincode::serialize(&log_id(7)).unwrap();
+        backend
+            .write_meta(
+                vec![
+                    (b"state_snapshot".to_vec(), Some(legacy_state)),
+                    (b"last_applied".to_vec(), Some(legacy_last_applied)),
+                ],
+                true,
+            )
+            .unwrap();
+    }
+
+    let state = SharedState::new();
+    let mut storage = HiveStorage::new(backend.clone(), state.clone()).unwrap();
+    assert_eq!(state.read(|s| s.goals.len()), 1);
+    assert_eq!(storage.last_applied_state().await.unwrap().0, Some(log_id(7)));
+
+    let snapshot = storage.get_current_snapshot().await.unwrap().unwrap();
+    assert_eq!(snapshot.meta.last_log_id, Some(log_id(7)));
+}
+
+#[tokio::test]
+async fn test_restarted_node_replays_committed_log() {
+    let dir = TempDir::new().unwrap();
+    let snapshot = SnapshotConfig {
+        logs_since_last: 4,
+        logs_to_keep: 100,
+    };
+
+    let goals: Vec<Goal> = (0..10).map(|i| goal(&format!("goal-{}", i))).collect();
+    {
+        let replicator = RaftReplicator::new(
+            1,
+            "127.0.0.1:0".to_string(),
+            "node-1".to_string(),
+            dir.path(),
+            None,
+            snapshot.clone(),
+            file_storage(),
+        )
+        .await
+        .unwrap();
+        replicator.bootstrap(Default::default()).await.unwrap();
+        replicator
+            .raft()
+            .wait(Some(Duration::from_secs(10)))
+            .current_leader(1, "leader election")
+            .await
+            .unwrap();
+
+        for goal in &goals {
+            replicator
+                .apply(ClusterCommand::PutGoalIf {
+                    goal: goal.clone(),
+                    expected_revision: 0,
+                })
+                .await
+                .unwrap();
+        }
+        replicator.raft().shutdown().await.unwrap();
+    }
+
+    let replicator = RaftReplicator::new(
+        1,
+        "127.0.0.1:0".to_string(),
+        "node-1".to_string(),
+        dir.path(),
+        None,
+        snapshot.clone(),
+        file_storage(),
+    )
+    .await
+    .unwrap();
+
+    let view = replicator.snapshot();
+    assert_eq!(view.goals.len(), goals.len());
+    assert!(goals
+        .iter()
+        .all(|goal| view.goals.iter().any(|g| g.id == goal.id)));
+}
+
+/// Log records exactly as the first release wrote them, one JSON entry
+/// per line.
+const V0_LOG: &str = include_str!("fixtures/v0_log.jsonl");
+
+#[tokio::test]
+async fn test_unversioned_records_are_migrated() {
+    let backend = memory_backend();
+    let vote = Vote::new_committed(1, 1);
+
+    {
+        let records = V0_LOG
+            .lines()
+            .zip(1..)
+            .map(|(line, i)| (i, line.as_bytes().to_vec()))
+            .collect();
+        backend.append_log(records).unwrap();
+        let vote = bincode::serialize(&vote).unwrap();
+        backend
+            .write_meta(vec![(b"vote".to_vec(), Some(vote))], true)
+            .unwrap();
+    }
+
+    let state = SharedState::new();
+    let mut storage = HiveStorage::new(backend.clone(), state.clone()).unwrap();
+    let log_state = storage.get_log_state().await.unwrap();
+    assert_eq!(log_state.last_log_id, Some(log_id(7)));
+    assert_eq!(storage.read_vote().await.unwrap(), Some(vote));
+
+    let records = backend.read_log(0, None).unwrap();
+    assert!(records.iter().all(|(_, record)| codec::has_header(record)));
+
+    let entries = storage.try_get_log_entries(1..=7).await.unwrap();
+    assert_eq!(entries.len(), 7);
+    storage.apply_to_state_machine(&entries).await.unwrap();
+    let (_, membership) = storage.last_applied_state().await.unwrap();
+    assert!(membership.membership().voter_ids().eq([1]));
+
+    state.read(|s| {
+        assert_eq!(s.nodes["web-1"].tags, vec!["web"]);
+        let task = &s.tasks["task-1"];
+        assert_eq!(task.status, TaskStatus::Completed);
+        assert_eq!(task.result, Some(serde_json::json!({ "exit_code": 0 })));
+        match &s.attachments["attach-1"].kind {
+            AttachmentKind::Custom { config, .. } => {
+                assert_eq!(config, &serde_json::json!({ "port": 8080 }))
+            }
+            kind => panic!("Wrong attachment kind {:?}", kind),
+        }
+        assert_eq!(s.goals["goal-1"].constraints, vec!["no restarts"]);
+    });
+}
+
+#[tokio::test]
+async fn test_unreadable_log_entry_is_an_error() {
+    let backend = memory_backend();
+
+    {
+        let mut storage = HiveStorage::new(backend.clone(), SharedState::new()).unwrap();
+        storage
+            .append_to_log([put_goal_entry(1, "fine")])
+            .await
+            .unwrap();
+    }
+    {
+        let (_, mut record) = backend.last_log().unwrap().unwrap();
+        record.truncate(record.len() / 2);
+        backend.append_log(vec![(2, record)]).unwrap();
+    }
+
+    let mut storage = HiveStorage::new(backend.clone(), SharedState::new()).unwrap();
+    let err = storage.get_log_state().await.unwrap_err();
+    assert!(err.to_string().contains("log entry 2"), "{}", err);
+    assert!(storage.try_get_log_entries(1..=2).await.is_err());
+}
+
+#[tokio::test]
+async fn test_newer_format_version_is_rejected() {
+    let backend = memory_backend();
+    let version = vec![codec::FORMAT_VERSION + 1];
+    backend
+        .write_meta(vec![(b"format_version".to_vec(), Some(version))], true)
+        .unwrap();
+
+    assert!(HiveStorage::new(backend.clone(), SharedState::new()).is_err());
+}
+
+#[tokio::test]
+async fn test_backup_restores_into_new_single_node_cluster() {
+    let source_dir = TempDir::new().unwrap();
+    let goals: Vec<Goal> = (0..3).map(|i| goal(&format!("goal-{}", i))).collect();
+
+    let data = {
+        let replicator = RaftReplicator::new(
+            1,
+            "127.0.0.1:0".to_string(),
+            "node-1".to_string(),
+            source_dir.path(),
+            None,
+            SnapshotConfig::default(),
+            file_storage(),
+        )
+        .await
+        .unwrap();
+        replicator.bootstrap(Default::default()).await.unwrap();
+        replicator
+            .raft()
+            .wait(Some(Duration::from_secs(10)))
+            .current_leader(1, "leader election")
+            .await
+            .unwrap();
+        replicator
+            .apply(ClusterCommand::AssignRaftId {
+                node_id: "node-1".to_string(),
+                raft_id: 1,
+            })
+            .await
+            .unwrap();
+        for goal in &goals {
+            replicator
+                .apply(ClusterCommand::PutGoalIf {
+                    goal: goal.clone(),
+                    expected_revision: 0,
+                })
+                .await
+                .unwrap();
+        }
+
+        let backup = replicator.backup().await.unwrap();
+        assert_eq!(backup.state.goals.len(), goals.len());
+        replicator.raft().shutdown().await.unwrap();
+        backup.encode().unwrap()
+    };
+
+    let backup = StateBackup::decode(&data).unwrap();
+    let state = SharedState::new();
+    state.restore(backup.state.clone());
+    assert_eq!(state.read(|s| s.goals.len()), goals.len());
+
+    let target_dir = TempDir::new().unwrap();
+    let config = NodeConfig {
+        data_dir: target_dir.path().to_path_buf(),
+        raft_id: Some(7),
+        storage: StorageSettings {
+            backend: BackendKind::File,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    assert_eq!(HiveDaemon::restore(&config, &backup).unwrap(), 7);
+    assert!(HiveDaemon::restore(&config, &backup).is_err());
+
+    let replicator = RaftReplicator::new(
+        7,
+        "127.0.0.1:0".to_string(),
+        "node-7".to_string(),
+        target_dir.path(),
+        None,
+        SnapshotConfig::default(),
+        config.storage.to_storage_config().unwrap(),
+    )
+    .await
+    .unwrap();
+    replicator.bootstrap(Default::default()).await.unwrap();
+    replicator
+        .raft()
+        .wait(Some(Duration::from_secs(10)))
+        .current_leader(7, "leader election")
+        .await
+        .unwrap();
+
+    let view = replicator.snapshot();
+    assert!(goals
+        .iter()
+        .all(|goal| view.goals.iter().any(|g| g.id == goal.id)));
+    let members = replicator.members();
+    assert_eq!(members.len(), 1);
+    assert!(members[0].is_leader);
+
+    replicator
+        .apply(ClusterCommand::PutGoalIf {
+            goal: goal("after restore"),
+            expected_revision: 0,
+        })
+        .await
+        .unwrap();
+    assert_eq!(replicator.snapshot().goals.len(), goals.len() + 1);
+    replicator.raft().shutdown().await.unwrap();
+
+    let new_node_dir = TempDir::new().unwrap();
+    let config = NodeConfig {
+        node_id: Some("node-2".to_string()),
+        data_dir: new_node_dir.path().to_path_buf(),
+        raft_id: None,
+        ..config
+    };
+    assert_eq!(HiveDaemon::restore(&config, &backup).unwrap(), 2);
+
+    let replicator = RaftReplicator::new(
+        2,
+        "127.0.0.1:0".to_string(),
+        "node-2".to_string(),
+        new_node_dir.path(),
+        None,
+        SnapshotConfig::default(),
+        config.storage.to_storage_config().unwrap(),
+    )
+    .await
+    .unwrap();
+    let state = replicator.hive_state();
+    assert_eq!(state.raft_id_of("node-1"), Some(1));
+    assert_eq!(state.raft_id_of("node-2"), Some(2));
+
+    let taken_dir = TempDir::new().unwrap();
+    let config = NodeConfig {
+        node_id: Some("node-3".to_string()),
+        data_dir: taken_dir.path().to_path_buf(),
+        raft_id: Some(1),
+        ..config
+    };
+    assert!(HiveDaemon::restore(&config, &backup).is_err());
+}
+
+#[tokio::test]
+async fn test_restore_needs_empty_storage_and_applied_state() {
+    let node = HiveNode::default();
+    let storage = HiveStorage::new(memory_backend(), SharedState::new()).unwrap();
+    let mut backup = storage.backup();
+    assert!(storage.restore_backup(&backup, 1, node.clone()).is_err());
+
+    backup.last_log_id = Some(log_id(3));
+    storage.restore_backup(&backup, 1, node.clone()).unwrap();
+    assert!(storage.restore_backup(&backup, 1, node).is_err());
+    assert!(StateBackup::decode(b"not a backup").is_err());
+}
+
+#[tokio::test]
+async fn test_upgraded_node_keeps_legacy_raft_id() {
+    let dir = TempDir::new().unwrap();
+    // Nodes used to run as the first eight bytes of their node ID.
+    let legacy_id = u64::from_le_bytes(*b"web-1\0\0\0");
+    {
+        let replicator = RaftReplicator::new(
+            legacy_id,
+            "127.0.0.1:0".to_string(),
+            "web-1".to_string(),
+            dir.path(),
+            None,
+            SnapshotConfig::default(),
+            StorageConfig::default(),
+        )
+        .await
+        .unwrap();
+        replicator.bootstrap(Default::default()).await.unwrap();
+        replicator
+            .raft()
+            .wait(Some(Duration::from_secs(10)))
+            .current_leader(legacy_id, "leader election")
+            .await
+            .unwrap();
+        replicator
+            .apply(ClusterCommand::PutGoal(goal("before upgrade")))
+            .await
+            .unwrap();
+        replicator.raft().shutdown().await.unwrap();
+    }
+
+    let config = NodeConfig {
+        node_id: Some("web-1".to_string()),
+        data_dir: dir.path().to_path_buf(),
+        peers: vec![PeerConfig {
+            node_id: "web-2".to_string(),
+            addr: "127.0.0.1:1".to_string(),
+            is_voter: true,
+            raft_id: None,
+        }],
+        tls: TlsSettings {
+            enabled: false,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let daemon = HiveDaemon::new(config).await.unwrap();
+    let replicator = daemon.replicator();
+    assert_eq!(replicator.node_id(), legacy_id);
+    replicator
+        .raft()
+        .wait(Some(Duration::from_secs(10)))
+        .current_leader(legacy_id, "leader election")
+        .await
+        .unwrap();
+    assert_eq!(replicator.snapshot().goals.len(), 1);
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("raft_id")).unwrap(),
+        legacy_id.to_string()
+    );
+}
@@ -0,0 +1,339 @@
+use axum::{routing::get, Router};
+use flockmind::auth::{CaCertificate, ClusterTls, NodeCertificate};
+use flockmind::{RevokedCertificate, SignedCrl};
+use flockmind::server;
+use flockmind::auth::{create_client_tls_config, create_tls_config, extract_cn_from_der};
+use std::sync::Arc;
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+async fn spawn_tls_server(tls: Arc<ClusterTls>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let router = Router::new()
+        .route("/health", get(|| async { "ok" }))
+        .route("/cluster", get(|| async { "cluster" }));
+
+    tokio::spawn(async move {
+        let _ = server::serve(listener, router, Some(tls)).await;
+    });
+
+    format!("https://{}", addr)
+}
+
+fn cluster_tls(ca: &CaCertificate, node_id: &str) -> Arc<ClusterTls> {
+    let node_cert = ca.sign_node(node_id, vec![], vec![]).unwrap();
+    Arc::new(ClusterTls::new(node_cert, ca.cert_pem.clone()).unwrap())
+}
+
+#[tokio::test]
+async fn test_mtls_request_between_cluster_members() {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let url = spawn_tls_server(cluster_tls(&ca, "node-1")).await;
+
+    let client = cluster_tls(&ca, "node-2").http_client();
+    let resp = client.get(format!("{}/cluster", url)).send().await.unwrap();
+
+    assert_eq!(resp.status(), reqwest::StatusCode::OK);
+    assert_eq!(resp.text().await.unwrap(), "cluster");
+}
+
+#[tokio::test]
+async fn test_mtls_requires_client_certificate_outside_public_routes() {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let url = spawn_tls_server(cluster_tls(&ca, "node-1")).await;
+
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+
+    let resp = client.get(format!("{}/cluster", url)).send().await.unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let resp = client.get(format!("{}/health", url)).send().await.unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_mtls_rejects_certificate_from_foreign_ca() {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let url = spawn_tls_server(cluster_tls(&ca, "node-1")).await;
+
+    let foreign_ca = CaCertificate::generate("other-cluster").unwrap();
+    let client = cluster_tls(&foreign_ca, "intruder").http_client();
+
+    assert!(client.get(format!("{}/cluster", url)).send().await.is_err());
+}
+
+fn revoke(ca: &CaCertificate, tls: &ClusterTls, cert: &NodeCertificate) {
+    let crl = ca
+        .sign_crl(
+            &[RevokedCertificate {
+                serial: cert.serial().unwrap(),
+                node_id: cert.node_id.clone(),
+                revoked_at: chrono::Utc::now(),
+                reason: None,
+            }],
+            1,
+        )
+        .unwrap();
+    tls.update_crl(SignedCrl { number: 1, pem: crl }).unwrap();
+}
+
+#[tokio::test]
+async fn test_mtls_rejects_revoked_certificate() {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let server_tls = cluster_tls(&ca, "node-1");
+    let url = spawn_tls_server(server_tls.clone()).await;
+
+    let client_tls = cluster_tls(&ca, "node-2");
+    revoke(&ca, &server_tls, &client_tls.node_cert());
+
+    let client = client_tls.http_client();
+    assert!(client.get(format!("{}/cluster", url)).send().await.is_err());
+
+    let other = cluster_tls(&ca, "node-3").http_client();
+    let resp = other.get(format!("{}/cluster", url)).send().await.unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_mtls_revocation_applies_to_open_connections() {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let server_tls = cluster_tls(&ca, "node-1");
+    let url = spawn_tls_server(server_tls.clone()).await;
+
+    let client_tls = cluster_tls(&ca, "node-2");
+    let client = client_tls.http_client();
+    let resp = client.get(format!("{}/cluster", url)).send().await.unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+    revoke(&ca, &server_tls, &client_tls.node_cert());
+
+    let resp = client.get(format!("{}/cluster", url)).send().await.unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_revocation_missing_from_crl_rejects_requests() {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let server_tls = cluster_tls(&ca, "node-1");
+    let url = spawn_tls_server(server_tls.clone()).await;
+
+    let client_tls = cluster_tls(&ca, "node-2");
+    server_tls
+        .set_revoked_records([client_tls.node_cert().serial().unwrap()].into())
+        .unwrap();
+
+    let resp = client_tls
+        .http_client()
+        .get(format!("{}/cluster", url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::FORBIDDEN);
+}
+
+/// Neither side has a CRL from the outgoing CA, so its certificates pass
+/// the handshake with an unknown revocation status. Revoked ones are then
+/// refused by serial.
+#[tokio::test]
+async fn test_revoked_certificate_from_outgoing_ca_during_rollover() {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let old_ca = CaCertificate::generate("test-cluster").unwrap();
+    let new_root = CaCertificate::generate_root("test-cluster").unwrap();
+    let new_ca = new_root.issue_intermediate("test-cluster").unwrap();
+    let both_roots = format!("{}{}", old_ca.cert_pem, new_root.cert_pem);
+    let node_tls = |ca: &CaCertificate, node_id: &str| {
+        let node_cert = ca.sign_node(node_id, vec![], vec![]).unwrap();
+        Arc::new(ClusterTls::new(node_cert, both_roots.clone()).unwrap())
+    };
+
+    let server_tls = node_tls(&new_ca, "node-1");
+    let url = spawn_tls_server(server_tls.clone()).await;
+    let old_tls = node_tls(&old_ca, "node-2");
+    let revoked = old_tls.node_cert().serial().unwrap();
+
+    revoke(&new_ca, &server_tls, &node_tls(&new_ca, "node-3").node_cert());
+    let client = old_tls.http_client();
+    let resp = client.get(format!("{}/cluster", url)).send().await.unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+    server_tls.set_revoked_records([revoked.clone()].into()).unwrap();
+    let resp = client.get(format!("{}/cluster", url)).send().await.unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let old_url = spawn_tls_server(old_tls.clone()).await;
+    let client = server_tls.http_client();
+    assert!(client.get(format!("{}/cluster", old_url)).send().await.is_err());
+}
+
+#[tokio::test]
+async fn test_client_rejects_revoked_server_certificate() {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let server_tls = cluster_tls(&ca, "node-1");
+    let url = spawn_tls_server(server_tls.clone()).await;
+
+    let client_tls = cluster_tls(&ca, "node-2");
+    revoke(&ca, &client_tls, &server_tls.node_cert());
+
+    assert!(client_tls
+        .http_client()
+        .get(format!("{}/cluster", url))
+        .send()
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_node_certificate_hot_swap() {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let expired = ca
+        .sign_node_with_validity("node-1", vec![], vec![], chrono::Duration::minutes(-1))
+        .unwrap();
+    let server_tls = Arc::new(ClusterTls::new(expired, ca.cert_pem.clone()).unwrap());
+    let url = spawn_tls_server(server_tls.clone()).await;
+
+    let client = cluster_tls(&ca, "node-2").http_client();
+    assert!(client.get(format!("{}/cluster", url)).send().await.is_err());
+
+    let renewed = ca.sign_node("node-1", vec![], vec![]).unwrap();
+    server_tls.update_node_cert(renewed.clone()).unwrap();
+
+    let resp = client.get(format!("{}/cluster", url)).send().await.unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::OK);
+    assert_eq!(server_tls.node_cert().serial().unwrap(), renewed.serial().unwrap());
+}
+
+/// Runs a handshake between two node certificates over an in-memory pipe
+/// and returns the node IDs each side authenticated.
+async fn handshake(
+    ca_cert_pem: &str,
+    server: &NodeCertificate,
+    client: &NodeCertificate,
+) -> anyhow::Result<(String, String)> {
+    let acceptor = TlsAcceptor::from(create_tls_config(server, ca_cert_pem)?);
+    let connector = TlsConnector::from(create_client_tls_config(client, ca_cert_pem)?);
+    let (client_io, server_io) = tokio::io::duplex(16 * 1024);
+
+    let server_task = tokio::spawn(async move {
+        let mut stream = acceptor.accept(server_io).await?;
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await?;
+        stream.write_all(b"pong").await?;
+        stream.flush().await?;
+        let peer = stream.get_ref().1.peer_certificates().unwrap()[0].clone();
+        anyhow::Ok(extract_cn_from_der(&peer)?)
+    });
+
+    let mut stream = connector
+        .connect(ServerName::try_from("127.0.0.1")?, client_io)
+        .await?;
+    stream.write_all(b"ping").await?;
+    stream.flush().await?;
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf).await?;
+    assert_eq!(&buf, b"pong");
+    let peer = stream.get_ref().1.peer_certificates().unwrap()[0].clone();
+
+    let seen_by_server = server_task.await??;
+    Ok((extract_cn_from_der(&peer)?, seen_by_server))
+}
+
+#[tokio::test]
+async fn test_rustls_handshake_between_issued_certificates() {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let node1 = ca.sign_node("node-1", vec![], vec![]).unwrap();
+    let node2 = ca.sign_node("node-2", vec![], vec![]).unwrap();
+
+    let (seen_by_client, seen_by_server) = handshake(&ca.cert_pem, &node1, &node2).await.unwrap();
+    assert_eq!(seen_by_client, "node-1");
+    assert_eq!(seen_by_server, "node-2");
+}
+
+#[tokio::test]
+async fn test_rustls_handshake_after_ca_reload() {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let temp_dir = TempDir::new().unwrap();
+    let cert_path = temp_dir.path().join("ca.crt");
+    let key_path = temp_dir.path().join("ca.key");
+
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    ca.save(&cert_path, &key_path).unwrap();
+    let before_restart = ca.sign_node("node-1", vec![], vec![]).unwrap();
+
+    let reloaded = CaCertificate::load(&cert_path, &key_path).unwrap();
+    let after_restart = reloaded.sign_node("node-2", vec![], vec![]).unwrap();
+    let saved_pem = std::fs::read_to_string(&cert_path).unwrap();
+
+    handshake(&saved_pem, &before_restart, &after_restart).await.unwrap();
+    handshake(&saved_pem, &after_restart, &before_restart).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_rustls_handshake_rejects_certificate_from_other_ca() {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let ca = CaCertificate::generate("test-cluster").unwrap();
+    let other = CaCertificate::generate("test-cluster").unwrap();
+    let node1 = ca.sign_node("node-1", vec![], vec![]).unwrap();
+    let impostor = other.sign_node("node-2", vec![], vec![]).unwrap();
+
+    assert!(handshake(&ca.cert_pem, &node1, &impostor).await.is_err());
+}
+
+#[tokio::test]
+async fn test_rustls_handshake_through_intermediate() {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let root = CaCertificate::generate_root("test-cluster").unwrap();
+    let intermediate = root.issue_intermediate("test-cluster").unwrap();
+    let node1 = intermediate.sign_node("node-1", vec![], vec![]).unwrap();
+    let node2 = intermediate.sign_node("node-2", vec![], vec![]).unwrap();
+
+    let (seen_by_client, seen_by_server) = handshake(&root.cert_pem, &node1, &node2).await.unwrap();
+    assert_eq!(seen_by_client, "node-1");
+    assert_eq!(seen_by_server, "node-2");
+}
+
+#[tokio::test]
+async fn test_rustls_handshake_during_ca_rollover() {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let old_ca = CaCertificate::generate("test-cluster").unwrap();
+    let new_root = CaCertificate::generate_root("test-cluster").unwrap();
+    let new_intermediate = new_root.issue_intermediate("test-cluster").unwrap();
+
+    let not_yet_reissued = old_ca.sign_node("node-1", vec![], vec![]).unwrap();
+    let reissued = new_intermediate.sign_node("node-2", vec![], vec![]).unwrap();
+
+    let both_roots = format!("{}{}", old_ca.cert_pem, new_root.cert_pem);
+    handshake(&both_roots, &not_yet_reissued, &reissued).await.unwrap();
+    handshake(&both_roots, &reissued, &not_yet_reissued).await.unwrap();
+
+    assert!(handshake(&new_root.cert_pem, &reissued, &not_yet_reissued).await.is_err());
+}
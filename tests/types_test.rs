@@ -24,6 +24,7 @@ fn test_cluster_view_node_by_id() {
         cpu_usage: 0.5,
         memory_usage: 0.3,
         disk_usage: 0.2,
+        revision: 0,
     });
 
     assert!(view.node_by_id("node-1").is_some());
@@ -42,6 +43,7 @@ fn test_cluster_view_healthy_nodes() {
         cpu_usage: 0.0,
         memory_usage: 0.0,
         disk_usage: 0.0,
+        revision: 0,
     });
     view.nodes.push(NodeStatus {
         node_id: "node-2".to_string(),
@@ -54,6 +56,7 @@ fn test_cluster_view_healthy_nodes() {
         cpu_usage: 0.9,
         memory_usage: 0.0,
         disk_usage: 0.0,
+        revision: 0,
     });
 
     let healthy = view.healthy_nodes();
@@ -73,6 +76,7 @@ fn test_cluster_view_nodes_with_tag() {
         cpu_usage: 0.0,
         memory_usage: 0.0,
         disk_usage: 0.0,
+        revision: 0,
     });
     view.nodes.push(NodeStatus {
         node_id: "node-2".to_string(),
@@ -83,6 +87,7 @@ fn test_cluster_view_nodes_with_tag() {
         cpu_usage: 0.0,
         memory_usage: 0.0,
         disk_usage: 0.0,
+        revision: 0,
     });
 
     let gpu_nodes = view.nodes_with_tag("gpu");
@@ -104,6 +109,8 @@ fn test_cluster_view_pending_tasks() {
         created_at: Utc::now(),
         updated_at: Utc::now(),
         result: None,
+        revision: 0,
+        lock: None,
     });
     view.tasks.push(Task {
         id: "task-2".to_string(),
@@ -116,6 +123,8 @@ fn test_cluster_view_pending_tasks() {
         created_at: Utc::now(),
         updated_at: Utc::now(),
         result: None,
+        revision: 0,
+        lock: None,
     });
 
     let pending = view.pending_tasks();
@@ -159,6 +168,7 @@ fn test_cluster_command_serialization() {
             memory_usage: 0.3,
             disk_usage: 0.2,
         },
+        expected_revision: None,
     };
 
     let json = serde_json::to_string(&cmd).unwrap();
@@ -174,3 +184,52 @@ fn test_cluster_command_serialization() {
         _ => panic!("Wrong variant"),
     }
 }
+
+#[test]
+fn test_embedded_json_round_trips_through_storage_codec() {
+    let result = serde_json::json!({ "exit_code": 0, "lines": ["a", "b"] });
+    let cmd = ClusterCommand::UpdateTaskStatus {
+        task_id: "task-1".to_string(),
+        status: TaskStatus::Completed,
+        result: Some(result.clone()),
+        expected_revision: Some(3),
+    };
+
+    let record = flockmind::replicator::codec::encode(&cmd).unwrap();
+    let decoded: ClusterCommand = flockmind::replicator::codec::decode(&record).unwrap();
+    match decoded {
+        ClusterCommand::UpdateTaskStatus {
+            result: decoded, ..
+        } => assert_eq!(decoded, Some(result.clone())),
+        _ => panic!("Wrong variant"),
+    }
+
+    // JSON keeps the value inline rather than as an escaped string.
+    let json = serde_json::to_value(&cmd).unwrap();
+    assert_eq!(json["UpdateTaskStatus"]["result"], result);
+}
+
+#[test]
+fn test_baseline_commands_deserialize() {
+    // Commands exactly as nodes from before entity revisions wrote them to
+    // their logs and sent them to peers.
+    let put_task = r#"{"PutTask":{"id":"task-1","target_node":"node-1","payload":{"Echo":{"message":"hi"}},"status":"Pending","priority":5,"created_at":"2024-05-01T12:00:00Z","updated_at":"2024-05-01T12:00:00Z","result":null}}"#;
+    let put_attachment = r#"{"PutAttachment":{"id":"attach-1","node_id":"node-1","kind":{"Custom":{"type_name":"probe","config":{"port":8080}}},"capabilities":["read"],"metadata":{},"created_at":"2024-05-01T12:00:00Z"}}"#;
+    let put_goal = r#"{"PutGoal":{"id":"goal-1","description":"Keep web up","constraints":[],"priority":5,"active":true,"created_at":"2024-05-01T12:00:00Z"}}"#;
+    let update_status = r#"{"UpdateTaskStatus":{"task_id":"task-1","status":"Completed","result":{"exit_code":0}}}"#;
+
+    let mut state = flockmind::replicator::state_machine::HiveState::new();
+    for json in [put_task, put_attachment, put_goal, update_status] {
+        let cmd: ClusterCommand = serde_json::from_str(json).unwrap();
+        assert!(state.apply(&cmd).is_applied(), "{}", json);
+    }
+    assert_eq!(state.tasks["task-1"].status, TaskStatus::Completed);
+    assert_eq!(state.tasks["task-1"].revision, 4);
+    assert_eq!(state.attachments["attach-1"].capabilities, vec!["read"]);
+    assert!(state.goals["goal-1"].active);
+
+    // Unchecked writes keep the shape older peers expect.
+    let cmd: ClusterCommand = serde_json::from_str(put_goal).unwrap();
+    let json = serde_json::to_value(&cmd).unwrap();
+    assert_eq!(json["PutGoal"]["id"], "goal-1");
+}
@@ -26,6 +26,7 @@ fn create_test_cluster_view() -> ClusterView {
         cpu_usage: 0.0,
         memory_usage: 0.0,
         disk_usage: 0.0,
+        revision: 0,
     });
     view.goals.push(Goal {
         id: "goal-1".to_string(),
@@ -34,6 +35,7 @@ fn create_test_cluster_view() -> ClusterView {
         priority: 5,
         active: true,
         created_at: Utc::now(),
+        revision: 0,
     });
     view.attachments.push(Attachment {
         id: "attach-1".to_string(),
@@ -44,6 +46,7 @@ fn create_test_cluster_view() -> ClusterView {
         capabilities: vec![],
         metadata: HashMap::new(),
         created_at: Utc::now(),
+        revision: 0,
     });
     view
 }
@@ -227,6 +230,8 @@ fn test_validate_task_limit() {
             created_at: Utc::now(),
             updated_at: Utc::now(),
             result: None,
+            revision: 0,
+            lock: None,
         });
     }
 
@@ -258,6 +263,8 @@ fn test_validate_cancel_task() {
         created_at: Utc::now(),
         updated_at: Utc::now(),
         result: None,
+        revision: 0,
+        lock: None,
     });
 
     let action = BrainAction::CancelTask {